@@ -70,7 +70,7 @@ pub fn filter_params(input: TokenStream) -> TokenStream {
     });
     (quote!{
 
-        #[derive(serde::Serialize, serde::Deserialize, Debug)]
+        #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
         pub enum #new_ident_name{
             #(#enum_variants),*
         }
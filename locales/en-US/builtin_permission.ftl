@@ -1,19 +1,26 @@
-enter_console = "Enter Console"
-    .desc = "Give access to the console"
-
-comment = "Comment"
-    .desc = "Comment"
-
-artcile_manage_full_to_all = "Article Full Access To All"
-    .desc = "Give full access to all articles including others'"
-article_manage_read_to_all = "Article Read Access To All"
-    .desc = "Give read access to all articles including others'"
-article_manage_write = "Write Article"
-    .desc = "Write, modify, delete an article"
-
-user_manage_full = "User Management Full Access"
-    .desc = "Give full access to user management"
-user_manage_read = "User Management Read Access"
-    .desc = "Give read access to user management"
-
-
+enter_console = Enter Console
+    .desc = Give access to the console
+create_article = Write Article
+    .desc = Write articles and edit your own or those you are co-author of
+manage_article = Article Full Access To All
+    .desc = Give full access to all articles including others'
+comment = Comment
+    .desc = Post comments
+upload_file = Upload File
+    .desc = Upload files and use them in articles
+manage_file = File Management
+    .desc = See and manage files uploaded by everyone
+manage_user = User Management
+    .desc = Give full access to user management
+manage_role = Role Management
+    .desc = Create, modify and assign roles and their permissions
+manage_mail = Mail Queue Management
+    .desc = Inspect, retry and purge outgoing mail and preview templates
+manage_newsletter = Newsletter Management
+    .desc = List, export and remove newsletter subscribers
+manage_webhook = Webhook Management
+    .desc = Register webhooks and inspect their deliveries
+manage_system = System Management
+    .desc = Reload caches and run other maintenance tasks
+view_audit_log = View Audit Log
+    .desc = Search and export the log of administrative and security relevant actions
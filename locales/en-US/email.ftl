@@ -4,3 +4,14 @@ link_leading = Click here to verify(If button not works, try copying the link be
 warning_authorized = If you did not request this, please change your password as soon as possible
 warning_unauthorized = If you did not request this, please ignore this email
 warning_code_not_leak = Below is your verification code, please do not leak it to others
+email_change_notice = The email address of your account is about to change to {$email}
+email_change_cancel_leading = If you did not request this, cancel the change here, or undo it if it was already confirmed, and change your password as soon as possible:
+new_post_intro = A new post was published on {$site}:
+read_more = Read it
+unsubscribe_leading = You receive this because you subscribed to {$site}. Unsubscribe here:
+notify_comment_reply = Someone replied to your comment
+notify_article_comment = Your article received a new comment
+notify_role_changed = Your roles were changed
+notify_data_export_ready = Your data export is ready to download
+notification_leading = Sign in to see all your notifications:
+notification_open = Open
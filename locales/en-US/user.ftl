@@ -1,4 +1,11 @@
 change_password = change password
 change_email = change email
 identity_check = identity check
-email_check = email check
\ No newline at end of file
+email_check = email check
+email_change_requested = Email change requested
+newsletter_subscribe = subscribe to new posts
+new_post = New post
+delete_account = delete your account
+unsubscribe_confirm = Stop receiving new posts by mail?
+unsubscribe_button = Unsubscribe
+unsubscribe_done = You are unsubscribed and will not receive new posts by mail anymore.
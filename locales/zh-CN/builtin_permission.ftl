@@ -0,0 +1,26 @@
+enter_console = 进入控制台
+    .desc = 允许访问控制台
+create_article = 撰写文章
+    .desc = 撰写文章，编辑自己的或作为共同作者的文章
+manage_article = 管理全部文章
+    .desc = 拥有所有文章（包括他人的）的全部权限
+comment = 评论
+    .desc = 发表评论
+upload_file = 上传文件
+    .desc = 上传文件并在文章中使用
+manage_file = 文件管理
+    .desc = 查看并管理所有人上传的文件
+manage_user = 用户管理
+    .desc = 拥有用户管理的全部权限
+manage_role = 角色管理
+    .desc = 创建、修改、分配角色及其权限
+manage_mail = 邮件队列管理
+    .desc = 查看、重试、清理待发邮件并预览模板
+manage_newsletter = 订阅管理
+    .desc = 查看、导出、移除邮件订阅者
+manage_webhook = Webhook 管理
+    .desc = 注册 Webhook 并查看投递记录
+manage_system = 系统管理
+    .desc = 重新加载缓存及执行其他维护操作
+view_audit_log = 查看审计日志
+    .desc = 查询并导出管理及安全相关操作的日志
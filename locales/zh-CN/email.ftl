@@ -3,4 +3,15 @@ probably_action = 貌似您正在尝试{$action}
 link_leading = 点击按钮去验证(如果按钮点不动，请复制下面的链接并访问)
 warning_authorized = 如果不是您本人操作，请尽快修改您的密码
 warning_unauthorized = 如果不是您本人操作，请忽略此邮件
-warning_code_not_leak = 以下是您的验证码，千万不要泄露哦
\ No newline at end of file
+warning_code_not_leak = 以下是您的验证码，千万不要泄露哦
+email_change_notice = 您账户的邮箱即将更换为 {$email}
+email_change_cancel_leading = 如果不是您本人操作，请点此取消更换（若已确认则恢复原邮箱），并尽快修改您的密码：
+new_post_intro = {$site} 发布了新文章：
+read_more = 去看看
+unsubscribe_leading = 您收到这封邮件是因为订阅了 {$site}，点此退订：
+notify_comment_reply = 有人回复了您的评论
+notify_article_comment = 您的文章收到了新评论
+notify_role_changed = 您的角色已变更
+notify_data_export_ready = 您的数据导出已可下载
+notification_leading = 登录查看全部通知：
+notification_open = 查看
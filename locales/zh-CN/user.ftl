@@ -1,4 +1,11 @@
 change_password = 修改密码
 change_email = 更换邮箱
 identity_check = 身份验证
-email_check = 邮箱验证
\ No newline at end of file
+email_check = 邮箱验证
+email_change_requested = 邮箱更换申请
+newsletter_subscribe = 订阅新文章
+new_post = 新文章
+delete_account = 注销账户
+unsubscribe_confirm = 不再通过邮件接收新文章？
+unsubscribe_button = 退订
+unsubscribe_done = 已退订，您将不再收到新文章邮件。
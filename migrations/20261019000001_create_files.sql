@@ -0,0 +1,22 @@
+CREATE TABLE IF NOT EXISTS files (
+    id INT NOT NULL AUTO_INCREMENT,
+    policy INT NOT NULL,
+    owner INT NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    path VARCHAR(255) NOT NULL,
+    size BIGINT NOT NULL,
+    mime VARCHAR(127) NOT NULL,
+    hash CHAR(64) NOT NULL,
+    ref_count INT NOT NULL DEFAULT 0,
+    created_at DATETIME NOT NULL,
+    PRIMARY KEY (id),
+    KEY idx_files_owner (owner)
+);
+
+CREATE TABLE IF NOT EXISTS file_refs (
+    file INT NOT NULL,
+    article INT NOT NULL,
+    kind TINYINT NOT NULL,
+    PRIMARY KEY (file, article, kind),
+    KEY idx_file_refs_article (article)
+);
@@ -0,0 +1,8 @@
+-- NULL means the role sets no limit / the user inherits from their roles
+ALTER TABLE roles
+    ADD COLUMN quota_bytes BIGINT NULL,
+    ADD COLUMN quota_files INT NULL;
+
+ALTER TABLE users
+    ADD COLUMN quota_bytes BIGINT NULL,
+    ADD COLUMN quota_files INT NULL;
@@ -0,0 +1,29 @@
+CREATE TABLE IF NOT EXISTS webhooks (
+    id INT NOT NULL AUTO_INCREMENT,
+    url VARCHAR(2048) NOT NULL,
+    secret VARCHAR(64) NOT NULL,
+    -- comma separated event names, e.g. article_published,user_registered
+    events VARCHAR(255) NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    created_by INT NOT NULL,
+    created_at DATETIME NOT NULL,
+    PRIMARY KEY (id)
+);
+
+-- state: 0 pending, 1 delivered, 2 failed
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    id INT NOT NULL AUTO_INCREMENT,
+    webhook INT NOT NULL,
+    event VARCHAR(63) NOT NULL,
+    payload LONGTEXT NOT NULL,
+    state TINYINT NOT NULL DEFAULT 0,
+    attempts INT NOT NULL DEFAULT 0,
+    next_attempt_at DATETIME NOT NULL,
+    response_code SMALLINT NULL,
+    error VARCHAR(1024) NULL,
+    created_at DATETIME NOT NULL,
+    delivered_at DATETIME NULL,
+    PRIMARY KEY (id),
+    KEY idx_webhook_deliveries_due (state, next_attempt_at),
+    KEY idx_webhook_deliveries_webhook (webhook)
+);
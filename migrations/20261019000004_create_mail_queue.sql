@@ -0,0 +1,16 @@
+-- state: 0 pending, 1 sent, 2 dead
+CREATE TABLE IF NOT EXISTS mail_queue (
+    id INT NOT NULL AUTO_INCREMENT,
+    -- NAME <MAIL>
+    recipient VARCHAR(512) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    body LONGTEXT NOT NULL,
+    state TINYINT NOT NULL DEFAULT 0,
+    attempts INT NOT NULL DEFAULT 0,
+    next_attempt_at DATETIME NOT NULL,
+    error VARCHAR(1024) NULL,
+    created_at DATETIME NOT NULL,
+    sent_at DATETIME NULL,
+    PRIMARY KEY (id),
+    KEY idx_mail_queue_due (state, next_attempt_at)
+);
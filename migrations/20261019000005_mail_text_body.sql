@@ -0,0 +1,2 @@
+-- the plain-text alternative, NULL sends the html body alone
+ALTER TABLE mail_queue ADD COLUMN text_body LONGTEXT NULL AFTER body;
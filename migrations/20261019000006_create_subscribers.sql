@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS subscribers (
+    id INT NOT NULL AUTO_INCREMENT,
+    email VARCHAR(255) NOT NULL,
+    lang VARCHAR(16) NOT NULL,
+    -- set once the double opt-in link was followed
+    confirmed BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at DATETIME NOT NULL,
+    confirmed_at DATETIME NULL,
+    PRIMARY KEY (id),
+    UNIQUE KEY uk_subscribers_email (email)
+);
+
+-- the one-click unsubscribe link of newsletter mail
+ALTER TABLE mail_queue ADD COLUMN list_unsubscribe VARCHAR(512) NULL AFTER text_body;
@@ -0,0 +1,19 @@
+CREATE TABLE IF NOT EXISTS notifications (
+    id INT NOT NULL AUTO_INCREMENT,
+    user INT NOT NULL,
+    -- comment_reply, article_comment, role_changed
+    kind VARCHAR(32) NOT NULL,
+    payload TEXT NOT NULL,
+    is_read BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at DATETIME NOT NULL,
+    PRIMARY KEY (id),
+    KEY idx_notifications_user (user, is_read)
+);
+
+-- channel: 0 none, 1 in-app, 2 email, 3 both; a missing row means the default of the kind
+CREATE TABLE IF NOT EXISTS notification_preferences (
+    user INT NOT NULL,
+    kind VARCHAR(32) NOT NULL,
+    channel TINYINT NOT NULL,
+    PRIMARY KEY (user, kind)
+);
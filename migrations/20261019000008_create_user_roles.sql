@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS user_roles (
+    user INT NOT NULL,
+    role INT NOT NULL,
+    PRIMARY KEY (user, role),
+    KEY idx_user_roles_role (role)
+);
+
+-- users.roles held the role ids comma separated, e.g. "0,1"; ids of deleted roles are dropped
+INSERT IGNORE INTO user_roles (user, role)
+SELECT users.id, r.role FROM users
+JOIN JSON_TABLE(CONCAT('[', users.roles, ']'), '$[*]' COLUMNS (role INT PATH '$')) AS r
+JOIN roles ON roles.id = r.role
+WHERE users.roles != '';
+
+ALTER TABLE users DROP COLUMN roles;
@@ -0,0 +1,10 @@
+-- level: 0 reviewer, 1 co-author; the author of an article never has a row here
+CREATE TABLE IF NOT EXISTS article_acl (
+    article INT NOT NULL,
+    user INT NOT NULL,
+    level TINYINT NOT NULL,
+    granted_by INT NOT NULL,
+    created_at DATETIME NOT NULL,
+    PRIMARY KEY (article, user),
+    KEY idx_article_acl_user (user)
+);
@@ -0,0 +1,7 @@
+-- a role holds every permission of its parents, transitively
+CREATE TABLE IF NOT EXISTS role_parents (
+    role INT NOT NULL,
+    parent INT NOT NULL,
+    PRIMARY KEY (role, parent),
+    KEY idx_role_parents_parent (parent)
+);
@@ -0,0 +1,10 @@
+-- a log of cache invalidations that every instance polls; kind: permissions, user_roles, fs_policies, all
+CREATE TABLE IF NOT EXISTS cache_changes (
+    id BIGINT NOT NULL AUTO_INCREMENT,
+    kind VARCHAR(32) NOT NULL,
+    target INT NULL,
+    origin VARCHAR(36) NOT NULL,
+    created_at DATETIME NOT NULL,
+    PRIMARY KEY (id),
+    KEY idx_cache_changes_created_at (created_at)
+);
@@ -0,0 +1,18 @@
+-- append only, rows are never updated; actor is NULL when nobody is signed in
+CREATE TABLE IF NOT EXISTS audit_logs (
+    id BIGINT NOT NULL AUTO_INCREMENT,
+    actor INT NULL,
+    action VARCHAR(64) NOT NULL,
+    target_type VARCHAR(32) NULL,
+    target_id INT NULL,
+    request_id VARCHAR(36) NOT NULL,
+    ip VARCHAR(64) NULL,
+    user_agent VARCHAR(255) NULL,
+    diff TEXT NOT NULL,
+    created_at DATETIME NOT NULL,
+    PRIMARY KEY (id),
+    KEY idx_audit_logs_actor (actor),
+    KEY idx_audit_logs_action (action),
+    KEY idx_audit_logs_target (target_type, target_id),
+    KEY idx_audit_logs_created_at (created_at)
+);
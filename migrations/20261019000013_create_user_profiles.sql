@@ -0,0 +1,13 @@
+-- a user without a row has an empty profile
+CREATE TABLE IF NOT EXISTS user_profiles (
+    user INT NOT NULL,
+    display_name VARCHAR(50) NULL,
+    bio TEXT NULL,
+    website VARCHAR(255) NULL,
+    -- [{"label": "...", "url": "..."}]
+    links TEXT NOT NULL,
+    locale VARCHAR(10) NULL,
+    timezone VARCHAR(64) NULL,
+    updated_at DATETIME NOT NULL,
+    PRIMARY KEY (user)
+);
@@ -0,0 +1,8 @@
+-- status: 0 active, 1 disabled, 2 banned; a ban without status_until never ends
+ALTER TABLE users
+    ADD COLUMN status TINYINT NOT NULL DEFAULT 0,
+    ADD COLUMN status_reason VARCHAR(255) NULL,
+    ADD COLUMN status_until DATETIME NULL,
+    ADD COLUMN password_reset_required BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX idx_users_status ON users (status);
@@ -0,0 +1,16 @@
+-- state: 0 building, 1 ready, 2 failed; path is the archive in the directory of the user on the default policy
+CREATE TABLE IF NOT EXISTS data_exports (
+    id INT NOT NULL AUTO_INCREMENT,
+    user INT NOT NULL,
+    state TINYINT NOT NULL,
+    path VARCHAR(255) NULL,
+    size BIGINT NOT NULL DEFAULT 0,
+    created_at DATETIME NOT NULL,
+    expires_at DATETIME NULL,
+    PRIMARY KEY (id),
+    KEY idx_data_exports_user (user)
+);
+
+-- set once a self-deletion is confirmed, the account is anonymized when it has passed
+ALTER TABLE users
+    ADD COLUMN delete_after DATETIME NULL;
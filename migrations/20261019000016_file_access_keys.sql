@@ -0,0 +1,4 @@
+-- files are served by a random key, ids are sequential and would let anyone walk through all uploads
+ALTER TABLE files ADD COLUMN access_key CHAR(32) NOT NULL DEFAULT '';
+UPDATE files SET access_key = REPLACE(UUID(), '-', '') WHERE access_key = '';
+ALTER TABLE files ADD UNIQUE KEY uk_files_access_key (access_key);
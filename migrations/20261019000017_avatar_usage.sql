@@ -0,0 +1,2 @@
+-- avatars count towards the storage quota of their user
+ALTER TABLE users ADD COLUMN avatar_bytes BIGINT NOT NULL DEFAULT 0;
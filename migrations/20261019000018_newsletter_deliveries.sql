@@ -0,0 +1,8 @@
+-- which subscribers a new post was queued for, so notifying again skips them
+CREATE TABLE IF NOT EXISTS newsletter_deliveries (
+    article INT NOT NULL,
+    subscriber INT NOT NULL,
+    created_at DATETIME NOT NULL,
+    PRIMARY KEY (article, subscriber),
+    KEY idx_newsletter_deliveries_subscriber (subscriber)
+);
@@ -0,0 +1,13 @@
+-- roles able to manage roles are the administrators, they get every permission added since
+-- that they do not hold yet
+INSERT INTO permission_to_roles (role, permission)
+SELECT admins.role, p.permission
+FROM (SELECT DISTINCT role FROM permission_to_roles WHERE permission = 'MANAGE_ROLE') AS admins
+CROSS JOIN JSON_TABLE(
+    '["MANAGE_ARTICLE","UPLOAD_FILE","MANAGE_FILE","MANAGE_MAIL","MANAGE_NEWSLETTER","MANAGE_WEBHOOK","MANAGE_SYSTEM","VIEW_AUDIT_LOG"]',
+    '$[*]' COLUMNS (permission VARCHAR(64) PATH '$')
+) AS p
+WHERE NOT EXISTS (
+    SELECT 1 FROM permission_to_roles granted
+    WHERE granted.role = admins.role AND granted.permission = p.permission
+);
@@ -0,0 +1,18 @@
+-- two accounts confirming a change to the same address at once could both pass the check before
+-- the update. Earlier versions did not check at all, so an address may already be shared: the oldest
+-- account keeps it, the others get a placeholder and an audit entry with the address they had,
+-- so an administrator can find them (action user.deduplicate_email) and sort them out
+INSERT INTO audit_logs (actor, action, target_type, target_id, request_id, ip, user_agent, diff, created_at)
+SELECT NULL, 'user.deduplicate_email', 'user', u.id, '', NULL, NULL,
+    JSON_OBJECT('email', JSON_OBJECT('old', u.email, 'new', CONCAT('duplicate-', u.id, '@invalid'))),
+    UTC_TIMESTAMP()
+FROM users u
+WHERE EXISTS (SELECT 1 FROM users kept WHERE kept.email = u.email AND kept.id < u.id);
+
+UPDATE users u
+JOIN (
+    SELECT DISTINCT d.id FROM users d JOIN users kept ON kept.email = d.email AND kept.id < d.id
+) duplicates ON duplicates.id = u.id
+SET u.email = CONCAT('duplicate-', u.id, '@invalid');
+
+ALTER TABLE users ADD UNIQUE KEY uk_users_email (email);
@@ -0,0 +1,12 @@
+-- parent is the comment replied to, on the same article; articles.comments counts the rows of an article
+CREATE TABLE IF NOT EXISTS article_comments (
+    id INT NOT NULL AUTO_INCREMENT,
+    article INT NOT NULL,
+    author INT NOT NULL,
+    parent INT NULL,
+    content TEXT NOT NULL,
+    created_at DATETIME NOT NULL,
+    PRIMARY KEY (id),
+    KEY idx_article_comments_article (article),
+    KEY idx_article_comments_author (author)
+);
@@ -0,0 +1,11 @@
+-- the roles every new user gets (DEFAULT_ROLES in db/rbac.rs) may comment, as may the administrators
+INSERT INTO permission_to_roles (role, permission)
+SELECT r.id, 'COMMENT' FROM roles r
+WHERE (r.id IN (0, 1) OR EXISTS (
+    SELECT 1 FROM permission_to_roles admin
+    WHERE admin.role = r.id AND admin.permission = 'MANAGE_ROLE'
+))
+AND NOT EXISTS (
+    SELECT 1 FROM permission_to_roles granted
+    WHERE granted.role = r.id AND granted.permission = 'COMMENT'
+);
@@ -1,7 +1,8 @@
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
-use sqlx::{FromRow, MySqlPool};
+use sqlx::{Executor, FromRow, MySqlPool};
+use super::file::FileRefKind;
 use tracing::instrument;
 use rustle_derive::{FilterParams, SortParams};
 use super::DBResult;
@@ -53,6 +54,39 @@ pub struct ArticleContent{
     pub id: i32,
     pub content: String,
 }
+// what a user other than the author may do with an article
+#[derive(Serialize,Deserialize,Debug,Clone,Copy,PartialEq,Eq,PartialOrd,Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ArticleGrant{
+    Reviewer,
+    CoAuthor
+}
+impl ArticleGrant{
+    fn to_i8(self) -> i8{
+        match self{
+            ArticleGrant::Reviewer => 0,
+            ArticleGrant::CoAuthor => 1
+        }
+    }
+}
+impl TryFrom<i8> for ArticleGrant{
+    type Error = String;
+    fn try_from(value: i8) -> Result<Self, Self::Error> {
+        match value{
+            0 => Ok(ArticleGrant::Reviewer),
+            1 => Ok(ArticleGrant::CoAuthor),
+            v => Err(format!("unknown article grant {v}"))
+        }
+    }
+}
+#[derive(Serialize,Debug,FromRow)]
+pub struct ArticleAcl{
+    pub user: i32,
+    #[sqlx(try_from = "i8")]
+    pub level: ArticleGrant,
+    pub granted_by: i32,
+    pub created_at: DateTime<Utc>,
+}
 #[instrument(err,skip_all)]
 pub async fn save_content(pool: &MySqlPool, content: &str) -> DBResult<i32>{
     Ok(sqlx::query("INSERT INTO contents (content) VALUES (?)")
@@ -60,9 +94,11 @@ pub async fn save_content(pool: &MySqlPool, content: &str) -> DBResult<i32>{
         .execute(pool)
         .await?.last_insert_id() as i32)
 }
+// the article and the references to its files are written together or not at all
 #[instrument(err,skip_all)]
-pub async fn create(pool: &MySqlPool, article: &Article) -> DBResult<i32>{
-    Ok(sqlx::query("INSERT INTO articles (author,content_id,draft_content_id,summary_content_id,template_id,cover_id,visits,comments,public_state,draft_state,is_pinned,is_commentable,created_at,updated_at,password,title,alias) VALUES (?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?)")
+pub async fn create(pool: &MySqlPool, article: &Article, cover: Option<i32>, attachments: &[i32]) -> DBResult<i32>{
+    let mut tx = pool.begin().await?;
+    let id = tx.execute(sqlx::query("INSERT INTO articles (author,content_id,draft_content_id,summary_content_id,template_id,cover_id,visits,comments,public_state,draft_state,is_pinned,is_commentable,created_at,updated_at,password,title,alias) VALUES (?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?)")
         .bind(article.author)
         .bind(article.content_id)
         .bind(article.draft_content_id)
@@ -79,22 +115,34 @@ pub async fn create(pool: &MySqlPool, article: &Article) -> DBResult<i32>{
         .bind(article.updated_at)
         .bind(&article.password)
         .bind(&article.title)
-        .bind(&article.alias)
-        .execute(pool)
-        .await?.last_insert_id() as i32)
+        .bind(&article.alias)).await?.last_insert_id() as i32;
+    if let Some(cover) = cover{
+        super::file::attach_in_tx(&mut tx, id, cover, FileRefKind::Cover).await?;
+    }
+    for file in attachments{
+        super::file::attach_in_tx(&mut tx, id, *file, FileRefKind::Attachment).await?;
+    }
+    tx.commit().await?;
+    Ok(id)
 }
 #[instrument(err,skip_all)]
 pub async fn list<T: Send + Unpin + for<'a> sqlx::FromRow<'a, sqlx::mysql::MySqlRow>>(
     pool: &MySqlPool,
     limit: i32,
     offset: i32,
+    // only articles the user wrote or holds a grant on
+    accessible_by: Option<i32>,
     filter: Vec<ArticleFilterable>,
     sort: Vec<ArticleSortable>,
 ) -> DBResult<(i32,Vec<T>)>{
-    let mut basic_query = "SELECT id FROM articles".to_string();
-    let where_query = filter.iter().map(|f| 
+    let mut conditions = filter.iter().map(|f|
         format!("{} = ?", f.get_field_name())
-    ).collect::<Vec<String>>().join(" AND ");
+    ).collect::<Vec<String>>();
+    if accessible_by.is_some(){
+        conditions.push(ACCESSIBLE_CONDITION.to_string());
+    }
+    let mut basic_query = "SELECT id FROM articles".to_string();
+    let where_query = conditions.join(" AND ");
     if where_query.len() != 0{
         basic_query.push_str(" WHERE ");
         basic_query.push_str(&where_query);
@@ -109,13 +157,102 @@ pub async fn list<T: Send + Unpin + for<'a> sqlx::FromRow<'a, sqlx::mysql::MySql
     for f in filter{
         instance = f.bind_value(instance);
     }
-    Ok((
-            sqlx::query_as::<_,(i64,)>("SELECT count(id) FROM articles")
-                .fetch_one(pool)
-                .await?
-                .0 as i32,
-            instance
-            .fetch_all(pool)
-            .await?
-    ))
-}
\ No newline at end of file
+    if let Some(u) = accessible_by{
+        instance = instance.bind(u).bind(u);
+    }
+    let total = match accessible_by{
+        Some(u) => sqlx::query_as::<_,(i64,)>(&format!("SELECT count(id) FROM articles WHERE {ACCESSIBLE_CONDITION}"))
+            .bind(u)
+            .bind(u)
+            .fetch_one(pool)
+            .await?.0,
+        None => sqlx::query_as::<_,(i64,)>("SELECT count(id) FROM articles")
+            .fetch_one(pool)
+            .await?.0
+    };
+    Ok((total as i32, instance.fetch_all(pool).await?))
+}
+// the content rows are written beforehand, the old ones are kept like every other revision
+#[instrument(err,skip_all)]
+pub async fn update(pool: &MySqlPool, article: &Article) -> DBResult<bool>{
+    Ok(sqlx::query("UPDATE articles SET content_id = ?, draft_content_id = ?, public_state = ?, is_pinned = ?, is_commentable = ?, updated_at = ?, title = ?, alias = ? WHERE id = ?")
+        .bind(article.content_id)
+        .bind(article.draft_content_id)
+        .bind(article.public_state)
+        .bind(article.is_pinned)
+        .bind(article.is_commentable)
+        .bind(article.updated_at)
+        .bind(&article.title)
+        .bind(&article.alias)
+        .bind(article.id)
+        .execute(pool)
+        .await?.rows_affected() == 1)
+}
+#[instrument(err,skip_all)]
+pub async fn select_by_id(pool: &MySqlPool, id: i32) -> DBResult<Option<Article>>{
+    sqlx::query_as::<_,Article>("SELECT * FROM articles WHERE id = ? LIMIT 1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+}
+const ACCESSIBLE_CONDITION: &str = "(author = ? OR id IN (SELECT article FROM article_acl WHERE user = ?))";
+#[instrument(err,skip_all)]
+pub async fn select_author(pool: &MySqlPool, id: i32) -> DBResult<Option<i32>>{
+    Ok(sqlx::query_as::<_,(i32,)>("SELECT author FROM articles WHERE id = ? LIMIT 1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?
+        .map(|t| t.0))
+}
+#[instrument(err,skip_all)]
+pub async fn select_by_author(pool: &MySqlPool, author: i32) -> DBResult<Vec<Article>>{
+    sqlx::query_as::<_,Article>("SELECT * FROM articles WHERE author = ? ORDER BY id")
+        .bind(author)
+        .fetch_all(pool)
+        .await
+}
+#[instrument(err,skip_all)]
+pub async fn select_content(pool: &MySqlPool, id: i32) -> DBResult<Option<String>>{
+    Ok(sqlx::query_as::<_,ArticleContent>("SELECT id,content FROM contents WHERE id = ? LIMIT 1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?
+        .map(|t| t.content))
+}
+#[instrument(err,skip_all)]
+pub async fn select_grant(pool: &MySqlPool, article: i32, user: i32) -> DBResult<Option<ArticleGrant>>{
+    Ok(sqlx::query_as::<_,(i8,)>("SELECT level FROM article_acl WHERE article = ? AND user = ? LIMIT 1")
+        .bind(article)
+        .bind(user)
+        .fetch_optional(pool)
+        .await?
+        .and_then(|t| ArticleGrant::try_from(t.0).ok()))
+}
+#[instrument(err,skip_all)]
+pub async fn list_grants(pool: &MySqlPool, article: i32) -> DBResult<Vec<ArticleAcl>>{
+    sqlx::query_as::<_,ArticleAcl>("SELECT user,level,granted_by,created_at FROM article_acl WHERE article = ? ORDER BY created_at")
+        .bind(article)
+        .fetch_all(pool)
+        .await
+}
+// granting again changes the level
+#[instrument(err,skip_all)]
+pub async fn set_grant(pool: &MySqlPool, article: i32, user: i32, level: ArticleGrant, granted_by: i32) -> DBResult<()>{
+    sqlx::query("INSERT INTO article_acl (article,user,level,granted_by,created_at) VALUES (?,?,?,?,?) ON DUPLICATE KEY UPDATE level = VALUES(level), granted_by = VALUES(granted_by)")
+        .bind(article)
+        .bind(user)
+        .bind(level.to_i8())
+        .bind(granted_by)
+        .bind(Utc::now())
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+#[instrument(err,skip_all)]
+pub async fn delete_grant(pool: &MySqlPool, article: i32, user: i32) -> DBResult<bool>{
+    Ok(sqlx::query("DELETE FROM article_acl WHERE article = ? AND user = ?")
+        .bind(article)
+        .bind(user)
+        .execute(pool)
+        .await?.rows_affected() == 1)
+}
@@ -0,0 +1,111 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use sqlx::{FromRow, MySql, MySqlPool, QueryBuilder};
+use tracing::instrument;
+use super::DBResult;
+
+#[derive(Serialize,Debug,FromRow)]
+pub struct AuditEntry{
+    pub id: i64,
+    pub actor: Option<i32>,
+    pub action: String,
+    pub target_type: Option<String>,
+    pub target_id: Option<i32>,
+    pub request_id: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub diff: Json<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+}
+pub struct NewAuditEntry<'a>{
+    pub actor: Option<i32>,
+    pub action: &'a str,
+    pub target_type: Option<&'a str>,
+    pub target_id: Option<i32>,
+    pub request_id: &'a str,
+    pub ip: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+    pub diff: &'a serde_json::Value,
+}
+#[derive(Deserialize,Debug,Default)]
+#[serde(default)]
+pub struct AuditFilter{
+    pub actor: Option<i32>,
+    pub action: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<i32>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+#[instrument(err,skip_all)]
+pub async fn create(pool: &MySqlPool, entry: &NewAuditEntry<'_>) -> DBResult<()>{
+    sqlx::query("INSERT INTO audit_logs (actor,action,target_type,target_id,request_id,ip,user_agent,diff,created_at) VALUES (?,?,?,?,?,?,?,?,?)")
+        .bind(entry.actor)
+        .bind(entry.action)
+        .bind(entry.target_type)
+        .bind(entry.target_id)
+        .bind(entry.request_id)
+        .bind(entry.ip)
+        .bind(entry.user_agent)
+        .bind(Json(entry.diff))
+        .bind(Utc::now())
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+fn push_filter<'a>(query: &mut QueryBuilder<'a, MySql>, filter: &'a AuditFilter){
+    query.push(" WHERE TRUE");
+    if let Some(actor) = filter.actor{
+        query.push(" AND actor = ").push_bind(actor);
+    }
+    if let Some(action) = &filter.action{
+        query.push(" AND action = ").push_bind(action);
+    }
+    if let Some(target_type) = &filter.target_type{
+        query.push(" AND target_type = ").push_bind(target_type);
+    }
+    if let Some(target_id) = filter.target_id{
+        query.push(" AND target_id = ").push_bind(target_id);
+    }
+    if let Some(from) = filter.from{
+        query.push(" AND created_at >= ").push_bind(from);
+    }
+    if let Some(to) = filter.to{
+        query.push(" AND created_at < ").push_bind(to);
+    }
+}
+#[instrument(err,skip_all)]
+pub async fn list(pool: &MySqlPool, filter: &AuditFilter, limit: i32, offset: i32) -> DBResult<(i32,Vec<AuditEntry>)>{
+    let mut count = QueryBuilder::new("SELECT count(id) FROM audit_logs");
+    push_filter(&mut count, filter);
+    let total = count.build_query_as::<(i64,)>().fetch_one(pool).await?.0;
+    let mut query = QueryBuilder::new("SELECT * FROM audit_logs");
+    push_filter(&mut query, filter);
+    query.push(" ORDER BY id DESC LIMIT ").push_bind(offset).push(",").push_bind(limit);
+    let entries = query.build_query_as::<AuditEntry>().fetch_all(pool).await?;
+    Ok((total as i32, entries))
+}
+// oldest first from `after`, for walking the whole log in batches
+#[instrument(err,skip_all)]
+pub async fn select_after(pool: &MySqlPool, filter: &AuditFilter, after: i64, limit: i32) -> DBResult<Vec<AuditEntry>>{
+    let mut query = QueryBuilder::new("SELECT * FROM audit_logs");
+    push_filter(&mut query, filter);
+    query.push(" AND id > ").push_bind(after).push(" ORDER BY id LIMIT ").push_bind(limit);
+    query.build_query_as::<AuditEntry>().fetch_all(pool).await
+}
+// what the user did and what was done to them, oldest first from `after`;
+// where a change by someone else came from is theirs, so ip and user agent are only kept on the user's own entries
+#[instrument(err,skip_all)]
+pub async fn select_concerning(pool: &MySqlPool, user: i32, after: i64, limit: i32) -> DBResult<Vec<AuditEntry>>{
+    sqlx::query_as::<_,AuditEntry>("SELECT id,actor,action,target_type,target_id,request_id,IF(actor <=> ?, ip, NULL) AS ip,IF(actor <=> ?, user_agent, NULL) AS user_agent,diff,created_at FROM audit_logs WHERE (actor = ? OR (target_type = 'user' AND target_id = ?)) AND id > ? ORDER BY id LIMIT ?")
+        .bind(user)
+        .bind(user)
+        .bind(user)
+        .bind(user)
+        .bind(after)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+}
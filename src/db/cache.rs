@@ -0,0 +1,46 @@
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, MySqlPool};
+use tracing::instrument;
+use super::DBResult;
+
+#[derive(Debug,FromRow)]
+pub struct CacheChangeRow{
+    pub id: i64,
+    pub kind: String,
+    pub target: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+// stamped with the clock of the database so that every instance reads the log against the same clock
+#[instrument(err,skip_all)]
+pub async fn record(pool: &MySqlPool, kind: &str, target: Option<i32>, origin: &str) -> DBResult<()>{
+    sqlx::query("INSERT INTO cache_changes (kind,target,origin,created_at) VALUES (?,?,?,UTC_TIMESTAMP())")
+        .bind(kind)
+        .bind(target)
+        .bind(origin)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+#[instrument(err,skip_all)]
+pub async fn select_now(pool: &MySqlPool) -> DBResult<DateTime<Utc>>{
+    Ok(sqlx::query_as::<_,(DateTime<Utc>,)>("SELECT UTC_TIMESTAMP()")
+        .fetch_one(pool)
+        .await?.0)
+}
+// changes recorded by other instances since `since`, ids do not tell the order of commits so the caller picks the window
+#[instrument(err,skip_all)]
+pub async fn select_since(pool: &MySqlPool, since: DateTime<Utc>, origin: &str) -> DBResult<Vec<CacheChangeRow>>{
+    sqlx::query_as::<_,CacheChangeRow>("SELECT id,kind,target,created_at FROM cache_changes WHERE created_at >= ? AND origin != ? ORDER BY id")
+        .bind(since)
+        .bind(origin)
+        .fetch_all(pool)
+        .await
+}
+#[instrument(err,skip_all)]
+pub async fn purge_older_than(pool: &MySqlPool, secs: i64) -> DBResult<u64>{
+    Ok(sqlx::query("DELETE FROM cache_changes WHERE created_at < UTC_TIMESTAMP() - INTERVAL ? SECOND")
+        .bind(secs)
+        .execute(pool)
+        .await?.rows_affected())
+}
@@ -0,0 +1,45 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{Executor, FromRow, MySqlPool};
+use tracing::instrument;
+use super::DBResult;
+
+#[derive(Serialize,Debug,FromRow)]
+pub struct Comment{
+    pub id: i32,
+    pub article: i32,
+    pub author: i32,
+    pub parent: Option<i32>,
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// the content is expected to be sanitized already
+#[instrument(err,skip_all)]
+pub async fn create(pool: &MySqlPool, article: i32, author: i32, parent: Option<i32>, content: &str) -> DBResult<i32>{
+    let mut tx = pool.begin().await?;
+    let id = tx.execute(sqlx::query("INSERT INTO article_comments (article,author,parent,content,created_at) VALUES (?,?,?,?,?)")
+        .bind(article)
+        .bind(author)
+        .bind(parent)
+        .bind(content)
+        .bind(Utc::now())).await?.last_insert_id() as i32;
+    tx.execute(sqlx::query("UPDATE articles SET comments = comments + 1 WHERE id = ?")
+        .bind(article)).await?;
+    tx.commit().await?;
+    Ok(id)
+}
+#[instrument(err,skip_all)]
+pub async fn select_by_id(pool: &MySqlPool, id: i32) -> DBResult<Option<Comment>>{
+    sqlx::query_as::<_,Comment>("SELECT * FROM article_comments WHERE id = ? LIMIT 1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+}
+#[instrument(err,skip_all)]
+pub async fn select_by_author(pool: &MySqlPool, author: i32) -> DBResult<Vec<Comment>>{
+    sqlx::query_as::<_,Comment>("SELECT * FROM article_comments WHERE author = ? ORDER BY id")
+        .bind(author)
+        .fetch_all(pool)
+        .await
+}
@@ -0,0 +1,99 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{Executor, FromRow, MySqlPool};
+use tracing::instrument;
+use super::DBResult;
+
+pub const EXPORT_BUILDING: i8 = 0;
+pub const EXPORT_READY: i8 = 1;
+pub const EXPORT_FAILED: i8 = 2;
+#[derive(Serialize,Debug,FromRow)]
+pub struct DataExport{
+    pub id: i32,
+    #[serde(skip_serializing)]
+    pub user: i32,
+    pub state: i8,
+    #[serde(skip_serializing)]
+    pub path: Option<String>,
+    pub size: i64,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+// None while another export of the user is still building; the lock on the user row orders concurrent requests
+#[instrument(err,skip_all)]
+pub async fn create_unless_building(pool: &MySqlPool, user: i32) -> DBResult<Option<i32>>{
+    let mut tx = pool.begin().await?;
+    tx.execute(sqlx::query("SELECT id FROM users WHERE id = ? FOR UPDATE").bind(user)).await?;
+    let inserted = tx.execute(sqlx::query("INSERT INTO data_exports (user,state,created_at) SELECT ?,?,? FROM DUAL WHERE NOT EXISTS (SELECT id FROM data_exports WHERE user = ? AND state = ?)")
+        .bind(user)
+        .bind(EXPORT_BUILDING)
+        .bind(Utc::now())
+        .bind(user)
+        .bind(EXPORT_BUILDING)).await?;
+    tx.commit().await?;
+    Ok((inserted.rows_affected() == 1).then(|| inserted.last_insert_id() as i32))
+}
+#[instrument(err,skip_all)]
+pub async fn finish(pool: &MySqlPool, id: i32, path: &str, size: i64, expires_at: DateTime<Utc>) -> DBResult<()>{
+    sqlx::query("UPDATE data_exports SET state = ?, path = ?, size = ?, expires_at = ? WHERE id = ?")
+        .bind(EXPORT_READY)
+        .bind(path)
+        .bind(size)
+        .bind(expires_at)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+#[instrument(err,skip_all)]
+pub async fn fail(pool: &MySqlPool, id: i32) -> DBResult<()>{
+    sqlx::query("UPDATE data_exports SET state = ? WHERE id = ?")
+        .bind(EXPORT_FAILED)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+// builds cut short by a restart never finish
+#[instrument(err,skip_all)]
+pub async fn fail_building(pool: &MySqlPool) -> DBResult<u64>{
+    Ok(sqlx::query("UPDATE data_exports SET state = ? WHERE state = ?")
+        .bind(EXPORT_FAILED)
+        .bind(EXPORT_BUILDING)
+        .execute(pool)
+        .await?.rows_affected())
+}
+#[instrument(err,skip_all)]
+pub async fn select(pool: &MySqlPool, id: i32) -> DBResult<Option<DataExport>>{
+    sqlx::query_as::<_,DataExport>("SELECT * FROM data_exports WHERE id = ? LIMIT 1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+}
+#[instrument(err,skip_all)]
+pub async fn list_by_user(pool: &MySqlPool, user: i32) -> DBResult<Vec<DataExport>>{
+    sqlx::query_as::<_,DataExport>("SELECT * FROM data_exports WHERE user = ? ORDER BY id DESC")
+        .bind(user)
+        .fetch_all(pool)
+        .await
+}
+// ready ones past their expiry and failed ones older than `before`
+#[instrument(err,skip_all)]
+pub async fn select_expired(pool: &MySqlPool, before: DateTime<Utc>) -> DBResult<Vec<DataExport>>{
+    sqlx::query_as::<_,DataExport>("SELECT * FROM data_exports WHERE (state = ? AND expires_at < ?) OR (state = ? AND created_at < ?)")
+        .bind(EXPORT_READY)
+        .bind(Utc::now())
+        .bind(EXPORT_FAILED)
+        .bind(before)
+        .fetch_all(pool)
+        .await
+}
+#[instrument(err,skip_all)]
+pub async fn delete(pool: &MySqlPool, id: i32) -> DBResult<()>{
+    sqlx::query("DELETE FROM data_exports WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
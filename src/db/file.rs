@@ -0,0 +1,219 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, FromRow, MySqlPool, Row};
+use tracing::instrument;
+use rustle_derive::{FilterParams, SortParams};
+use super::DBResult;
+use super::quota::Quota;
+
+#[derive(Serialize,Debug,FromRow,FilterParams,SortParams)]
+pub struct File{
+    pub id: i32,
+    #[filterable]
+    pub policy: i32,
+    #[filterable]
+    pub owner: i32,
+    #[sortable]
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub path: String,
+    #[sortable]
+    pub size: i64,
+    #[filterable]
+    pub mime: String,
+    pub hash: String,
+    // what the file is served by, ids are sequential and guessable
+    pub access_key: String,
+    pub ref_count: i32,
+    #[sortable]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize,Deserialize,Debug,Clone,Copy,PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FileRefKind{
+    Cover,
+    Attachment
+}
+impl FileRefKind{
+    fn to_i8(self) -> i8{
+        match self{
+            FileRefKind::Cover => 0,
+            FileRefKind::Attachment => 1
+        }
+    }
+}
+
+// None if the file does not fit into the quota of its owner, checked under a lock on the owner
+#[instrument(err,skip_all)]
+pub async fn create(pool: &MySqlPool, file: &File, quota: &Quota) -> DBResult<Option<i32>>{
+    let mut tx = pool.begin().await?;
+    if !super::quota::lock_and_check(&mut tx, file.owner, quota, file.size, 1).await?{
+        return Ok(None);
+    }
+    let id = tx.execute(sqlx::query("INSERT INTO files (policy,owner,name,path,size,mime,hash,access_key,ref_count,created_at) VALUES (?,?,?,?,?,?,?,?,0,?)")
+        .bind(file.policy)
+        .bind(file.owner)
+        .bind(&file.name)
+        .bind(&file.path)
+        .bind(file.size)
+        .bind(&file.mime)
+        .bind(&file.hash)
+        .bind(&file.access_key)
+        .bind(file.created_at)).await?.last_insert_id() as i32;
+    tx.commit().await?;
+    Ok(Some(id))
+}
+#[instrument(err,skip_all)]
+pub async fn select_by_id(pool: &MySqlPool, id: i32) -> DBResult<Option<File>>{
+    sqlx::query_as::<_,File>("SELECT * FROM files WHERE id = ? LIMIT 1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+}
+#[instrument(err,skip_all)]
+pub async fn select_by_access_key(pool: &MySqlPool, access_key: &str) -> DBResult<Option<File>>{
+    sqlx::query_as::<_,File>("SELECT * FROM files WHERE access_key = ? LIMIT 1")
+        .bind(access_key)
+        .fetch_optional(pool)
+        .await
+}
+#[instrument(err,skip_all)]
+pub async fn list(
+    pool: &MySqlPool,
+    owner: Option<i32>,
+    limit: i32,
+    offset: i32,
+    filter: Vec<FileFilterable>,
+    sort: Vec<FileSortable>,
+) -> DBResult<(i32,Vec<File>)>{
+    let mut conditions = filter.iter().map(|f|
+        format!("{} = ?", f.get_field_name())
+    ).collect::<Vec<String>>();
+    if owner.is_some(){
+        conditions.push("owner = ?".to_string());
+    }
+    let mut basic_query = "SELECT id FROM files".to_string();
+    let mut count_query = "SELECT count(id) FROM files".to_string();
+    let where_query = conditions.join(" AND ");
+    if !where_query.is_empty(){
+        basic_query.push_str(" WHERE ");
+        basic_query.push_str(&where_query);
+        count_query.push_str(" WHERE ");
+        count_query.push_str(&where_query);
+    }
+    let order_query = sort.iter().map(|f| f.to_sql()).collect::<Vec<String>>().join(",");
+    if !order_query.is_empty(){
+        basic_query.push_str(" ORDER BY ");
+        basic_query.push_str(&order_query);
+    }
+    let final_query = format!("SELECT * FROM files JOIN ({} LIMIT {},{})t USING(id)", basic_query, offset, limit);
+    let mut instance = sqlx::query_as::<_,File>(&final_query);
+    let mut count_instance = sqlx::query_as::<_,(i64,)>(&count_query);
+    for f in filter{
+        count_instance = f.clone().bind_value(count_instance);
+        instance = f.bind_value(instance);
+    }
+    if let Some(o) = owner{
+        instance = instance.bind(o);
+        count_instance = count_instance.bind(o);
+    }
+    let total = count_instance.fetch_one(pool).await?.0;
+    Ok((total as i32, instance.fetch_all(pool).await?))
+}
+#[instrument(err,skip_all)]
+pub async fn rename(pool: &MySqlPool, id: i32, name: &str) -> DBResult<()>{
+    sqlx::query("UPDATE files SET name = ? WHERE id = ?")
+        .bind(name)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+#[instrument(err,skip_all)]
+pub async fn select_by_owner(pool: &MySqlPool, owner: i32) -> DBResult<Vec<File>>{
+    sqlx::query_as::<_,File>("SELECT * FROM files WHERE owner = ? ORDER BY id")
+        .bind(owner)
+        .fetch_all(pool)
+        .await
+}
+#[instrument(err,skip_all)]
+pub async fn select_unreferenced_by_owner(pool: &MySqlPool, owner: i32) -> DBResult<Vec<File>>{
+    sqlx::query_as::<_,File>("SELECT * FROM files WHERE owner = ? AND ref_count = 0")
+        .bind(owner)
+        .fetch_all(pool)
+        .await
+}
+// returns false if the file is still referenced, in which case nothing is deleted
+#[instrument(err,skip_all)]
+pub async fn delete_unreferenced(pool: &MySqlPool, id: i32) -> DBResult<bool>{
+    Ok(sqlx::query("DELETE FROM files WHERE id = ? AND ref_count = 0")
+        .bind(id)
+        .execute(pool)
+        .await?.rows_affected() == 1)
+}
+
+#[instrument(err,skip_all)]
+pub async fn attach(pool: &MySqlPool, article: i32, file: i32, kind: FileRefKind) -> DBResult<()>{
+    let mut tx = pool.begin().await?;
+    attach_in_tx(&mut tx, article, file, kind).await?;
+    tx.commit().await?;
+    Ok(())
+}
+pub(super) async fn attach_in_tx(tx: &mut sqlx::Transaction<'_, sqlx::MySql>, article: i32, file: i32, kind: FileRefKind) -> DBResult<()>{
+    if kind == FileRefKind::Cover{
+        // an article has at most one cover, release the previous one first
+        let previous = tx.fetch_optional(sqlx::query("SELECT file FROM file_refs WHERE article = ? AND kind = ? LIMIT 1")
+            .bind(article)
+            .bind(kind.to_i8())).await?;
+        if let Some(row) = previous{
+            let previous_file: i32 = row.try_get("file")?;
+            if previous_file == file{
+                return Ok(());
+            }
+            detach_in_tx(&mut tx, article, previous_file, kind).await?;
+        }
+        tx.execute(sqlx::query("UPDATE articles SET cover_id = ? WHERE id = ?")
+            .bind(file)
+            .bind(article)).await?;
+    }
+    let inserted = tx.execute(sqlx::query("INSERT IGNORE INTO file_refs (file,article,kind) VALUES (?,?,?)")
+        .bind(file)
+        .bind(article)
+        .bind(kind.to_i8())).await?.rows_affected();
+    if inserted == 1{
+        tx.execute(sqlx::query("UPDATE files SET ref_count = ref_count + 1 WHERE id = ?")
+            .bind(file)).await?;
+    }
+    Ok(())
+}
+#[instrument(err,skip_all)]
+pub async fn detach(pool: &MySqlPool, article: i32, file: i32, kind: FileRefKind) -> DBResult<()>{
+    let mut tx = pool.begin().await?;
+    detach_in_tx(&mut tx, article, file, kind).await?;
+    if kind == FileRefKind::Cover{
+        tx.execute(sqlx::query("UPDATE articles SET cover_id = 0 WHERE id = ? AND cover_id = ?")
+            .bind(article)
+            .bind(file)).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+async fn detach_in_tx(tx: &mut sqlx::Transaction<'_, sqlx::MySql>, article: i32, file: i32, kind: FileRefKind) -> DBResult<()>{
+    let deleted = tx.execute(sqlx::query("DELETE FROM file_refs WHERE file = ? AND article = ? AND kind = ?")
+        .bind(file)
+        .bind(article)
+        .bind(kind.to_i8())).await?.rows_affected();
+    if deleted == 1{
+        tx.execute(sqlx::query("UPDATE files SET ref_count = ref_count - 1 WHERE id = ?")
+            .bind(file)).await?;
+    }
+    Ok(())
+}
+#[instrument(err,skip_all)]
+pub async fn select_by_article(pool: &MySqlPool, article: i32) -> DBResult<Vec<File>>{
+    sqlx::query_as::<_,File>("SELECT files.* FROM files JOIN file_refs ON files.id = file_refs.file WHERE file_refs.article = ?")
+        .bind(article)
+        .fetch_all(pool)
+        .await
+}
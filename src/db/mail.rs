@@ -0,0 +1,170 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, MySql, MySqlPool, Transaction};
+use tracing::instrument;
+use super::DBResult;
+
+#[derive(Serialize,Deserialize,Debug,Clone,Copy,PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MailState{
+    Pending,
+    Sent,
+    // gave up after too many attempts
+    Dead
+}
+impl MailState{
+    fn to_i8(self) -> i8{
+        match self{
+            MailState::Pending => 0,
+            MailState::Sent => 1,
+            MailState::Dead => 2
+        }
+    }
+}
+impl TryFrom<i8> for MailState{
+    type Error = String;
+    fn try_from(value: i8) -> Result<Self, Self::Error> {
+        match value{
+            0 => Ok(MailState::Pending),
+            1 => Ok(MailState::Sent),
+            2 => Ok(MailState::Dead),
+            v => Err(format!("unknown mail state {v}"))
+        }
+    }
+}
+
+#[derive(Serialize,Debug,FromRow)]
+pub struct QueuedMail{
+    pub id: i32,
+    pub recipient: String,
+    pub subject: String,
+    #[serde(skip_serializing)]
+    pub body: String,
+    #[serde(skip_serializing)]
+    pub text_body: Option<String>,
+    #[serde(skip_serializing)]
+    pub list_unsubscribe: Option<String>,
+    #[sqlx(try_from = "i8")]
+    pub state: MailState,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub sent_at: Option<DateTime<Utc>>,
+}
+pub struct NewMail{
+    // NAME <MAIL>
+    pub recipient: String,
+    pub subject: String,
+    pub body: String,
+    pub text_body: Option<String>,
+    pub list_unsubscribe: Option<String>,
+}
+pub struct MailAttempt<'a>{
+    pub state: MailState,
+    pub next_attempt_at: DateTime<Utc>,
+    pub error: Option<&'a str>,
+}
+
+const INSERT_MAIL: &str = "INSERT INTO mail_queue (recipient,subject,body,text_body,list_unsubscribe,state,attempts,next_attempt_at,created_at) VALUES (?,?,?,?,?,?,0,?,?)";
+#[instrument(err,skip_all)]
+pub async fn enqueue(pool: &MySqlPool, mail: &NewMail) -> DBResult<i32>{
+    let now = Utc::now();
+    Ok(sqlx::query(INSERT_MAIL)
+        .bind(&mail.recipient)
+        .bind(&mail.subject)
+        .bind(&mail.body)
+        .bind(&mail.text_body)
+        .bind(&mail.list_unsubscribe)
+        .bind(MailState::Pending.to_i8())
+        .bind(now)
+        .bind(now)
+        .execute(pool)
+        .await?.last_insert_id() as i32)
+}
+pub(super) async fn enqueue_in_tx(tx: &mut Transaction<'_, MySql>, mail: &NewMail) -> DBResult<()>{
+    let now = Utc::now();
+    sqlx::query(INSERT_MAIL)
+        .bind(&mail.recipient)
+        .bind(&mail.subject)
+        .bind(&mail.body)
+        .bind(&mail.text_body)
+        .bind(&mail.list_unsubscribe)
+        .bind(MailState::Pending.to_i8())
+        .bind(now)
+        .bind(now)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+// due mails are pushed back by the lease while they are sent, so another instance skips
+// them, and become due again if the one holding them goes away before recording the attempt
+#[instrument(err,skip_all)]
+pub async fn claim_due(pool: &MySqlPool, limit: i32, lease: chrono::Duration) -> DBResult<Vec<QueuedMail>>{
+    let mut tx = pool.begin().await?;
+    let mails = sqlx::query_as::<_,QueuedMail>("SELECT * FROM mail_queue WHERE state = ? AND next_attempt_at <= ? ORDER BY next_attempt_at LIMIT ? FOR UPDATE SKIP LOCKED")
+        .bind(MailState::Pending.to_i8())
+        .bind(Utc::now())
+        .bind(limit)
+        .fetch_all(&mut *tx)
+        .await?;
+    if !mails.is_empty(){
+        let placeholders = vec!["?"; mails.len()].join(",");
+        let sql = format!("UPDATE mail_queue SET next_attempt_at = ? WHERE id IN ({placeholders})");
+        let mut query = sqlx::query(&sql).bind(Utc::now() + lease);
+        for mail in &mails{
+            query = query.bind(mail.id);
+        }
+        query.execute(&mut *tx).await?;
+    }
+    tx.commit().await?;
+    Ok(mails)
+}
+#[instrument(err,skip_all)]
+pub async fn list(pool: &MySqlPool, state: Option<MailState>, limit: i32, offset: i32) -> DBResult<(i32,Vec<QueuedMail>)>{
+    let state = state.map(MailState::to_i8);
+    let total = sqlx::query_as::<_,(i64,)>("SELECT count(id) FROM mail_queue WHERE ? IS NULL OR state = ?")
+        .bind(state)
+        .bind(state)
+        .fetch_one(pool)
+        .await?.0;
+    let mails = sqlx::query_as::<_,QueuedMail>("SELECT * FROM mail_queue WHERE ? IS NULL OR state = ? ORDER BY id DESC LIMIT ?,?")
+        .bind(state)
+        .bind(state)
+        .bind(offset)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+    Ok((total as i32, mails))
+}
+#[instrument(err,skip_all)]
+pub async fn record_attempt(pool: &MySqlPool, id: i32, attempt: &MailAttempt<'_>) -> DBResult<()>{
+    let sent_at = (attempt.state == MailState::Sent).then(Utc::now);
+    sqlx::query("UPDATE mail_queue SET state = ?, attempts = attempts + 1, next_attempt_at = ?, error = ?, sent_at = ? WHERE id = ?")
+        .bind(attempt.state.to_i8())
+        .bind(attempt.next_attempt_at)
+        .bind(attempt.error)
+        .bind(sent_at)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+// a sent mail is never sent again, dead ones start over with a fresh attempt budget
+#[instrument(err,skip_all)]
+pub async fn retry(pool: &MySqlPool, id: i32) -> DBResult<bool>{
+    Ok(sqlx::query("UPDATE mail_queue SET state = ?, attempts = 0, next_attempt_at = ? WHERE id = ? AND state != ?")
+        .bind(MailState::Pending.to_i8())
+        .bind(Utc::now())
+        .bind(id)
+        .bind(MailState::Sent.to_i8())
+        .execute(pool)
+        .await?.rows_affected() == 1)
+}
+#[instrument(err,skip_all)]
+pub async fn purge(pool: &MySqlPool, state: MailState) -> DBResult<u64>{
+    Ok(sqlx::query("DELETE FROM mail_queue WHERE state = ?")
+        .bind(state.to_i8())
+        .execute(pool)
+        .await?.rows_affected())
+}
@@ -13,6 +13,17 @@ pub mod rbac;
 pub mod user;
 pub mod verification;
 pub mod fs;
+pub mod file;
+pub mod quota;
+pub mod webhook;
+pub mod mail;
+pub mod subscriber;
+pub mod notification;
+pub mod cache;
+pub mod audit;
+pub mod profile;
+pub mod export;
+pub mod comment;
 
 pub static DB_POOL: OnceCell<MySqlPool> = OnceCell::new();
 pub fn get_db_pool() -> &'static MySqlPool {
@@ -56,6 +67,9 @@ impl AppService for DBService {
             Ok(t) => t.get("version()"),
             Err(_e) => String::from("unknown"),
         };
+        sqlx::migrate!().run(&pool).await.map_err(|e|{
+            error!("failed to run database migrations, {:?}", e);
+        })?;
         DB_POOL.set(pool).unwrap();
         info!("connected. Mysql version: {}", version);
         Ok(())
@@ -0,0 +1,189 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use sqlx::{FromRow, MySqlPool, QueryBuilder};
+use tracing::instrument;
+use super::DBResult;
+
+#[derive(Serialize,Deserialize,Debug,Clone,Copy,PartialEq,Eq,Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind{
+    // someone replied to a comment of the user
+    CommentReply,
+    // someone commented on an article of the user
+    ArticleComment,
+    RoleChanged,
+    // an export of the data of the user can be downloaded
+    DataExportReady
+}
+impl NotificationKind{
+    pub const ALL: [NotificationKind; 4] = [
+        NotificationKind::CommentReply,
+        NotificationKind::ArticleComment,
+        NotificationKind::RoleChanged,
+        NotificationKind::DataExportReady
+    ];
+    pub fn as_str(&self) -> &'static str{
+        match self{
+            NotificationKind::CommentReply => "comment_reply",
+            NotificationKind::ArticleComment => "article_comment",
+            NotificationKind::RoleChanged => "role_changed",
+            NotificationKind::DataExportReady => "data_export_ready"
+        }
+    }
+    pub fn parse(s: &str) -> Option<Self>{
+        Self::ALL.into_iter().find(|k| k.as_str() == s)
+    }
+    pub fn default_channel(&self) -> Channel{
+        match self{
+            NotificationKind::RoleChanged | NotificationKind::DataExportReady => Channel::Both,
+            _ => Channel::InApp
+        }
+    }
+}
+impl TryFrom<String> for NotificationKind{
+    type Error = String;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value).ok_or_else(|| format!("unknown notification kind {value}"))
+    }
+}
+
+#[derive(Serialize,Deserialize,Debug,Clone,Copy,PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel{
+    None,
+    InApp,
+    Email,
+    Both
+}
+impl Channel{
+    fn to_i8(self) -> i8{
+        match self{
+            Channel::None => 0,
+            Channel::InApp => 1,
+            Channel::Email => 2,
+            Channel::Both => 3
+        }
+    }
+    pub fn in_app(&self) -> bool{
+        matches!(self, Channel::InApp | Channel::Both)
+    }
+    pub fn email(&self) -> bool{
+        matches!(self, Channel::Email | Channel::Both)
+    }
+}
+impl TryFrom<i8> for Channel{
+    type Error = String;
+    fn try_from(value: i8) -> Result<Self, Self::Error> {
+        match value{
+            0 => Ok(Channel::None),
+            1 => Ok(Channel::InApp),
+            2 => Ok(Channel::Email),
+            3 => Ok(Channel::Both),
+            v => Err(format!("unknown notification channel {v}"))
+        }
+    }
+}
+
+#[derive(Serialize,Debug,FromRow)]
+pub struct Notification{
+    pub id: i32,
+    #[serde(skip_serializing)]
+    pub user: i32,
+    #[sqlx(try_from = "String")]
+    pub kind: NotificationKind,
+    pub payload: Json<serde_json::Value>,
+    pub is_read: bool,
+    pub created_at: DateTime<Utc>,
+}
+#[derive(Serialize,Debug,FromRow)]
+pub struct Preference{
+    #[sqlx(try_from = "String")]
+    pub kind: NotificationKind,
+    #[sqlx(try_from = "i8")]
+    pub channel: Channel,
+}
+
+#[instrument(err,skip_all)]
+pub async fn create(pool: &MySqlPool, user: i32, kind: NotificationKind, payload: &serde_json::Value) -> DBResult<i32>{
+    Ok(sqlx::query("INSERT INTO notifications (user,kind,payload,is_read,created_at) VALUES (?,?,?,FALSE,?)")
+        .bind(user)
+        .bind(kind.as_str())
+        .bind(Json(payload))
+        .bind(Utc::now())
+        .execute(pool)
+        .await?.last_insert_id() as i32)
+}
+#[instrument(err,skip_all)]
+pub async fn list(pool: &MySqlPool, user: i32, unread_only: bool, limit: i32, offset: i32) -> DBResult<(i32,Vec<Notification>)>{
+    let total = sqlx::query_as::<_,(i64,)>("SELECT count(id) FROM notifications WHERE user = ? AND (is_read = FALSE OR ? = FALSE)")
+        .bind(user)
+        .bind(unread_only)
+        .fetch_one(pool)
+        .await?.0;
+    let notifications = sqlx::query_as::<_,Notification>("SELECT * FROM notifications WHERE user = ? AND (is_read = FALSE OR ? = FALSE) ORDER BY id DESC LIMIT ?,?")
+        .bind(user)
+        .bind(unread_only)
+        .bind(offset)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+    Ok((total as i32, notifications))
+}
+#[instrument(err,skip_all)]
+pub async fn unread_count(pool: &MySqlPool, user: i32) -> DBResult<i32>{
+    Ok(sqlx::query_as::<_,(i64,)>("SELECT count(id) FROM notifications WHERE user = ? AND is_read = FALSE")
+        .bind(user)
+        .fetch_one(pool)
+        .await?.0 as i32)
+}
+// ids of other users are silently skipped
+#[instrument(err,skip_all)]
+pub async fn mark_read(pool: &MySqlPool, user: i32, ids: &[i32]) -> DBResult<u64>{
+    if ids.is_empty(){
+        return Ok(0);
+    }
+    let mut query = QueryBuilder::new("UPDATE notifications SET is_read = TRUE WHERE user = ");
+    query.push_bind(user);
+    query.push(" AND id IN (");
+    let mut separated = query.separated(",");
+    for id in ids{
+        separated.push_bind(id);
+    }
+    separated.push_unseparated(")");
+    Ok(query.build().execute(pool).await?.rows_affected())
+}
+#[instrument(err,skip_all)]
+pub async fn mark_all_read(pool: &MySqlPool, user: i32) -> DBResult<u64>{
+    Ok(sqlx::query("UPDATE notifications SET is_read = TRUE WHERE user = ? AND is_read = FALSE")
+        .bind(user)
+        .execute(pool)
+        .await?.rows_affected())
+}
+
+#[instrument(err,skip_all)]
+pub async fn select_preferences(pool: &MySqlPool, user: i32) -> DBResult<Vec<Preference>>{
+    sqlx::query_as::<_,Preference>("SELECT kind, channel FROM notification_preferences WHERE user = ?")
+        .bind(user)
+        .fetch_all(pool)
+        .await
+}
+#[instrument(err,skip_all)]
+pub async fn select_channel(pool: &MySqlPool, user: i32, kind: NotificationKind) -> DBResult<Channel>{
+    let channel = sqlx::query_as::<_,(i8,)>("SELECT channel FROM notification_preferences WHERE user = ? AND kind = ? LIMIT 1")
+        .bind(user)
+        .bind(kind.as_str())
+        .fetch_optional(pool)
+        .await?;
+    Ok(channel.and_then(|c| Channel::try_from(c.0).ok()).unwrap_or(kind.default_channel()))
+}
+#[instrument(err,skip_all)]
+pub async fn set_preference(pool: &MySqlPool, user: i32, kind: NotificationKind, channel: Channel) -> DBResult<()>{
+    sqlx::query("INSERT INTO notification_preferences (user,kind,channel) VALUES (?,?,?) ON DUPLICATE KEY UPDATE channel = VALUES(channel)")
+        .bind(user)
+        .bind(kind.as_str())
+        .bind(channel.to_i8())
+        .execute(pool)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use sqlx::{FromRow, MySql, MySqlPool, Transaction};
+use tracing::instrument;
+use validator::Validate;
+use super::DBResult;
+
+#[derive(Serialize,Deserialize,Debug,Clone,Validate)]
+pub struct ProfileLink{
+    #[validate(length(min = 1, max = 30))]
+    pub label: String,
+    #[validate(url, length(max = 255), custom = "crate::utils::validate::check_http_url")]
+    pub url: String,
+}
+#[derive(Serialize,Debug,FromRow,Default)]
+pub struct Profile{
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub website: Option<String>,
+    pub links: Json<Vec<ProfileLink>>,
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
+}
+
+#[instrument(err,skip_all)]
+pub async fn select(pool: &MySqlPool, user: i32) -> DBResult<Profile>{
+    Ok(sqlx::query_as::<_,Profile>("SELECT display_name,bio,website,links,locale,timezone FROM user_profiles WHERE user = ? LIMIT 1")
+        .bind(user)
+        .fetch_optional(pool)
+        .await?
+        .unwrap_or_default())
+}
+// `change` gets the stored profile, or an empty one, under a row lock so concurrent updates do not drop each other
+#[instrument(err,skip_all)]
+pub async fn update(pool: &MySqlPool, user: i32, change: impl FnOnce(&mut Profile)) -> DBResult<()>{
+    let mut tx = pool.begin().await?;
+    let mut profile = sqlx::query_as::<_,Profile>("SELECT display_name,bio,website,links,locale,timezone FROM user_profiles WHERE user = ? LIMIT 1 FOR UPDATE")
+        .bind(user)
+        .fetch_optional(&mut *tx)
+        .await?
+        .unwrap_or_default();
+    change(&mut profile);
+    upsert(&mut tx, user, &profile).await?;
+    tx.commit().await
+}
+async fn upsert(tx: &mut Transaction<'_, MySql>, user: i32, profile: &Profile) -> DBResult<()>{
+    sqlx::query("INSERT INTO user_profiles (user,display_name,bio,website,links,locale,timezone,updated_at) VALUES (?,?,?,?,?,?,?,?) ON DUPLICATE KEY UPDATE display_name = VALUES(display_name), bio = VALUES(bio), website = VALUES(website), links = VALUES(links), locale = VALUES(locale), timezone = VALUES(timezone), updated_at = VALUES(updated_at)")
+        .bind(user)
+        .bind(&profile.display_name)
+        .bind(&profile.bio)
+        .bind(&profile.website)
+        .bind(&profile.links)
+        .bind(&profile.locale)
+        .bind(&profile.timezone)
+        .bind(Utc::now())
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, FromRow, MySql, MySqlPool, QueryBuilder};
+use tracing::instrument;
+use super::DBResult;
+
+#[derive(Serialize,Deserialize,Debug,FromRow,Default,Clone,Copy)]
+pub struct Quota{
+    pub quota_bytes: Option<i64>,
+    pub quota_files: Option<i32>,
+}
+#[derive(Serialize,Debug,FromRow,Default,Clone,Copy)]
+pub struct Usage{
+    pub used_bytes: i64,
+    pub used_files: i64,
+}
+
+#[instrument(err,skip_all)]
+pub async fn select_user_quota(pool: &MySqlPool, user: i32) -> DBResult<Quota>{
+    Ok(sqlx::query_as::<_,Quota>("SELECT quota_bytes,quota_files FROM users WHERE id = ? LIMIT 1")
+        .bind(user)
+        .fetch_optional(pool)
+        .await?
+        .unwrap_or_default())
+}
+// None when there is no such role
+#[instrument(err,skip_all)]
+pub async fn select_role_quota(pool: &MySqlPool, role: i32) -> DBResult<Option<Quota>>{
+    sqlx::query_as::<_,Quota>("SELECT quota_bytes,quota_files FROM roles WHERE id = ? LIMIT 1")
+        .bind(role)
+        .fetch_optional(pool)
+        .await
+}
+#[instrument(err,skip_all)]
+pub async fn select_role_quotas(pool: &MySqlPool, roles: &[i32]) -> DBResult<Vec<Quota>>{
+    if roles.is_empty(){
+        return Ok(vec![]);
+    }
+    let mut query_builder: QueryBuilder<MySql> = QueryBuilder::new(
+        "SELECT quota_bytes,quota_files FROM roles WHERE id IN ("
+    );
+    let mut separated = query_builder.separated(",");
+    for role in roles{
+        separated.push_bind(role);
+    }
+    separated.push_unseparated(")");
+    query_builder.build_query_as::<Quota>()
+        .fetch_all(pool)
+        .await
+}
+// files and avatars, SUM over no rows gives NULL
+const USAGE_QUERY: &str = "SELECT CAST(COALESCE(SUM(size), 0) + (SELECT avatar_bytes FROM users WHERE id = ?) AS SIGNED),COUNT(id) FROM files WHERE owner = ?";
+impl Usage{
+    fn fits(&self, quota: &Quota, bytes: i64, files: i64) -> bool{
+        quota.quota_bytes.is_none_or(|q| self.used_bytes + bytes <= q)
+            && quota.quota_files.is_none_or(|q| self.used_files + files <= q as i64)
+    }
+}
+#[instrument(err,skip_all)]
+pub async fn select_usage(pool: &MySqlPool, user: i32) -> DBResult<Usage>{
+    let res: (Option<i64>, i64) = sqlx::query_as(USAGE_QUERY)
+        .bind(user)
+        .bind(user)
+        .fetch_one(pool)
+        .await?;
+    Ok(Usage{
+        used_bytes: res.0.unwrap_or(0),
+        used_files: res.1
+    })
+}
+// the row lock on the user serializes concurrent uploads, so two of them can not both
+// take the last of the quota; false if the change does not fit
+pub(super) async fn lock_and_check(
+    tx: &mut sqlx::Transaction<'_, MySql>,
+    user: i32,
+    quota: &Quota,
+    bytes: i64,
+    files: i64
+) -> DBResult<bool>{
+    tx.execute(sqlx::query("SELECT id FROM users WHERE id = ? FOR UPDATE").bind(user)).await?;
+    let res: (Option<i64>, i64) = sqlx::query_as(USAGE_QUERY)
+        .bind(user)
+        .bind(user)
+        .fetch_one(&mut **tx)
+        .await?;
+    Ok(Usage{ used_bytes: res.0.unwrap_or(0), used_files: res.1 }.fits(quota, bytes, files))
+}
+// a new avatar replaces the old one, so only the difference has to fit
+#[instrument(err,skip_all)]
+pub async fn update_avatar_bytes(pool: &MySqlPool, user: i32, bytes: i64, quota: &Quota) -> DBResult<bool>{
+    let mut tx = pool.begin().await?;
+    let old: (i64,) = sqlx::query_as("SELECT avatar_bytes FROM users WHERE id = ? FOR UPDATE")
+        .bind(user)
+        .fetch_one(&mut *tx)
+        .await?;
+    if !lock_and_check(&mut tx, user, quota, bytes - old.0, 0).await? {
+        return Ok(false);
+    }
+    tx.execute(sqlx::query("UPDATE users SET avatar_bytes = ? WHERE id = ?")
+        .bind(bytes)
+        .bind(user)).await?;
+    tx.commit().await?;
+    Ok(true)
+}
+#[instrument(err,skip_all)]
+pub async fn update_user_quota(pool: &MySqlPool, user: i32, quota: &Quota) -> DBResult<()>{
+    sqlx::query("UPDATE users SET quota_bytes = ?, quota_files = ? WHERE id = ?")
+        .bind(quota.quota_bytes)
+        .bind(quota.quota_files)
+        .bind(user)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+#[instrument(err,skip_all)]
+pub async fn update_role_quota(pool: &MySqlPool, role: i32, quota: &Quota) -> DBResult<()>{
+    sqlx::query("UPDATE roles SET quota_bytes = ?, quota_files = ? WHERE id = ?")
+        .bind(quota.quota_bytes)
+        .bind(quota.quota_files)
+        .bind(role)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
@@ -1,8 +1,9 @@
 use rustle_derive::ErrorHelper;
 use serde::Serialize;
-use sqlx::{Executor, MySql, MySqlPool, QueryBuilder, Row};
+use sqlx::{Executor, MySql, MySqlPool, QueryBuilder, Row, Transaction};
 
 use crate::types::{err::{AppResult, GlobalUserError}, join::Joinable};
+use crate::db::user::UserIdName;
 
 use super::DBResult;
 use tracing::instrument;
@@ -38,7 +39,7 @@ pub struct RoleSimple{
 //     pub user: i32,
 //     pub roles: Vec<RoleSimple>,
 // }
-pub const DEFAULT_ROLE_STR: &'static str = "0,1";
+pub const DEFAULT_ROLES: &[i32] = &[0, 1];
 #[instrument(err,skip_all)]
 pub async fn select_permission_to_roles(
     pool: &MySqlPool,
@@ -48,31 +49,35 @@ pub async fn select_permission_to_roles(
         .await
 }
 #[instrument(err,skip_all)]
-pub async fn select_user_role(
+pub async fn select_user_roles(
     pool: &MySqlPool,
     user: i32
-) -> DBResult<String> {
-    let res: (String,) = match sqlx::query_as("SELECT roles FROM users WHERE id = ? LIMIT 1")
+) -> DBResult<Vec<i32>> {
+    Ok(sqlx::query_as::<_, (i32,)>("SELECT role FROM user_roles WHERE user = ? ORDER BY role")
         .bind(user)
-        .fetch_one(pool)
-        .await{
-            Ok(t) => t,
-            Err(sqlx::Error::RowNotFound) => return Ok(String::from("")),
-            Err(e) => return Err(e)
-        };
-    Ok(res.0)
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|t| t.0)
+        .collect())
 }
 #[instrument(err,skip_all)]
 pub async fn update_user_roles(
     pool: &MySqlPool,
     user: i32,
-    roles: &str
+    roles: &[i32]
 ) -> DBResult<()> {
-    sqlx::query("UPDATE users SET roles = ? WHERE id = ?")
-        .bind(roles)
-        .bind(user)
-        .execute(pool)
-        .await?;
+    let mut tx = pool.begin().await?;
+    tx.execute(sqlx::query("DELETE FROM user_roles WHERE user = ?")
+        .bind(user)).await?;
+    if !roles.is_empty() {
+        let mut query_builder: QueryBuilder<MySql> = QueryBuilder::new("INSERT INTO user_roles (user, role) ");
+        query_builder.push_values(roles, |mut b, role| {
+            b.push_bind(user).push_bind(role);
+        });
+        tx.execute(query_builder.build()).await?;
+    }
+    tx.commit().await?;
     Ok(())
 }
 #[instrument(err,skip_all)]
@@ -112,6 +117,12 @@ pub async fn delete_role(
     tx.execute(sqlx::query(r#"
         DELETE FROM permission_to_roles WHERE role = ?;
         "#).bind(role)).await?;
+    tx.execute(sqlx::query(r#"
+        DELETE FROM user_roles WHERE role = ?;
+        "#).bind(role)).await?;
+    tx.execute(sqlx::query(r#"
+        DELETE FROM role_parents WHERE role = ? OR parent = ?;
+        "#).bind(role).bind(role)).await?;
     tx.execute(sqlx::query(r#"
         DELETE FROM roles WHERE id = ?;
         "#).bind(role)).await?;
@@ -125,51 +136,256 @@ pub async fn add_role(
     pool: &MySqlPool,
     name: &str,
     alias: &str,
-    permissions: Vec<&str>
-) -> DBResult<AppResult<()>> {
+    permissions: &[&str]
+) -> DBResult<AppResult<i32>> {
     let mut tx = pool.begin().await?;
     let role_id = tx.execute(sqlx::query(r#"
         INSERT INTO roles (name,alias,role_type) VALUES (?,?,0)
     "#).bind(name).bind(alias)
     ).await?.last_insert_id();
-    let mut query_builder: QueryBuilder<MySql> = QueryBuilder::new(
-        // Note the trailing space
-        "INSERT INTO permission_to_roles(role, permission) "
-    );
-    
-    query_builder.push_values(permissions, |mut b, entry| {
-        b.push_bind(role_id).push_bind(entry);
-    });
-    tx.execute(query_builder.build()).await?;
+    // an empty VALUES list is not valid sql, a role may start without permissions
+    if !permissions.is_empty() {
+        let mut query_builder: QueryBuilder<MySql> = QueryBuilder::new(
+            // Note the trailing space
+            "INSERT INTO permission_to_roles(role, permission) "
+        );
+
+        query_builder.push_values(permissions, |mut b, entry| {
+            b.push_bind(role_id).push_bind(entry);
+        });
+        tx.execute(query_builder.build()).await?;
+    }
     tx.commit().await?;
-    Ok(Ok(()))
+    Ok(Ok(role_id as i32))
 }
 
+// the permissions and parents of a system role are fixed, the row stays locked until the transaction ends
+async fn lock_custom_role(tx: &mut Transaction<'_, MySql>, role: i32) -> DBResult<AppResult<()>> {
+    let role_type = tx.fetch_optional(sqlx::query("SELECT role_type FROM roles WHERE id = ? LIMIT 1 FOR UPDATE")
+        .bind(role)).await?;
+    Ok(match role_type {
+        None => Err(GlobalUserError::NotFound.into()),
+        Some(t) if t.try_get::<i8, _>("role_type")? == 1i8 => Err(GlobalUserError::SystemReserved.into()),
+        Some(_) => Ok(())
+    })
+}
 #[instrument(err,skip_all)]
 pub async fn delete_role_permission(
     pool: &MySqlPool,
     role: i32,
     permission: &str
-) -> DBResult<()> {
-    sqlx::query("DELETE roles WHERE role = ? AND permission = ?")
+) -> DBResult<AppResult<bool>> {
+    let mut tx = pool.begin().await?;
+    if let Err(e) = lock_custom_role(&mut tx, role).await? {
+        return Ok(Err(e));
+    }
+    let deleted = tx.execute(sqlx::query("DELETE FROM permission_to_roles WHERE role = ? AND permission = ?")
         .bind(role)
-        .bind(permission)
-        .execute(pool)
-        .await?;
-    Ok(())
+        .bind(permission)).await?.rows_affected() > 0;
+    tx.commit().await?;
+    Ok(Ok(deleted))
 }
 #[instrument(err,skip_all)]
 pub async fn add_role_permission(
     pool: &MySqlPool,
     role: i32,
     permission: &str
-) -> DBResult<()> {
-    sqlx::query("INSERT INTO roles (role, permission) VALUES (?,?)")
+) -> DBResult<AppResult<bool>> {
+    let mut tx = pool.begin().await?;
+    if let Err(e) = lock_custom_role(&mut tx, role).await? {
+        return Ok(Err(e));
+    }
+    let granted = tx.fetch_optional(sqlx::query("SELECT id FROM permission_to_roles WHERE role = ? AND permission = ? LIMIT 1")
+        .bind(role)
+        .bind(permission)).await?;
+    if granted.is_some() {
+        return Ok(Ok(false));
+    }
+    tx.execute(sqlx::query("INSERT INTO permission_to_roles (role, permission) VALUES (?,?)")
+        .bind(role)
+        .bind(permission)).await?;
+    tx.commit().await?;
+    Ok(Ok(true))
+}
+#[instrument(err,skip_all)]
+pub async fn select_role(
+    pool: &MySqlPool,
+    role: i32
+) -> DBResult<Option<Role>> {
+    sqlx::query_as::<_, Role>("SELECT * FROM roles WHERE id = ? LIMIT 1")
+        .bind(role)
+        .fetch_optional(pool)
+        .await
+}
+#[instrument(err,skip_all)]
+pub async fn select_role_permissions(
+    pool: &MySqlPool,
+    role: i32
+) -> DBResult<Vec<String>> {
+    Ok(sqlx::query_as::<_, (String,)>("SELECT permission FROM permission_to_roles WHERE role = ? ORDER BY permission")
+        .bind(role)
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|t| t.0)
+        .collect())
+}
+// how many of the given role ids exist, the ids must not repeat
+#[instrument(err,skip_all)]
+pub async fn count_existing_roles(
+    pool: &MySqlPool,
+    roles: &[i32]
+) -> DBResult<i32> {
+    if roles.is_empty() {
+        return Ok(0);
+    }
+    let mut query: QueryBuilder<MySql> = QueryBuilder::new("SELECT COUNT(*) FROM roles WHERE id IN (");
+    let mut separated = query.separated(",");
+    for role in roles {
+        separated.push_bind(role);
+    }
+    separated.push_unseparated(")");
+    Ok(query.build_query_as::<(i64,)>().fetch_one(pool).await?.0 as i32)
+}
+#[instrument(err,skip_all)]
+pub async fn update_role(
+    pool: &MySqlPool,
+    role: i32,
+    name: &str,
+    alias: &str
+) -> DBResult<AppResult<()>> {
+    let mut tx = pool.begin().await?;
+    let role_type = tx.fetch_optional(sqlx::query("SELECT role_type FROM roles WHERE id = ? LIMIT 1 FOR UPDATE")
+        .bind(role)).await?;
+    match role_type {
+        None => return Ok(Err(GlobalUserError::NotFound.into())),
+        Some(t) if t.try_get::<i8, _>("role_type")? == 1i8 => return Ok(Err(GlobalUserError::SystemReserved.into())),
+        Some(_) => ()
+    }
+    tx.execute(sqlx::query("UPDATE roles SET name = ?, alias = ? WHERE id = ?")
+        .bind(name)
+        .bind(alias)
+        .bind(role)).await?;
+    tx.commit().await?;
+    Ok(Ok(()))
+}
+#[instrument(err,skip_all)]
+pub async fn list_role_users(
+    pool: &MySqlPool,
+    role: i32,
+    limit: i32,
+    offset: i32
+) -> DBResult<(i32, Vec<UserIdName>)> {
+    let total = sqlx::query_as::<_, (i64,)>("SELECT COUNT(*) FROM user_roles WHERE role = ?")
+        .bind(role)
+        .fetch_one(pool)
+        .await?.0;
+    let users = sqlx::query_as::<_, UserIdName>("SELECT users.id,users.name FROM user_roles JOIN users ON users.id = user_roles.user WHERE user_roles.role = ? ORDER BY users.id LIMIT ?,?")
         .bind(role)
-        .bind(permission)
-        .execute(pool)
+        .bind(offset)
+        .bind(limit)
+        .fetch_all(pool)
         .await?;
-    Ok(())
+    Ok((total as i32, users))
+}
+// adds or takes away a single role, returns the roles the user holds afterwards
+#[instrument(err,skip_all)]
+pub async fn modify_user_role(
+    pool: &MySqlPool,
+    user: i32,
+    role: i32,
+    assign: bool
+) -> DBResult<AppResult<Vec<i32>>> {
+    let mut tx = pool.begin().await?;
+    if tx.fetch_optional(sqlx::query("SELECT id FROM users WHERE id = ? LIMIT 1").bind(user)).await?.is_none() {
+        return Ok(Err(GlobalUserError::NotFound.into()));
+    }
+    if assign {
+        if tx.fetch_optional(sqlx::query("SELECT id FROM roles WHERE id = ? LIMIT 1").bind(role)).await?.is_none() {
+            return Ok(Err(GlobalUserError::NotFound.into()));
+        }
+        tx.execute(sqlx::query("INSERT IGNORE INTO user_roles (user, role) VALUES (?,?)")
+            .bind(user)
+            .bind(role)).await?;
+    } else {
+        tx.execute(sqlx::query("DELETE FROM user_roles WHERE user = ? AND role = ?")
+            .bind(user)
+            .bind(role)).await?;
+    }
+    let roles = tx.fetch_all(sqlx::query("SELECT role FROM user_roles WHERE user = ? ORDER BY role")
+        .bind(user)).await?
+        .iter()
+        .map(|r| r.try_get("role"))
+        .collect::<Result<Vec<i32>, _>>()?;
+    tx.commit().await?;
+    Ok(Ok(roles))
+}
+// (role, parent) pairs
+#[instrument(err,skip_all)]
+pub async fn select_role_parents(
+    pool: &MySqlPool,
+) -> DBResult<Vec<(i32, i32)>> {
+    sqlx::query_as::<_, (i32, i32)>("SELECT role, parent FROM role_parents")
+        .fetch_all(pool)
+        .await
+}
+#[instrument(err,skip_all)]
+pub async fn select_parents_of(
+    pool: &MySqlPool,
+    role: i32
+) -> DBResult<Vec<i32>> {
+    Ok(sqlx::query_as::<_, (i32,)>("SELECT parent FROM role_parents WHERE role = ? ORDER BY parent")
+        .bind(role)
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|t| t.0)
+        .collect())
+}
+// every edge is read under lock, so the caller judges cycles on the graph the insert goes into
+// and two edges closing a cycle together on different instances wait for each other;
+// a system role may still be the parent, only what the role itself inherits changes
+#[instrument(err,skip_all)]
+pub async fn add_role_parent(
+    pool: &MySqlPool,
+    role: i32,
+    parent: i32,
+    would_cycle: impl FnOnce(Vec<(i32, i32)>) -> bool
+) -> DBResult<AppResult<()>> {
+    let mut tx = pool.begin().await?;
+    if let Err(e) = lock_custom_role(&mut tx, role).await? {
+        return Ok(Err(e));
+    }
+    if tx.fetch_optional(sqlx::query("SELECT id FROM roles WHERE id = ? LIMIT 1").bind(parent)).await?.is_none() {
+        return Ok(Err(GlobalUserError::NotFound.into()));
+    }
+    let edges = sqlx::query_as::<_, (i32, i32)>("SELECT role, parent FROM role_parents FOR UPDATE")
+        .fetch_all(&mut *tx)
+        .await?;
+    if would_cycle(edges) {
+        return Ok(Err(RBACUserError::RoleCycle.into()));
+    }
+    tx.execute(sqlx::query("INSERT IGNORE INTO role_parents (role, parent) VALUES (?,?)")
+        .bind(role)
+        .bind(parent)).await?;
+    tx.commit().await?;
+    Ok(Ok(()))
+}
+#[instrument(err,skip_all)]
+pub async fn delete_role_parent(
+    pool: &MySqlPool,
+    role: i32,
+    parent: i32
+) -> DBResult<AppResult<bool>> {
+    let mut tx = pool.begin().await?;
+    if let Err(e) = lock_custom_role(&mut tx, role).await? {
+        return Ok(Err(e));
+    }
+    let deleted = tx.execute(sqlx::query("DELETE FROM role_parents WHERE role = ? AND parent = ?")
+        .bind(role)
+        .bind(parent)).await?.rows_affected() == 1;
+    tx.commit().await?;
+    Ok(Ok(deleted))
 }
 #[instrument(err,skip_all)]
 pub async fn join_user_role_info(
@@ -182,9 +398,18 @@ pub async fn join_user_role_info(
             role_ids.extend(t);
         }
     }
-    let role_infos: Vec<RoleSimple> = sqlx::query_as(&format!("SELECT id,name FROM roles WHERE id in ({})",
-        role_ids.iter().map(|t| t.to_string()).collect::<Vec<String>>().join(",")
-    )).fetch_all(pool).await?;
+    role_ids.sort_unstable();
+    role_ids.dedup();
+    if role_ids.is_empty() {
+        return Ok(());
+    }
+    let mut query: QueryBuilder<MySql> = QueryBuilder::new("SELECT id,name FROM roles WHERE id IN (");
+    let mut separated = query.separated(",");
+    for role in &role_ids {
+        separated.push_bind(role);
+    }
+    separated.push_unseparated(")");
+    let role_infos: Vec<RoleSimple> = query.build_query_as().fetch_all(pool).await?;
     for entry in &mut *outer {
         let entry = entry.get_ref();
         let key = match entry.0{
@@ -207,25 +432,6 @@ pub async fn join_user_role_info(
 #[derive(ErrorHelper)]
 #[err(user, default_msg)]
 pub enum RBACUserError{
-    RoleTooLong,
-    RoleStrCorrupted
-}
-const MAX_ROLE_STR_LEN: usize = 255;
-pub fn role_vec_to_str(roles: Vec<i32>) -> AppResult<String>{
-    let role_str = roles.iter().map(|t| t.to_string()).collect::<Vec<String>>().join(",");
-    if role_str.len() > MAX_ROLE_STR_LEN{
-        Err(RBACUserError::RoleTooLong.into())
-    } else {
-        Ok(role_str)
-    }
+    UnknownPermission,
+    RoleCycle
 }
-pub fn role_str_to_vec(roles: String) -> AppResult<Vec<i32>>{
-    if roles.is_empty() {
-        Ok(vec![])
-    }else{
-        roles.split(",").map(|t| t.parse()).collect::<Result<Vec<i32>, _>>()
-        .map_err(|_| 
-            RBACUserError::RoleStrCorrupted.into()
-        )
-    }
-}
\ No newline at end of file
@@ -0,0 +1,117 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, MySqlPool};
+use tracing::instrument;
+use super::DBResult;
+use super::mail::{enqueue_in_tx, NewMail};
+
+#[derive(Serialize,Debug,FromRow)]
+pub struct Subscriber{
+    pub id: i32,
+    pub email: String,
+    pub lang: String,
+    pub confirmed: bool,
+    pub created_at: DateTime<Utc>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+}
+
+// subscribing again before confirming only refreshes the language
+#[instrument(err,skip_all)]
+pub async fn upsert(pool: &MySqlPool, email: &str, lang: &str) -> DBResult<()>{
+    sqlx::query("INSERT INTO subscribers (email,lang,confirmed,created_at) VALUES (?,?,FALSE,?) ON DUPLICATE KEY UPDATE lang = IF(confirmed, lang, VALUES(lang))")
+        .bind(email)
+        .bind(lang)
+        .bind(Utc::now())
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+#[instrument(err,skip_all)]
+pub async fn select_by_email(pool: &MySqlPool, email: &str) -> DBResult<Option<Subscriber>>{
+    sqlx::query_as::<_,Subscriber>("SELECT * FROM subscribers WHERE email = ? LIMIT 1")
+        .bind(email)
+        .fetch_optional(pool)
+        .await
+}
+#[instrument(err,skip_all)]
+pub async fn confirm(pool: &MySqlPool, email: &str) -> DBResult<bool>{
+    Ok(sqlx::query("UPDATE subscribers SET confirmed = TRUE, confirmed_at = ? WHERE email = ? AND confirmed = FALSE")
+        .bind(Utc::now())
+        .bind(email)
+        .execute(pool)
+        .await?.rows_affected() == 1)
+}
+// confirmed subscribers with an id above `after` a new post was not queued for yet,
+// for walking the list in batches
+#[instrument(err,skip_all)]
+pub async fn select_unnotified_after(pool: &MySqlPool, article: i32, after: i32, limit: i32) -> DBResult<Vec<Subscriber>>{
+    sqlx::query_as::<_,Subscriber>("SELECT * FROM subscribers s WHERE confirmed = TRUE AND id > ? AND NOT EXISTS (SELECT 1 FROM newsletter_deliveries d WHERE d.article = ? AND d.subscriber = s.id) ORDER BY id LIMIT ?")
+        .bind(after)
+        .bind(article)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+}
+// the mails and the record of who got them are written together, a subscriber recorded
+// meanwhile by another attempt is skipped
+#[instrument(err,skip_all)]
+pub async fn queue_new_post(pool: &MySqlPool, article: i32, mails: &[(i32, NewMail)]) -> DBResult<usize>{
+    let mut tx = pool.begin().await?;
+    let mut queued = 0;
+    for (subscriber, mail) in mails{
+        let recorded = sqlx::query("INSERT IGNORE INTO newsletter_deliveries (article,subscriber,created_at) VALUES (?,?,?)")
+            .bind(article)
+            .bind(subscriber)
+            .bind(Utc::now())
+            .execute(&mut *tx)
+            .await?.rows_affected() == 1;
+        if recorded{
+            enqueue_in_tx(&mut tx, mail).await?;
+            queued += 1;
+        }
+    }
+    tx.commit().await?;
+    Ok(queued)
+}
+// confirmed subscribers with an id above `after`, for walking the list in batches
+#[instrument(err,skip_all)]
+pub async fn select_confirmed_after(pool: &MySqlPool, after: i32, limit: i32) -> DBResult<Vec<Subscriber>>{
+    sqlx::query_as::<_,Subscriber>("SELECT * FROM subscribers WHERE confirmed = TRUE AND id > ? ORDER BY id LIMIT ?")
+        .bind(after)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+}
+#[instrument(err,skip_all)]
+pub async fn list(pool: &MySqlPool, limit: i32, offset: i32) -> DBResult<(i32,Vec<Subscriber>)>{
+    let total = sqlx::query_as::<_,(i64,)>("SELECT count(id) FROM subscribers")
+        .fetch_one(pool)
+        .await?.0;
+    let subscribers = sqlx::query_as::<_,Subscriber>("SELECT * FROM subscribers ORDER BY id LIMIT ?,?")
+        .bind(offset)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+    Ok((total as i32, subscribers))
+}
+#[instrument(err,skip_all)]
+pub async fn delete(pool: &MySqlPool, id: i32) -> DBResult<bool>{
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM newsletter_deliveries WHERE subscriber = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    let deleted = sqlx::query("DELETE FROM subscribers WHERE id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?.rows_affected() == 1;
+    tx.commit().await?;
+    Ok(deleted)
+}
+#[instrument(err,skip_all)]
+pub async fn select_by_id(pool: &MySqlPool, id: i32) -> DBResult<Option<Subscriber>>{
+    sqlx::query_as::<_,Subscriber>("SELECT * FROM subscribers WHERE id = ? LIMIT 1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+}
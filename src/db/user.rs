@@ -1,4 +1,4 @@
-use chrono::NaiveDateTime;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Serialize,Deserialize};
 
 use sqlx::mysql::MySqlRow;
@@ -6,15 +6,31 @@ use sqlx::Row;
 use tracing::instrument;
 use sqlx::MySqlPool;
 use sqlx::FromRow;
+use sqlx::Executor;
+use rustle_derive::{FilterParams, SortParams};
 use super::DBResult;
-#[derive(Deserialize,Serialize,Debug)]
+pub const STATUS_ACTIVE: i8 = 0;
+// switched off by an admin, may be switched on again
+pub const STATUS_DISABLED: i8 = 1;
+// locked out for misbehaving, until status_until if set
+pub const STATUS_BANNED: i8 = 2;
+#[derive(Deserialize,Serialize,Debug,FilterParams,SortParams)]
 pub struct User{
+    #[sortable]
     pub id: i32,
+    #[filterable]
+    #[sortable]
     pub name: String,
+    #[filterable]
+    #[sortable]
     pub email: String,
     #[serde(skip)]
     pub password: Option<String>,
+    #[sortable]
     pub avatar_time: NaiveDateTime,
+    #[filterable]
+    #[sortable]
+    pub status: i8,
     #[serde(skip)]
     pub roles: Option<Vec<i32>>
 }
@@ -27,17 +43,31 @@ impl<'r> FromRow<'r, MySqlRow> for User{
             email: row.try_get("email")?,
             password: row.try_get("password").unwrap_or(None),
             avatar_time: row.try_get("avatar_time")?,
-            roles: row.try_get::<String, _>("roles").map_or(None, |t| {
-                if t.is_empty(){
-                    Some(vec![])
-                }else{
-                    Some(t.split(",").filter_map(|r| r.parse::<i32>().ok()).collect())
-                }
+            status: row.try_get("status")?,
+            // only queries selecting roles_column!() fill it, a user without roles gets NULL
+            roles: row.try_get::<Option<String>, _>("roles").ok().map(|t| {
+                t.map(|t| t.split(",").filter_map(|r| r.parse::<i32>().ok()).collect()).unwrap_or_default()
             })
         })
     }
 }
-#[derive(Deserialize,Serialize,Debug)]
+// the role ids of users.id, joined from user_roles
+macro_rules! roles_column {
+    () => { "(SELECT GROUP_CONCAT(role ORDER BY role) FROM user_roles WHERE user_roles.user = users.id) AS roles" };
+}
+// what keeps a user from using their account, only users with one are cached
+#[derive(Serialize,Debug,Clone,FromRow)]
+pub struct UserRestriction{
+    pub id: i32,
+    pub status: i8,
+    pub status_reason: Option<String>,
+    pub status_until: Option<NaiveDateTime>,
+    pub password_reset_required: bool,
+}
+macro_rules! restriction_columns {
+    () => { "id,status,status_reason,status_until,password_reset_required" };
+}
+#[derive(Deserialize,Serialize,Debug,FromRow)]
 pub struct UserIdName{
     pub id: i32,
     pub name: String,
@@ -59,7 +89,7 @@ pub async fn select_by_id(
     pool: &MySqlPool,
     id: i32
 ) -> DBResult<Option<User>> {
-    sqlx::query_as::<_,User>("SELECT id,name,email FROM users WHERE id = ? LIMIT 1")
+    sqlx::query_as::<_,User>(concat!("SELECT id,name,email,avatar_time,status,", roles_column!(), " FROM users WHERE id = ? LIMIT 1"))
         .bind(id)
         .fetch_optional(pool)
         .await
@@ -81,7 +111,7 @@ pub async fn select_by_email(
     pool: &MySqlPool,
     email: &str
 ) -> DBResult<Option<User>> {
-    sqlx::query_as::<_,User>("SELECT id,name,email FROM users WHERE email = ? LIMIT 1")
+    sqlx::query_as::<_,User>("SELECT id,name,email,avatar_time,status FROM users WHERE email = ? LIMIT 1")
         .bind(email)
         .fetch_optional(pool)
         .await
@@ -93,15 +123,24 @@ pub async fn create(
     name: &str,
     email: &str,
     password: &str,
-    roles: &str
+    roles: &[i32]
 ) -> DBResult<i32> {
-    Ok(sqlx::query("INSERT INTO users (name,email,password,avatar_time,roles) VALUES (?,?,?,now(),?)")
+    let mut tx = pool.begin().await?;
+    let id = sqlx::query("INSERT INTO users (name,email,password,avatar_time) VALUES (?,?,?,now())")
         .bind(name)
         .bind(email)
         .bind(password)
-        .bind(roles)
-        .execute(pool)
-        .await?.last_insert_id() as i32)
+        .execute(&mut *tx)
+        .await?.last_insert_id() as i32;
+    for role in roles {
+        sqlx::query("INSERT INTO user_roles (user,role) VALUES (?,?)")
+            .bind(id)
+            .bind(role)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(id)
 }
 
 #[instrument(err,skip_all)]
@@ -110,7 +149,8 @@ pub async fn update_password(
     id: i32,
     password: &str
 ) -> DBResult<()> {
-    sqlx::query("UPDATE users SET password = ? WHERE id = ?")
+    // a new password is what a forced reset asks for
+    sqlx::query("UPDATE users SET password = ?, password_reset_required = FALSE WHERE id = ?")
         .bind(password)
         .bind(id)
         .execute(pool)
@@ -118,26 +158,292 @@ pub async fn update_password(
     Ok(())
 }
 
+// false when another account holds the address, the unique key decides between concurrent changes
 #[instrument(err,skip_all)]
 pub async fn update_email(
     pool: &MySqlPool,
     id: i32,
     email: &str
+) -> DBResult<bool>{
+    match sqlx::query("UPDATE users SET email = ? WHERE id = ?")
+        .bind(email)
+        .bind(id)
+        .execute(pool)
+        .await {
+        Ok(_) => Ok(true),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => Ok(false),
+        Err(e) => Err(e)
+    }
+}
+
+// lets clients tell a new avatar from a cached one
+#[instrument(err,skip_all)]
+pub async fn touch_avatar(
+    pool: &MySqlPool,
+    id: i32
+) -> DBResult<()>{
+    sqlx::query("UPDATE users SET avatar_time = now() WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+#[instrument(err,skip_all)]
+pub async fn update_identity(
+    pool: &MySqlPool,
+    id: i32,
+    name: &str,
+    email: &str
 ) -> DBResult<()>{
-    sqlx::query("UPDATE users SET email = ? WHERE id = ?")
+    sqlx::query("UPDATE users SET name = ?, email = ? WHERE id = ?")
+        .bind(name)
         .bind(email)
         .bind(id)
         .execute(pool)
         .await?;
-        Ok(())
+    Ok(())
+}
+
+// (name taken, email taken) by a user other than except
+#[instrument(err,skip_all)]
+pub async fn select_taken(
+    pool: &MySqlPool,
+    name: &str,
+    email: &str,
+    except: Option<i32>
+) -> DBResult<(bool, bool)>{
+    let rows = sqlx::query_as::<_,(i64,i64)>("SELECT name = ?, email = ? FROM users WHERE (name = ? OR email = ?) AND id != ?")
+        .bind(name)
+        .bind(email)
+        .bind(name)
+        .bind(email)
+        .bind(except.unwrap_or(0))
+        .fetch_all(pool)
+        .await?;
+    Ok((rows.iter().any(|t| t.0 != 0), rows.iter().any(|t| t.1 != 0)))
+}
+
+#[instrument(err,skip_all)]
+pub async fn update_status(
+    pool: &MySqlPool,
+    id: i32,
+    status: i8,
+    reason: Option<&str>,
+    until: Option<NaiveDateTime>
+) -> DBResult<bool>{
+    Ok(sqlx::query("UPDATE users SET status = ?, status_reason = ?, status_until = ? WHERE id = ?")
+        .bind(status)
+        .bind(reason)
+        .bind(until)
+        .bind(id)
+        .execute(pool)
+        .await?.rows_affected() == 1)
+}
+
+#[instrument(err,skip_all)]
+pub async fn require_password_reset(
+    pool: &MySqlPool,
+    id: i32
+) -> DBResult<bool>{
+    Ok(sqlx::query("UPDATE users SET password_reset_required = TRUE WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?.rows_affected() == 1)
 }
 
 #[instrument(err,skip_all)]
-pub async fn get_list(pool: &MySqlPool, limit: i32, offset: i32) -> DBResult<Vec<User>> {
-    let res: Vec<User> = sqlx::query_as("SELECT id,email,name,avatar_time,roles FROM users LIMIT ?,?")
-    .bind(offset)
-    .bind(limit)
-    .fetch_all(pool)
-    .await?;
-    Ok(res)
-}
\ No newline at end of file
+pub async fn select_restriction(
+    pool: &MySqlPool,
+    id: i32
+) -> DBResult<Option<UserRestriction>>{
+    sqlx::query_as::<_,UserRestriction>(concat!("SELECT ", restriction_columns!(), " FROM users WHERE id = ? LIMIT 1"))
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+}
+
+#[instrument(err,skip_all)]
+pub async fn select_restrictions(pool: &MySqlPool) -> DBResult<Vec<UserRestriction>>{
+    sqlx::query_as::<_,UserRestriction>(concat!("SELECT ", restriction_columns!(), " FROM users WHERE status != 0 OR password_reset_required"))
+        .fetch_all(pool)
+        .await
+}
+#[instrument(err,skip_all)]
+pub async fn list(
+    pool: &MySqlPool,
+    limit: i32,
+    offset: i32,
+    // part of the name or email
+    keyword: Option<&str>,
+    role: Option<i32>,
+    filter: Vec<UserFilterable>,
+    sort: Vec<UserSortable>,
+) -> DBResult<(i32, Vec<User>)>{
+    let mut conditions = filter.iter().map(|f|
+        format!("{} = ?", f.get_field_name())
+    ).collect::<Vec<String>>();
+    if keyword.is_some(){
+        conditions.push("(name LIKE ? OR email LIKE ?)".to_string());
+    }
+    if role.is_some(){
+        conditions.push("id IN (SELECT user FROM user_roles WHERE role = ?)".to_string());
+    }
+    let mut where_query = conditions.join(" AND ");
+    if !where_query.is_empty(){
+        where_query.insert_str(0, " WHERE ");
+    }
+    let order_query = sort.iter().map(|f| f.to_sql()).collect::<Vec<String>>().join(",");
+    let mut basic_query = format!("SELECT id FROM users{where_query}");
+    if !order_query.is_empty(){
+        basic_query.push_str(" ORDER BY ");
+        basic_query.push_str(&order_query);
+    }
+    let mut final_query = format!(concat!("SELECT id,name,email,avatar_time,status,", roles_column!(), " FROM users JOIN ({} LIMIT {},{})t USING(id)"), basic_query, offset, limit);
+    // the join does not keep the order of the page
+    if !order_query.is_empty(){
+        final_query.push_str(" ORDER BY ");
+        final_query.push_str(&order_query);
+    }
+    let count_query = format!("SELECT count(id) FROM users{where_query}");
+    let pattern = keyword.map(|k| format!("%{}%", k.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")));
+    let mut instance = sqlx::query_as::<_,User>(&final_query);
+    let mut count_instance = sqlx::query_as::<_,(i64,)>(&count_query);
+    for f in filter{
+        count_instance = f.clone().bind_value(count_instance);
+        instance = f.bind_value(instance);
+    }
+    if let Some(p) = &pattern{
+        instance = instance.bind(p).bind(p);
+        count_instance = count_instance.bind(p).bind(p);
+    }
+    if let Some(r) = role{
+        instance = instance.bind(r);
+        count_instance = count_instance.bind(r);
+    }
+    let total = count_instance.fetch_one(pool).await?.0;
+    Ok((total as i32, instance.fetch_all(pool).await?))
+}
+
+// rows about the user that mean nothing without them
+// quotas and avatar usage are columns of users and go with the row
+const PERSONAL_TABLES: [&str; 7] = ["article_acl", "user_roles", "user_profiles", "notifications", "notification_preferences", "verifications", "data_exports"];
+
+// with reassign_to the articles go to that user, otherwise they are removed with their contents and comments;
+// the comments of the user are removed either way;
+// files stay where they are, the caller removes the ones nothing refers to any more
+#[instrument(err,skip_all)]
+pub async fn delete(
+    pool: &MySqlPool,
+    id: i32,
+    reassign_to: Option<i32>
+) -> DBResult<bool>{
+    let mut tx = pool.begin().await?;
+    match reassign_to {
+        Some(to) => {
+            tx.execute(sqlx::query("UPDATE articles SET author = ? WHERE author = ?")
+                .bind(to)
+                .bind(id)).await?;
+            // the new author needs no grant on their own articles
+            tx.execute(sqlx::query("DELETE FROM article_acl WHERE user = ? AND article IN (SELECT id FROM articles WHERE author = ?)")
+                .bind(to)
+                .bind(to)).await?;
+        },
+        None => {
+            tx.execute(sqlx::query("UPDATE files JOIN (SELECT file, count(*) AS refs FROM file_refs WHERE article IN (SELECT id FROM articles WHERE author = ?) GROUP BY file)t ON files.id = t.file SET files.ref_count = files.ref_count - t.refs")
+                .bind(id)).await?;
+            tx.execute(sqlx::query("DELETE FROM file_refs WHERE article IN (SELECT id FROM articles WHERE author = ?)")
+                .bind(id)).await?;
+            tx.execute(sqlx::query("DELETE FROM article_acl WHERE article IN (SELECT id FROM articles WHERE author = ?)")
+                .bind(id)).await?;
+            tx.execute(sqlx::query("DELETE contents FROM contents JOIN articles ON contents.id IN (articles.content_id, articles.draft_content_id, articles.summary_content_id) WHERE articles.author = ?")
+                .bind(id)).await?;
+            tx.execute(sqlx::query("DELETE FROM article_comments WHERE article IN (SELECT id FROM articles WHERE author = ?)")
+                .bind(id)).await?;
+            tx.execute(sqlx::query("DELETE FROM articles WHERE author = ?")
+                .bind(id)).await?;
+        }
+    }
+    // replies to them stay, pointing at a parent that is gone
+    tx.execute(sqlx::query("UPDATE articles JOIN (SELECT article, count(*) AS n FROM article_comments WHERE author = ? GROUP BY article)t ON articles.id = t.article SET articles.comments = articles.comments - t.n")
+        .bind(id)).await?;
+    tx.execute(sqlx::query("DELETE FROM article_comments WHERE author = ?")
+        .bind(id)).await?;
+    for table in PERSONAL_TABLES {
+        tx.execute(sqlx::query(&format!("DELETE FROM {table} WHERE user = ?"))
+            .bind(id)).await?;
+    }
+    let deleted = tx.execute(sqlx::query("DELETE FROM users WHERE id = ?")
+        .bind(id)).await?.rows_affected() == 1;
+    tx.commit().await?;
+    Ok(deleted)
+}
+
+// None cancels a scheduled deletion
+#[instrument(err,skip_all)]
+pub async fn schedule_deletion(
+    pool: &MySqlPool,
+    id: i32,
+    delete_after: Option<DateTime<Utc>>
+) -> DBResult<bool>{
+    Ok(sqlx::query("UPDATE users SET delete_after = ? WHERE id = ?")
+        .bind(delete_after)
+        .bind(id)
+        .execute(pool)
+        .await?.rows_affected() == 1)
+}
+
+#[instrument(err,skip_all)]
+pub async fn select_delete_after(
+    pool: &MySqlPool,
+    id: i32
+) -> DBResult<Option<DateTime<Utc>>>{
+    Ok(sqlx::query_as::<_,(Option<DateTime<Utc>>,)>("SELECT delete_after FROM users WHERE id = ? LIMIT 1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?
+        .and_then(|t| t.0))
+}
+
+#[instrument(err,skip_all)]
+pub async fn select_due_deletions(pool: &MySqlPool, now: DateTime<Utc>) -> DBResult<Vec<i32>>{
+    Ok(sqlx::query_as::<_,(i32,)>("SELECT id FROM users WHERE delete_after <= ?")
+        .bind(now)
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|t| t.0)
+        .collect())
+}
+
+// keeps the row and the articles pointing at it, but nothing that tells who the user was
+#[instrument(err,skip_all)]
+pub async fn anonymize(
+    pool: &MySqlPool,
+    id: i32
+) -> DBResult<bool>{
+    let mut tx = pool.begin().await?;
+    let Some((email,)) = sqlx::query_as::<_,(String,)>("SELECT email FROM users WHERE id = ? FOR UPDATE")
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await? else {
+        return Ok(false);
+    };
+    // a newsletter subscription of the address would keep it around
+    tx.execute(sqlx::query("DELETE newsletter_deliveries FROM newsletter_deliveries JOIN subscribers ON subscribers.id = newsletter_deliveries.subscriber WHERE subscribers.email = ?")
+        .bind(&email)).await?;
+    tx.execute(sqlx::query("DELETE FROM subscribers WHERE email = ?")
+        .bind(&email)).await?;
+    // the one exception to the log being append only: the entries stay, where they came from goes
+    tx.execute(sqlx::query("UPDATE audit_logs SET ip = NULL, user_agent = NULL WHERE actor = ?")
+        .bind(id)).await?;
+    let updated = tx.execute(sqlx::query("UPDATE users SET name = CONCAT('deleted-user-', id), email = CONCAT('deleted-', id, '@invalid'), password = '', status = ?, status_reason = 'deleted', status_until = NULL, password_reset_required = FALSE, quota_bytes = NULL, quota_files = NULL, avatar_bytes = 0, delete_after = NULL WHERE id = ?")
+        .bind(STATUS_DISABLED)
+        .bind(id)).await?.rows_affected() == 1;
+    for table in PERSONAL_TABLES {
+        tx.execute(sqlx::query(&format!("DELETE FROM {table} WHERE user = ?"))
+            .bind(id)).await?;
+    }
+    tx.commit().await?;
+    Ok(updated)
+}
@@ -1,8 +1,22 @@
 use serde::Serialize;
 use sqlx::{MySqlPool, types::chrono};
+use sqlx::types::chrono::Utc;
 use tracing::instrument;
 use super::DBResult;
 use sqlx::FromRow;
+// what following the link or entering the code does
+// identity is the requested new address, the link is sent there
+pub const ACTION_CHANGE_EMAIL: i16 = 0;
+// identity is the subscribed address, there is no user
+pub const ACTION_SUBSCRIBE: i16 = 1;
+// confirms a self-deletion, which then waits out the grace period
+pub const ACTION_DELETE_ACCOUNT: i16 = 2;
+// sent to the old address along with a change, identity is the requested new address
+pub const ACTION_CANCEL_EMAIL_CHANGE: i16 = 3;
+// what the cancel link turns into once the change is confirmed, identity is the old address to go back to
+pub const ACTION_REVERT_EMAIL_CHANGE: i16 = 4;
+// the code mailed for a forgotten password, identity is the address of the account as it was then
+pub const ACTION_RESET_PASSWORD: i16 = 5;
 #[derive(Serialize,Debug,FromRow)]
 pub struct Verification{
     pub id: i32,
@@ -20,11 +34,12 @@ pub async fn create(
     random_code: &str,
     action: i16,
 ) -> DBResult<i32> {
-    Ok(sqlx::query("INSERT INTO verifications (user,identity,random_code,action,created_at) VALUES (?,?,?,?,NOW())")
+    Ok(sqlx::query("INSERT INTO verifications (user,identity,random_code,action,created_at) VALUES (?,?,?,?,?)")
         .bind(user)
         .bind(identity)
         .bind(random_code)
         .bind(action)
+        .bind(Utc::now().naive_utc())
         .execute(pool)
         .await?.last_insert_id() as i32)
 }
@@ -49,4 +64,60 @@ pub async fn delete_by_id(
         .execute(pool)
         .await?;
     Ok(())
-}
\ No newline at end of file
+}
+#[instrument(err,skip_all)]
+pub async fn delete_by_user(
+    pool: &MySqlPool,
+    user: i32,
+    action: i16
+) -> DBResult<()>{
+    sqlx::query("DELETE FROM verifications WHERE user = ? AND action = ?")
+        .bind(user)
+        .bind(action)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+// keeps the id, so the link already mailed to the old address goes on working
+#[instrument(err,skip_all)]
+pub async fn turn_into_revert(
+    pool: &MySqlPool,
+    user: i32,
+    old_email: &str
+) -> DBResult<()>{
+    sqlx::query("UPDATE verifications SET action = ?, identity = ? WHERE user = ? AND action = ?")
+        .bind(ACTION_REVERT_EMAIL_CHANGE)
+        .bind(old_email)
+        .bind(user)
+        .bind(ACTION_CANCEL_EMAIL_CHANGE)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+#[instrument(err,skip_all)]
+pub async fn delete_created_before(
+    pool: &MySqlPool,
+    action: i16,
+    before: chrono::NaiveDateTime
+) -> DBResult<u64>{
+    Ok(sqlx::query("DELETE FROM verifications WHERE action = ? AND created_at < ?")
+        .bind(action)
+        .bind(before)
+        .execute(pool)
+        .await?.rows_affected())
+}
+// how many were created for the identity lately, to hold back repeated mails to one address
+#[instrument(err,skip_all)]
+pub async fn count_since(
+    pool: &MySqlPool,
+    identity: &str,
+    action: i16,
+    since: chrono::NaiveDateTime
+) -> DBResult<i64>{
+    Ok(sqlx::query_as::<_,(i64,)>("SELECT count(id) FROM verifications WHERE identity = ? AND action = ? AND created_at > ?")
+        .bind(identity)
+        .bind(action)
+        .bind(since)
+        .fetch_one(pool)
+        .await?.0)
+}
@@ -0,0 +1,202 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, MySqlPool};
+use tracing::instrument;
+use super::DBResult;
+
+#[derive(Serialize,Debug,FromRow,Clone)]
+pub struct Webhook{
+    pub id: i32,
+    pub url: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub events: String,
+    pub enabled: bool,
+    pub created_by: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize,Debug,Clone,Copy,PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryState{
+    Pending,
+    Delivered,
+    Failed
+}
+impl DeliveryState{
+    fn to_i8(self) -> i8{
+        match self{
+            DeliveryState::Pending => 0,
+            DeliveryState::Delivered => 1,
+            DeliveryState::Failed => 2
+        }
+    }
+}
+impl TryFrom<i8> for DeliveryState{
+    type Error = String;
+    fn try_from(value: i8) -> Result<Self, Self::Error> {
+        match value{
+            0 => Ok(DeliveryState::Pending),
+            1 => Ok(DeliveryState::Delivered),
+            2 => Ok(DeliveryState::Failed),
+            v => Err(format!("unknown delivery state {v}"))
+        }
+    }
+}
+
+#[derive(Serialize,Debug,FromRow)]
+pub struct WebhookDelivery{
+    pub id: i32,
+    pub webhook: i32,
+    pub event: String,
+    pub payload: String,
+    #[sqlx(try_from = "i8")]
+    pub state: DeliveryState,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub response_code: Option<i16>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+pub struct DeliveryAttempt<'a>{
+    pub state: DeliveryState,
+    pub next_attempt_at: DateTime<Utc>,
+    pub response_code: Option<i16>,
+    pub error: Option<&'a str>,
+}
+
+#[instrument(err,skip_all)]
+pub async fn create(pool: &MySqlPool, url: &str, secret: &str, events: &str, created_by: i32) -> DBResult<i32>{
+    Ok(sqlx::query("INSERT INTO webhooks (url,secret,events,enabled,created_by,created_at) VALUES (?,?,?,TRUE,?,?)")
+        .bind(url)
+        .bind(secret)
+        .bind(events)
+        .bind(created_by)
+        .bind(Utc::now())
+        .execute(pool)
+        .await?.last_insert_id() as i32)
+}
+#[instrument(err,skip_all)]
+pub async fn select_by_id(pool: &MySqlPool, id: i32) -> DBResult<Option<Webhook>>{
+    sqlx::query_as::<_,Webhook>("SELECT * FROM webhooks WHERE id = ? LIMIT 1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+}
+#[instrument(err,skip_all)]
+pub async fn select_enabled_by_event(pool: &MySqlPool, event: &str) -> DBResult<Vec<Webhook>>{
+    sqlx::query_as::<_,Webhook>("SELECT * FROM webhooks WHERE enabled = TRUE AND FIND_IN_SET(?, events) > 0")
+        .bind(event)
+        .fetch_all(pool)
+        .await
+}
+#[instrument(err,skip_all)]
+pub async fn list(pool: &MySqlPool, limit: i32, offset: i32) -> DBResult<(i32,Vec<Webhook>)>{
+    let total = sqlx::query_as::<_,(i64,)>("SELECT count(id) FROM webhooks")
+        .fetch_one(pool)
+        .await?.0;
+    let webhooks = sqlx::query_as::<_,Webhook>("SELECT * FROM webhooks ORDER BY id LIMIT ?,?")
+        .bind(offset)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+    Ok((total as i32, webhooks))
+}
+#[instrument(err,skip_all)]
+pub async fn update(pool: &MySqlPool, id: i32, url: &str, events: &str, enabled: bool) -> DBResult<bool>{
+    Ok(sqlx::query("UPDATE webhooks SET url = ?, events = ?, enabled = ? WHERE id = ?")
+        .bind(url)
+        .bind(events)
+        .bind(enabled)
+        .bind(id)
+        .execute(pool)
+        .await?.rows_affected() == 1)
+}
+// the delivery history goes with the webhook
+#[instrument(err,skip_all)]
+pub async fn delete(pool: &MySqlPool, id: i32) -> DBResult<bool>{
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM webhook_deliveries WHERE webhook = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    let deleted = sqlx::query("DELETE FROM webhooks WHERE id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?.rows_affected() == 1;
+    tx.commit().await?;
+    Ok(deleted)
+}
+
+#[instrument(err,skip_all)]
+pub async fn create_delivery(pool: &MySqlPool, webhook: i32, event: &str, payload: &str) -> DBResult<i32>{
+    let now = Utc::now();
+    Ok(sqlx::query("INSERT INTO webhook_deliveries (webhook,event,payload,state,attempts,next_attempt_at,created_at) VALUES (?,?,?,?,0,?,?)")
+        .bind(webhook)
+        .bind(event)
+        .bind(payload)
+        .bind(DeliveryState::Pending.to_i8())
+        .bind(now)
+        .bind(now)
+        .execute(pool)
+        .await?.last_insert_id() as i32)
+}
+#[instrument(err,skip_all)]
+pub async fn select_delivery(pool: &MySqlPool, id: i32) -> DBResult<Option<WebhookDelivery>>{
+    sqlx::query_as::<_,WebhookDelivery>("SELECT * FROM webhook_deliveries WHERE id = ? LIMIT 1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+}
+// due deliveries are pushed back by the lease while they are worked on, so another instance
+// skips them, and become due again if the one holding them goes away
+#[instrument(err,skip_all)]
+pub async fn claim_due_deliveries(pool: &MySqlPool, limit: i32, lease: chrono::Duration) -> DBResult<Vec<WebhookDelivery>>{
+    let mut tx = pool.begin().await?;
+    let deliveries = sqlx::query_as::<_,WebhookDelivery>("SELECT * FROM webhook_deliveries WHERE state = ? AND next_attempt_at <= ? ORDER BY next_attempt_at LIMIT ? FOR UPDATE SKIP LOCKED")
+        .bind(DeliveryState::Pending.to_i8())
+        .bind(Utc::now())
+        .bind(limit)
+        .fetch_all(&mut *tx)
+        .await?;
+    if !deliveries.is_empty(){
+        let placeholders = vec!["?"; deliveries.len()].join(",");
+        let sql = format!("UPDATE webhook_deliveries SET next_attempt_at = ? WHERE id IN ({placeholders})");
+        let mut query = sqlx::query(&sql).bind(Utc::now() + lease);
+        for delivery in &deliveries{
+            query = query.bind(delivery.id);
+        }
+        query.execute(&mut *tx).await?;
+    }
+    tx.commit().await?;
+    Ok(deliveries)
+}
+#[instrument(err,skip_all)]
+pub async fn list_deliveries(pool: &MySqlPool, webhook: i32, limit: i32, offset: i32) -> DBResult<(i32,Vec<WebhookDelivery>)>{
+    let total = sqlx::query_as::<_,(i64,)>("SELECT count(id) FROM webhook_deliveries WHERE webhook = ?")
+        .bind(webhook)
+        .fetch_one(pool)
+        .await?.0;
+    let deliveries = sqlx::query_as::<_,WebhookDelivery>("SELECT * FROM webhook_deliveries WHERE webhook = ? ORDER BY id DESC LIMIT ?,?")
+        .bind(webhook)
+        .bind(offset)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+    Ok((total as i32, deliveries))
+}
+#[instrument(err,skip_all)]
+pub async fn record_attempt(pool: &MySqlPool, id: i32, attempt: &DeliveryAttempt<'_>) -> DBResult<()>{
+    let delivered_at = (attempt.state == DeliveryState::Delivered).then(Utc::now);
+    sqlx::query("UPDATE webhook_deliveries SET state = ?, attempts = attempts + 1, next_attempt_at = ?, response_code = ?, error = ?, delivered_at = ? WHERE id = ?")
+        .bind(attempt.state.to_i8())
+        .bind(attempt.next_attempt_at)
+        .bind(attempt.response_code)
+        .bind(attempt.error)
+        .bind(delivered_at)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
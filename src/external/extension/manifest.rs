@@ -0,0 +1,66 @@
+use std::path::{Component, Path};
+
+use serde::Deserialize;
+use tracing::error;
+
+pub const MANIFEST_FILE: &str = "manifest.toml";
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability{
+    // register storage providers usable by fs policies
+    Fs,
+    // rewrite article content before it is saved
+    Filter,
+    // inspect incoming requests and answer them early
+    Http,
+    // filter and act on domain events
+    Events
+}
+impl Capability{
+    pub fn as_str(&self) -> &'static str{
+        match self{
+            Capability::Fs => "fs",
+            Capability::Filter => "filter",
+            Capability::Http => "http",
+            Capability::Events => "events"
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Manifest{
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default = "default_entry")]
+    pub entry: String,
+    #[serde(default)]
+    pub capabilities: Vec<Capability>
+}
+fn default_entry() -> String{
+    String::from("main.lua")
+}
+
+impl Manifest{
+    pub fn load(dir: &Path) -> Result<Self, ()>{
+        let path = dir.join(MANIFEST_FILE);
+        let content = std::fs::read_to_string(&path).map_err(|e| {
+            error!("cannot read {}: {:?}", path.to_string_lossy(), e);
+        })?;
+        let manifest: Manifest = toml::from_str(&content).map_err(|e| {
+            error!("corrupted manifest {}: {}", path.to_string_lossy(), e);
+        })?;
+        if manifest.name.is_empty(){
+            error!("manifest {} has an empty name", path.to_string_lossy());
+            return Err(());
+        }
+        // the entry must stay inside the extension directory
+        if !Path::new(&manifest.entry).components().all(|c| matches!(c, Component::Normal(_))){
+            error!("entry of extension {} must be a relative path inside its directory", manifest.name);
+            return Err(());
+        }
+        Ok(manifest)
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use mlua::{Function, LuaSerdeExt, Table, Value};
+use once_cell::sync::OnceCell;
+use rustle_derive::ErrorHelper;
+use serde::{Deserialize, Serialize};
+use serde_inline_default::serde_inline_default;
+use tracing::{error, info};
+
+use crate::external::fs::local::LocalFs;
+use crate::get_config;
+use crate::internal::event::{self, Event, EventKind, HookError};
+use crate::types::err::EmptyErrResult;
+use crate::types::service::AppService;
+use self::runtime::Extension;
+
+pub mod manifest;
+pub mod runtime;
+
+// in load order, which is the order of the directory names
+static EXTENSIONS: OnceCell<Vec<Arc<Extension>>> = OnceCell::new();
+pub struct ExtensionService;
+impl AppService for ExtensionService {
+    fn name() -> &'static str {
+        "ExtensionService"
+    }
+    async fn initialize() -> EmptyErrResult<()> {
+        let config = get_config!(extension).clone();
+        if !config.enabled{
+            return EXTENSIONS.set(Vec::new()).map_err(|_| error!("extensions are already loaded"));
+        }
+        let mut dirs = match std::fs::read_dir(&config.path){
+            Ok(entries) => entries.filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.is_dir())
+                .collect::<Vec<_>>(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                info!("extension directory {} not found, no extension is loaded", config.path);
+                Vec::new()
+            },
+            Err(e) => {
+                error!("cannot read extension directory {}: {:?}", config.path, e);
+                return Err(());
+            }
+        };
+        dirs.sort();
+        let mut extensions: Vec<Arc<Extension>> = Vec::new();
+        for dir in dirs{
+            // a broken extension is skipped, it should not keep the blog down
+            let Ok(extension) = Extension::load(&dir, &config) else {
+                error!("extension in {} is not loaded", dir.to_string_lossy());
+                continue;
+            };
+            if extensions.iter().any(|e| e.manifest.name == extension.manifest.name){
+                error!("extension {} is loaded twice, the one in {} is skipped", extension.manifest.name, dir.to_string_lossy());
+                continue;
+            }
+            if let Some(p) = extension.fs_providers.iter().find(|p| {
+                p.as_str() == LocalFs::EXTENSION_NAME || extensions.iter().any(|e| e.fs_providers.contains(p))
+            }){
+                error!("fs provider {p} of extension {} is already registered, the extension is skipped", extension.manifest.name);
+                continue;
+            }
+            info!("loaded extension {} {}", extension.manifest.name, extension.manifest.version);
+            let extension = Arc::new(extension);
+            subscribe_events(&extension);
+            extensions.push(extension);
+        }
+        EXTENSIONS.set(extensions).map_err(|_| error!("extensions are already loaded"))
+    }
+}
+
+fn loaded() -> &'static [Arc<Extension>]{
+    EXTENSIONS.get().map(Vec::as_slice).unwrap_or_default()
+}
+
+pub fn find_fs_provider(name: &str) -> Option<Arc<Extension>>{
+    loaded().iter().find(|e| e.fs_providers.iter().any(|p| p == name)).cloned()
+}
+
+// filters run in load order, each gets the output of the previous one
+// a failing extension is skipped so a broken filter can not block publishing
+pub async fn apply_content_filters(kind: &'static str, mut content: String) -> String{
+    for extension in loaded().iter().filter(|e| e.has_filters){
+        let input = content.clone();
+        if let Ok(filtered) = extension.call(move |lua, r| {
+            let mut content = input;
+            for key in &r.filters{
+                let filter: Function = lua.registry_value(key)?;
+                if let Some(s) = filter.call::<_, Option<String>>((content.as_str(), kind))?{
+                    content = s;
+                }
+            }
+            Ok(content)
+        }).await{
+            content = filtered;
+        }
+    }
+    content
+}
+
+fn subscribe_events(extension: &Arc<Extension>){
+    for kind in &extension.filtered_events{
+        let (extension, kind) = (extension.clone(), *kind);
+        event::subscribe_filter(kind, Arc::new(move |e| Box::pin(filter_event(extension.clone(), kind, e))));
+    }
+    for kind in &extension.acted_events{
+        let (extension, kind) = (extension.clone(), *kind);
+        event::subscribe_action(kind, Arc::new(move |e| Box::pin(act_on_event(extension.clone(), kind, e))));
+    }
+}
+
+// a filter returns nil to keep the data, a table to replace it, or false and a reason to veto
+async fn filter_event(extension: Arc<Extension>, kind: EventKind, event: Event) -> Result<Event, HookError>{
+    let name = extension.manifest.name.clone();
+    extension.call(move |lua, r| {
+        let envelope: Table = lua.unpack(lua.to_value(&event)?)?;
+        for (_, key) in r.event_filters.iter().filter(|(k, _)| *k == kind){
+            let hook: Function = lua.registry_value(key)?;
+            let (res, reason): (Value, Option<String>) = hook.call(envelope.get::<_, Value>("data")?)?;
+            match res{
+                Value::Nil => {},
+                Value::Boolean(false) => return Ok(Err(HookError::Veto(
+                    reason.unwrap_or_else(|| format!("rejected by extension {name}"))
+                ))),
+                data => envelope.set("data", data)?
+            }
+        }
+        lua.from_value::<Event>(Value::Table(envelope)).map(Ok)
+    }).await.map_err(|e| HookError::Failed(e.to_string()))?
+}
+
+async fn act_on_event(extension: Arc<Extension>, kind: EventKind, event: Arc<Event>) -> Result<(), String>{
+    extension.call(move |lua, r| {
+        let envelope: Table = lua.unpack(lua.to_value(event.as_ref())?)?;
+        let data: Value = envelope.get("data")?;
+        for (_, key) in r.event_actions.iter().filter(|(k, _)| *k == kind){
+            lua.registry_value::<Function>(key)?.call::<_, ()>(data.clone())?;
+        }
+        Ok(())
+    }).await.map_err(|e| e.to_string())
+}
+
+#[derive(Serialize, Debug)]
+pub struct HookRequest{
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    pub headers: HashMap<String, String>
+}
+#[serde_inline_default]
+#[derive(Deserialize, Debug)]
+pub struct HookResponse{
+    #[serde_inline_default(200)]
+    pub status: u16,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub body: String
+}
+
+pub fn has_http_hooks() -> bool{
+    loaded().iter().any(|e| e.has_http_hooks)
+}
+
+// the first hook returning a response answers the request, nil lets it through
+pub async fn run_http_hooks(request: Arc<HookRequest>) -> Option<HookResponse>{
+    for extension in loaded().iter().filter(|e| e.has_http_hooks){
+        let request = request.clone();
+        if let Ok(Some(response)) = extension.call(move |lua, r| {
+            let value = lua.to_value(request.as_ref())?;
+            for key in &r.http_hooks{
+                let hook: Function = lua.registry_value(key)?;
+                let res: Value = hook.call(value.clone())?;
+                if !res.is_nil(){
+                    return lua.from_value(res).map(Some);
+                }
+            }
+            Ok(None)
+        }).await{
+            return Some(response);
+        }
+    }
+    None
+}
+
+#[derive(ErrorHelper)]
+#[err(internal)]
+pub enum ExtensionInternalError{
+    #[err(msg = "error.extension.call")]
+    Call,
+    #[err(msg = "error.extension.task")]
+    Task,
+    #[err(msg = "error.extension.stalled")]
+    Stalled
+}
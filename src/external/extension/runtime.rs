@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use mlua::{ChunkMode, Function, HookTriggers, Lua, LuaOptions, MultiValue, RegistryKey, StdLib, Table, Value, Variadic};
+use tracing::{debug, error, info, warn};
+
+use crate::internal::event::EventKind;
+use crate::types::config::ExtensionConfig;
+use crate::types::err::AppResult;
+use super::manifest::{Capability, Manifest};
+use super::ExtensionInternalError;
+
+// the cpu budget is checked every this many vm instructions
+const INSTRUCTION_CHECK_INTERVAL: u32 = 1000;
+// base library functions able to load bytecode or touch the host, coroutines are left out
+// entirely because the instruction hook does not follow them
+const UNSAFE_GLOBALS: [&str; 5] = ["dofile", "loadfile", "load", "require", "collectgarbage"];
+const CPU_LIMIT_EXCEEDED: &str = "cpu time limit exceeded";
+// the hook cannot interrupt a c function such as string.find, a call still running this
+// long past its deadline is given up on by the caller
+const STALL_GRACE: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+pub struct Registrations{
+    pub fs: HashMap<String, RegistryKey>,
+    pub filters: Vec<RegistryKey>,
+    pub http_hooks: Vec<RegistryKey>,
+    pub event_filters: Vec<(EventKind, RegistryKey)>,
+    pub event_actions: Vec<(EventKind, RegistryKey)>
+}
+// lives in the app data only while the entry script runs, so registering afterwards fails
+struct Loading{
+    manifest_name: String,
+    capabilities: Vec<Capability>,
+    registrations: Registrations
+}
+// the budget of the running call, shared by the instruction hook and the waiting caller
+struct Clock{
+    budget: Duration,
+    deadline: Mutex<Option<Instant>>
+}
+impl Clock{
+    fn set(&self, deadline: Option<Instant>){
+        *self.deadline.lock().unwrap_or_else(PoisonError::into_inner) = deadline;
+    }
+    fn get(&self) -> Option<Instant>{
+        *self.deadline.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    fn renew(&self){
+        self.set(Some(Instant::now() + self.budget));
+    }
+    fn exceeded(&self) -> bool{
+        self.get().map_or(false, |d| Instant::now() > d)
+    }
+    // no call running yet counts as a whole budget left
+    fn remaining(&self) -> Duration{
+        self.get().map_or(self.budget, |d| d.saturating_duration_since(Instant::now()))
+    }
+    fn stalled(&self) -> bool{
+        self.get().map_or(false, |d| Instant::now() > d + STALL_GRACE)
+    }
+}
+struct ExtensionState{
+    lua: Lua,
+    registrations: Registrations
+}
+pub struct Extension{
+    pub manifest: Manifest,
+    pub fs_providers: Vec<String>,
+    pub has_filters: bool,
+    pub has_http_hooks: bool,
+    pub filtered_events: Vec<EventKind>,
+    pub acted_events: Vec<EventKind>,
+    clock: Arc<Clock>,
+    // set while a call overran its deadline inside a c function and still holds the state
+    stalled: AtomicBool,
+    state: Mutex<ExtensionState>
+}
+
+impl Extension{
+    pub fn load(dir: &Path, config: &ExtensionConfig) -> Result<Self, ()>{
+        let manifest = Manifest::load(dir)?;
+        let entry = dir.join(&manifest.entry);
+        let source = std::fs::read(&entry).map_err(|e| {
+            error!("cannot read {}: {:?}", entry.to_string_lossy(), e);
+        })?;
+        let lua = Lua::new_with(StdLib::TABLE | StdLib::STRING | StdLib::MATH | StdLib::UTF8, LuaOptions::default())
+            .map_err(|e| error!("cannot create lua state for {}: {}", manifest.name, e))?;
+        let clock = Arc::new(Clock{
+            budget: Duration::from_millis(config.max_call_millis),
+            deadline: Mutex::new(None)
+        });
+        let registrations = (|| {
+            sandbox(&lua, &manifest.name)?;
+            lua.set_hook(HookTriggers::new().every_nth_instruction(INSTRUCTION_CHECK_INTERVAL), |lua, _| {
+                if budget_exceeded(lua){
+                    return Err(mlua::Error::runtime(CPU_LIMIT_EXCEEDED));
+                }
+                Ok(())
+            });
+            lua.set_memory_limit(config.max_memory)?;
+            lua.set_app_data(clock.clone());
+            lua.set_app_data(Loading{
+                manifest_name: manifest.name.clone(),
+                capabilities: manifest.capabilities.clone(),
+                registrations: Registrations::default()
+            });
+            let res = guarded(&clock, || {
+                lua.load(source.as_slice())
+                    .set_name(manifest.entry.as_str())
+                    .set_mode(ChunkMode::Text)
+                    .exec()
+            });
+            let loading = lua.remove_app_data::<Loading>()
+                .ok_or_else(|| mlua::Error::runtime("registrations lost while loading"))?;
+            res.map(|_| loading.registrations)
+        })().map_err(|e| error!("failed to load extension {}: {}", manifest.name, e))?;
+        Ok(Self{
+            fs_providers: registrations.fs.keys().cloned().collect(),
+            has_filters: !registrations.filters.is_empty(),
+            has_http_hooks: !registrations.http_hooks.is_empty(),
+            filtered_events: distinct_kinds(&registrations.event_filters),
+            acted_events: distinct_kinds(&registrations.event_actions),
+            manifest,
+            clock,
+            stalled: AtomicBool::new(false),
+            state: Mutex::new(ExtensionState{ lua, registrations })
+        })
+    }
+
+    // lua is blocking and may run up to the cpu budget, keep it away from the io threads
+    pub async fn call<R, F>(self: &Arc<Self>, f: F) -> AppResult<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&Lua, &Registrations) -> mlua::Result<R> + Send + 'static
+    {
+        if self.stalled.load(Ordering::Acquire){
+            return Err(ExtensionInternalError::Stalled.into());
+        }
+        let extension = self.clone();
+        let mut task = tokio::task::spawn_blocking(move || extension.call_blocking(f));
+        // the deadline moves when a call renews its budget, so it is looked at again on every wake up
+        loop {
+            match tokio::time::timeout(self.clock.remaining() + STALL_GRACE, &mut task).await{
+                Ok(res) => return res.map_err(|e| {
+                    error!("extension task failed: {:?}", e);
+                    ExtensionInternalError::Task
+                })?,
+                Err(_) if self.clock.stalled() => {
+                    self.stalled.store(true, Ordering::Release);
+                    error!("extension {} is stuck past its deadline, it is not called until it returns", self.manifest.name);
+                    return Err(ExtensionInternalError::Stalled.into());
+                },
+                Err(_) => continue
+            }
+        }
+    }
+    fn call_blocking<R>(&self, f: impl FnOnce(&Lua, &Registrations) -> mlua::Result<R>) -> AppResult<R>{
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let res = guarded(&self.clock, || f(&state.lua, &state.registrations));
+        self.stalled.store(false, Ordering::Release);
+        res.map_err(|e| {
+            error!("extension {} failed: {}", self.manifest.name, e);
+            ExtensionInternalError::Call.into()
+        })
+    }
+}
+
+fn distinct_kinds(hooks: &[(EventKind, RegistryKey)]) -> Vec<EventKind>{
+    let mut kinds: Vec<EventKind> = Vec::new();
+    for (kind, _) in hooks{
+        if !kinds.contains(kind){
+            kinds.push(*kind);
+        }
+    }
+    kinds
+}
+
+fn guarded<R>(clock: &Clock, f: impl FnOnce() -> mlua::Result<R>) -> mlua::Result<R>{
+    clock.renew();
+    let res = f();
+    clock.set(None);
+    res
+}
+fn budget_exceeded(lua: &Lua) -> bool{
+    lua.app_data_ref::<Arc<Clock>>().map_or(false, |c| c.exceeded())
+}
+// for waiting on the host within a call, e.g. for the next piece of an upload, which is
+// neither held against the budget nor taken for a stuck call
+pub fn outside_budget<R>(lua: &Lua, f: impl FnOnce() -> R) -> R{
+    let Some(clock) = lua.app_data_ref::<Arc<Clock>>().map(|c| c.clone()) else {
+        return f();
+    };
+    clock.set(None);
+    let res = f();
+    clock.renew();
+    res
+}
+
+fn sandbox(lua: &Lua, name: &str) -> mlua::Result<()>{
+    let globals = lua.globals();
+    for global in UNSAFE_GLOBALS{
+        globals.raw_set(global, Value::Nil)?;
+    }
+    // a script catching the budget error could loop forever, so it is raised again past the catch
+    for global in ["pcall", "xpcall"]{
+        let original = lua.create_registry_value(globals.get::<_, Function>(global)?)?;
+        globals.raw_set(global, lua.create_function(move |lua, args: MultiValue| {
+            let res = lua.registry_value::<Function>(&original)?.call::<_, MultiValue>(args)?;
+            if budget_exceeded(lua){
+                return Err(mlua::Error::runtime(CPU_LIMIT_EXCEEDED));
+            }
+            Ok(res)
+        })?)?;
+    }
+    let api = lua.create_table()?;
+    let log_name = name.to_string();
+    api.set("log", lua.create_function(move |_, (level, message): (String, String)| {
+        match level.as_str(){
+            "error" => error!(extension = log_name, "{message}"),
+            "warn" => warn!(extension = log_name, "{message}"),
+            "debug" => debug!(extension = log_name, "{message}"),
+            _ => info!(extension = log_name, "{message}")
+        }
+        Ok(())
+    })?)?;
+    let print_name = name.to_string();
+    globals.raw_set("print", lua.create_function(move |_, args: Variadic<Value>| {
+        let message = args.iter()
+            .map(|v| v.to_string().unwrap_or_else(|_| v.type_name().to_string()))
+            .collect::<Vec<_>>()
+            .join("\t");
+        info!(extension = print_name, "{message}");
+        Ok(())
+    })?)?;
+    api.set("register_fs", lua.create_function(|lua, (name, provider): (String, Table)| {
+        let key = lua.create_registry_value(provider)?;
+        register(lua, Capability::Fs, |r| {
+            if r.fs.contains_key(&name){
+                return Err(mlua::Error::runtime(format!("fs provider {name} is already registered")));
+            }
+            r.fs.insert(name, key);
+            Ok(())
+        })
+    })?)?;
+    api.set("register_filter", lua.create_function(|lua, filter: Function| {
+        let key = lua.create_registry_value(filter)?;
+        register(lua, Capability::Filter, |r| {
+            r.filters.push(key);
+            Ok(())
+        })
+    })?)?;
+    api.set("register_http_hook", lua.create_function(|lua, hook: Function| {
+        let key = lua.create_registry_value(hook)?;
+        register(lua, Capability::Http, |r| {
+            r.http_hooks.push(key);
+            Ok(())
+        })
+    })?)?;
+    api.set("on_filter", lua.create_function(|lua, (event, hook): (String, Function)| {
+        let kind = parse_event(&event)?;
+        let key = lua.create_registry_value(hook)?;
+        register(lua, Capability::Events, |r| {
+            r.event_filters.push((kind, key));
+            Ok(())
+        })
+    })?)?;
+    api.set("on_action", lua.create_function(|lua, (event, hook): (String, Function)| {
+        let kind = parse_event(&event)?;
+        let key = lua.create_registry_value(hook)?;
+        register(lua, Capability::Events, |r| {
+            r.event_actions.push((kind, key));
+            Ok(())
+        })
+    })?)?;
+    globals.raw_set("rustle", api)
+}
+
+fn parse_event(event: &str) -> mlua::Result<EventKind>{
+    EventKind::parse(event).ok_or_else(|| mlua::Error::runtime(format!("unknown event {event}")))
+}
+
+fn register(lua: &Lua, capability: Capability, f: impl FnOnce(&mut Registrations) -> mlua::Result<()>) -> mlua::Result<()>{
+    let mut loading = lua.app_data_mut::<Loading>()
+        .ok_or_else(|| mlua::Error::runtime("extensions can only register while being loaded"))?;
+    if !loading.capabilities.contains(&capability){
+        return Err(mlua::Error::runtime(format!("extension {} does not declare the {} capability",
+            loading.manifest_name, capability.as_str())));
+    }
+    f(&mut loading.registrations)
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use mlua::{Function, LuaSerdeExt, RegistryKey, Table, TableExt, UserData, UserDataMethods, Value};
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::mpsc;
+use tracing::error;
+
+use crate::external::extension::find_fs_provider;
+use crate::external::extension::runtime::{outside_budget, Extension};
+use crate::types::err::{AppResult, GlobalInternalError, GlobalUserError};
+use super::interface::FileStat;
+
+// an upload is handed to lua in pieces of this size, the whole of it would not fit in max_memory
+const UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+// a storage provider registered by an extension through rustle.register_fs,
+// bound to the instance its init function returned for one policy
+pub struct ExtensionFs{
+    extension: Arc<Extension>,
+    instance: Arc<RegistryKey>
+}
+// the upload as put sees it, upload:read() gives the next piece or nil once all is read;
+// the end is sent as an empty piece, so a dropped sender is told apart from it
+struct Upload{
+    chunks: mpsc::Receiver<Result<Vec<u8>, ()>>,
+    finished: bool
+}
+impl UserData for Upload{
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method_mut("read", |lua, upload, ()| {
+            if upload.finished{
+                return Ok(None);
+            }
+            match outside_budget(lua, || upload.chunks.blocking_recv()){
+                Some(Ok(chunk)) if chunk.is_empty() => {
+                    upload.finished = true;
+                    Ok(None)
+                },
+                Some(Ok(chunk)) => lua.create_string(&chunk).map(Some),
+                Some(Err(())) | None => Err(mlua::Error::runtime("upload aborted"))
+            }
+        });
+    }
+}
+#[derive(Deserialize)]
+struct ExtensionStat{
+    size: u64,
+    // unix timestamp in seconds
+    #[serde(default)]
+    modified: Option<i64>
+}
+
+impl ExtensionFs{
+    pub async fn initialize(provider: &str, meta: HashMap<String, String>) -> Result<Self, ()>{
+        let extension = find_fs_provider(provider).ok_or_else(|| {
+            error!("fs provider {provider} is not registered by any extension");
+        })?;
+        let name = provider.to_string();
+        let instance = extension.call(move |lua, r| {
+            let provider: Table = lua.registry_value(&r.fs[&name])?;
+            let instance = match provider.get::<_, Option<Function>>("init")?{
+                Some(init) => init.call::<_, Table>((provider, meta))?,
+                None => provider
+            };
+            lua.create_registry_value(instance)
+        }).await.map_err(|_| error!("cannot initialize fs provider {provider}"))?;
+        Ok(Self{
+            extension,
+            instance: Arc::new(instance)
+        })
+    }
+
+    // put reads the upload while it is still coming in, one piece at a time
+    pub async fn upload_file<T: AsyncRead + Unpin>(
+        &self,
+        stream: &mut T,
+        user_id: i32,
+        path: &Path,
+    ) -> AppResult<u64> {
+        let (tx, rx) = mpsc::channel(1);
+        let instance = self.instance.clone();
+        let path = path.to_string_lossy().into_owned();
+        let put = self.extension.call(move |lua, _| {
+            let instance: Table = lua.registry_value(&instance)?;
+            let upload = lua.create_userdata(Upload{ chunks: rx, finished: false })?;
+            let res = instance.call_method::<_, ()>("put", (user_id, path, upload.clone()));
+            // taken back so the rest of the upload is not waited for once put returned
+            let upload = upload.take::<Upload>()?;
+            res.map(|_| upload.finished)
+        });
+        let feed = async move {
+            let mut size = 0u64;
+            let mut buf = vec![0u8; UPLOAD_CHUNK_SIZE];
+            loop {
+                let n = match stream.read(&mut buf).await{
+                    Ok(n) => n,
+                    Err(e) => {
+                        error!("cannot read upload: {:?}", e);
+                        let _ = tx.send(Err(())).await;
+                        return Err(GlobalInternalError::IO);
+                    }
+                };
+                // a closed channel means put returned early, which is told apart below
+                if tx.send(Ok(buf[..n].to_vec())).await.is_err() || n == 0{
+                    return Ok(size);
+                }
+                size += n as u64;
+            }
+        };
+        let (finished, size) = tokio::try_join!(put, async { feed.await.map_err(Into::into) })?;
+        if !finished{
+            error!("fs provider returned before reading the whole upload");
+            return Err(GlobalInternalError::IO.into());
+        }
+        Ok(size)
+    }
+
+    async fn read(
+        &self,
+        user_id: i32,
+        path: &Path,
+        range: Option<(u64, u64)>,
+    ) -> AppResult<Vec<u8>> {
+        let instance = self.instance.clone();
+        let path = path.to_string_lossy().into_owned();
+        self.extension.call(move |lua, _| {
+            let instance: Table = lua.registry_value(&instance)?;
+            let data = match range{
+                Some((start, len)) if instance.contains_key("get_range")? =>
+                    instance.call_method::<_, Option<mlua::String>>("get_range", (user_id, path, start, len))?
+                        .map(|d| d.as_bytes().to_vec()),
+                // providers without get_range hand out the whole file, the range is cut here
+                Some((start, len)) => instance.call_method::<_, Option<mlua::String>>("get", (user_id, path))?
+                    .map(|d| d.as_bytes().iter().skip(start as usize).take(len as usize).copied().collect()),
+                None => instance.call_method::<_, Option<mlua::String>>("get", (user_id, path))?
+                    .map(|d| d.as_bytes().to_vec())
+            };
+            Ok(data)
+        }).await?.ok_or_else(|| GlobalUserError::NotFound.into())
+    }
+
+    pub async fn get_file(
+        &self,
+        user_id: i32,
+        path: &Path,
+    ) -> AppResult<impl AsyncRead + Unpin> {
+        Ok(Cursor::new(self.read(user_id, path, None).await?))
+    }
+
+    pub async fn get_file_range(
+        &self,
+        user_id: i32,
+        path: &Path,
+        start: u64,
+        len: u64,
+    ) -> AppResult<impl AsyncRead + Unpin> {
+        Ok(Cursor::new(self.read(user_id, path, Some((start, len))).await?))
+    }
+
+    pub async fn stat_file(
+        &self,
+        user_id: i32,
+        path: &Path,
+    ) -> AppResult<FileStat> {
+        let instance = self.instance.clone();
+        let path = path.to_string_lossy().into_owned();
+        let stat = self.extension.call(move |lua, _| {
+            let instance: Table = lua.registry_value(&instance)?;
+            match instance.call_method::<_, Value>("stat", (user_id, path))?{
+                Value::Nil => Ok(None),
+                v => lua.from_value::<ExtensionStat>(v).map(Some)
+            }
+        }).await?.ok_or(GlobalUserError::NotFound)?;
+        Ok(FileStat{
+            size: stat.size,
+            modified: stat.modified.and_then(|t| DateTime::<Utc>::from_timestamp(t, 0)).unwrap_or_else(Utc::now)
+        })
+    }
+
+    pub async fn delete_file(
+        &self,
+        user_id: i32,
+        path: &Path,
+    ) -> AppResult<()> {
+        let instance = self.instance.clone();
+        let path = path.to_string_lossy().into_owned();
+        self.extension.call(move |lua, _| {
+            let instance: Table = lua.registry_value(&instance)?;
+            instance.call_method::<_, ()>("delete", (user_id, path))
+        }).await
+    }
+}
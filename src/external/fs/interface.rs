@@ -2,18 +2,26 @@ use std::borrow::Borrow;
 use std::error::Error;
 use std::path::Path;
 
-use futures_util::Stream;
+use chrono::{DateTime, Utc};
 use tokio::io::AsyncRead;
 use tokio_util::bytes::Bytes;
-use crate::types::err::AppResult;
+use tokio_util::either::Either;
+use crate::get_config;
+use crate::types::err::{AppResult, GlobalUserError};
+use crate::utils::stream::LimitedReader;
+use super::extension::ExtensionFs;
 use super::local::LocalFs;
+use super::quota::get_storage_state;
 use super::{FsUserError, FS_POLICY_CACHE};
 
+pub struct FileStat{
+    pub size: u64,
+    pub modified: DateTime<Utc>
+}
+
 pub enum FsProvider{
     LocalProvider(LocalFs),
-    ExtensionProvider{
-        // js running context etc    
-    }
+    ExtensionProvider(ExtensionFs)
 }
 
 fn check_path(p: &Path) -> bool{
@@ -40,12 +48,37 @@ impl FsProvider {
             user_id: i32,
             path: &Path) -> AppResult<u64>{
                 
-                let policy_spec = FS_POLICY_CACHE.get(&policy_id).ok_or(FsUserError::PolicyNotFound)?;
-                match policy_spec.instance.borrow(){
-                    FsProvider::LocalProvider(p) => p.upload_file(stream, user_id, path).await,
-                    FsProvider::ExtensionProvider {  } => todo!(),
+                // the quota is checked against what is actually received, Content-Length can not be trusted
+                let max_upload_size = get_config!(http).max_upload_size as u64;
+                let (limit, limited_by_quota) = match get_storage_state(user_id).await?.remaining_bytes(){
+                    Some(remaining) if remaining < max_upload_size => (remaining, true),
+                    _ => (max_upload_size, false)
+                };
+                let mut limited = LimitedReader::new(stream, limit);
+                let res = Self::put_file(&mut limited, policy_id, user_id, path).await;
+                if limited.exceeded(){
+                    let _ = Self::delete_file(policy_id, user_id, path).await;
+                    return Err(if limited_by_quota {
+                        FsUserError::QuotaExceeded.into()
+                    } else {
+                        GlobalUserError::PayloadTooLarge.into()
+                    });
                 }
+                res
             }
+            // writes without any quota accounting, meant for data derived from existing files
+            pub async fn put_file<T: AsyncRead + Unpin>( 
+                stream: &mut T, 
+                policy_id: i32,
+                user_id: i32,
+                path: &Path) -> AppResult<u64>{
+                    
+                    let policy_spec = FS_POLICY_CACHE.get(&policy_id).ok_or(FsUserError::PolicyNotFound)?;
+                    match policy_spec.instance.borrow(){
+                        FsProvider::LocalProvider(p) => p.upload_file(stream, user_id, path).await,
+                        FsProvider::ExtensionProvider(p) => p.upload_file(stream, user_id, path).await,
+                    }
+                }
             pub async fn get_file( 
                 policy_id: i32,
                 user_id: i32,
@@ -54,8 +87,44 @@ impl FsProvider {
                     
                     let policy_spec = FS_POLICY_CACHE.get(&policy_id).ok_or(FsUserError::PolicyNotFound)?;
                     match policy_spec.instance.borrow(){
-                        FsProvider::LocalProvider(p) => p.get_file(user_id, path).await,
-                        FsProvider::ExtensionProvider {  } => todo!(),
+                        FsProvider::LocalProvider(p) => p.get_file(user_id, path).await.map(Either::Left),
+                        FsProvider::ExtensionProvider(p) => p.get_file(user_id, path).await.map(Either::Right),
+                    }
+                }
+            pub async fn get_file_range( 
+                policy_id: i32,
+                user_id: i32,
+                path: &Path,
+                start: u64,
+                len: u64) 
+                -> AppResult<impl AsyncRead + Unpin>{
+                    
+                    let policy_spec = FS_POLICY_CACHE.get(&policy_id).ok_or(FsUserError::PolicyNotFound)?;
+                    match policy_spec.instance.borrow(){
+                        FsProvider::LocalProvider(p) => p.get_file_range(user_id, path, start, len).await.map(Either::Left),
+                        FsProvider::ExtensionProvider(p) => p.get_file_range(user_id, path, start, len).await.map(Either::Right),
+                    }
+                }
+            pub async fn stat_file( 
+                policy_id: i32,
+                user_id: i32,
+                path: &Path) -> AppResult<FileStat>{
+                    
+                    let policy_spec = FS_POLICY_CACHE.get(&policy_id).ok_or(FsUserError::PolicyNotFound)?;
+                    match policy_spec.instance.borrow(){
+                        FsProvider::LocalProvider(p) => p.stat_file(user_id, path).await,
+                        FsProvider::ExtensionProvider(p) => p.stat_file(user_id, path).await,
+                    }
+                }
+            pub async fn delete_file( 
+                policy_id: i32,
+                user_id: i32,
+                path: &Path) -> AppResult<()>{
+                    
+                    let policy_spec = FS_POLICY_CACHE.get(&policy_id).ok_or(FsUserError::PolicyNotFound)?;
+                    match policy_spec.instance.borrow(){
+                        FsProvider::LocalProvider(p) => p.delete_file(user_id, path).await,
+                        FsProvider::ExtensionProvider(p) => p.delete_file(user_id, path).await,
                     }
                 }
 }
\ No newline at end of file
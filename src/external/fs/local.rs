@@ -1,4 +1,5 @@
-use tokio::io::AsyncRead;
+use chrono::{DateTime, Utc};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
 use tokio_util::io::StreamReader;
 use tracing::error;
 
@@ -6,13 +7,14 @@ use crate::types::err::GlobalInternalError;
 use crate::types::err::AppResult;
 use crate::types::err::GlobalUserError;
 use std::fs::File;
+use std::io::SeekFrom;
 use std::path::Path;
+use super::interface::FileStat;
 pub struct LocalFs{
     pub path: String
 }
 
 
-use futures_util::AsyncReadExt;
 impl LocalFs{
     pub const EXTENSION_NAME: &'static str = "rustle.builtin.fs.local";
     pub async fn initialize(path: String) -> Result<Self, ()>{
@@ -66,11 +68,11 @@ impl LocalFs{
             })
     }
 
-    pub async fn get_file(
+    async fn open(
         &self,
         user_id: i32,
         path: &Path,
-    ) -> AppResult<impl AsyncRead + Unpin> {
+    ) -> AppResult<tokio::fs::File> {
         let file_path = Path::new(&self.path).join(Path::new(&format!("{user_id}"))).join(path);
         let file = tokio::fs::File::open(&file_path)
             .await
@@ -84,4 +86,58 @@ impl LocalFs{
             })?;
         Ok(file)
     }
+
+    pub async fn get_file(
+        &self,
+        user_id: i32,
+        path: &Path,
+    ) -> AppResult<impl AsyncRead + Unpin> {
+        self.open(user_id, path).await
+    }
+
+    pub async fn get_file_range(
+        &self,
+        user_id: i32,
+        path: &Path,
+        start: u64,
+        len: u64,
+    ) -> AppResult<impl AsyncRead + Unpin> {
+        let mut file = self.open(user_id, path).await?;
+        file.seek(SeekFrom::Start(start)).await.map_err(|e| {
+            error!("cannot seek file: {:?}", e);
+            GlobalInternalError::IO
+        })?;
+        Ok(file.take(len))
+    }
+
+    pub async fn stat_file(
+        &self,
+        user_id: i32,
+        path: &Path,
+    ) -> AppResult<FileStat> {
+        let meta = self.open(user_id, path).await?.metadata().await.map_err(|e| {
+            error!("cannot read metadata: {:?}", e);
+            GlobalInternalError::IO
+        })?;
+        Ok(FileStat{
+            size: meta.len(),
+            modified: meta.modified().map(DateTime::<Utc>::from).unwrap_or_else(|_| Utc::now())
+        })
+    }
+
+    pub async fn delete_file(
+        &self,
+        user_id: i32,
+        path: &Path,
+    ) -> AppResult<()> {
+        let file_path = Path::new(&self.path).join(Path::new(&format!("{user_id}"))).join(path);
+        match tokio::fs::remove_file(&file_path).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => {
+                error!("cannot delete {}: {:?}", file_path.to_string_lossy(), e);
+                Err(GlobalInternalError::IO.into())
+            }
+        }
+    }
 }
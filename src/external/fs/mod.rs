@@ -1,5 +1,7 @@
 
 
+use std::collections::HashSet;
+
 use dashmap::DashMap;
 use once_cell::sync::Lazy;
 use rustle_derive::ErrorHelper;
@@ -8,12 +10,16 @@ use tracing::error;
 use crate::types::err::EmptyErrResult;
 use crate::types::service::AppService;
 use crate::db::{fs as fsDao, get_db_pool};
+use self::extension::ExtensionFs;
 use self::interface::FsProvider;
 use self::local::LocalFs;
 
 pub mod embed;
+pub mod extension;
 pub mod local;
 pub mod interface;
+pub mod quota;
+pub mod serve;
 pub static FS_POLICY_CACHE: Lazy<DashMap<i32, FsPolicy>> = Lazy::new(|| DashMap::new());
 pub struct FsService;
 pub struct FsPolicy{
@@ -25,34 +31,51 @@ impl AppService for FsService {
         "FsService"
     }
     async fn initialize() -> EmptyErrResult<()> {
-        for policy_info in fsDao::get_all_policies(get_db_pool()).await.map_err(|e|{
-            error!("failed to get fs policy cache: {}", e);
-        })?{
-            if policy_info.extension.eq(LocalFs::EXTENSION_NAME){
-                let path = match policy_info.meta.get("path"){
-                    Some(p) => p.to_string(),
-                    None => {
-                        error!("path not found in the config of local fs");
-                        continue;
-                    }
-                };
-                let _ = FS_POLICY_CACHE.insert(policy_info.id, FsPolicy{
-                    instance: FsProvider::LocalProvider(LocalFs::initialize(path).await?),
-                    info: policy_info
-                });
-            } else {
-                todo!()
-            }
-        }
-        Ok(())
+        load_policies().await
+    }
+}
+
+// also used for a reload, policies are swapped one by one and deleted ones dropped last
+pub async fn load_policies() -> EmptyErrResult<()> {
+    let mut loaded = HashSet::new();
+    for policy_info in fsDao::get_all_policies(get_db_pool()).await.map_err(|e|{
+        error!("failed to get fs policy cache: {}", e);
+    })?{
+        // a policy that fails to load keeps the instance it had before
+        loaded.insert(policy_info.id);
+        let instance = if policy_info.extension.eq(LocalFs::EXTENSION_NAME){
+            let path = match policy_info.meta.get("path"){
+                Some(p) => p.to_string(),
+                None => {
+                    error!("path not found in the config of local fs");
+                    continue;
+                }
+            };
+            FsProvider::LocalProvider(LocalFs::initialize(path).await?)
+        } else {
+            // extensions are loaded beforehand, the name matches what they registered
+            let Ok(instance) = ExtensionFs::initialize(&policy_info.extension, policy_info.meta.clone()).await else {
+                error!("fs policy {} is not loaded", policy_info.name);
+                continue;
+            };
+            FsProvider::ExtensionProvider(instance)
+        };
+        let _ = FS_POLICY_CACHE.insert(policy_info.id, FsPolicy{
+            instance,
+            info: policy_info
+        });
     }
+    FS_POLICY_CACHE.retain(|id, _| loaded.contains(id));
+    Ok(())
 }
 
 #[derive(ErrorHelper)]
 #[err(user, default_msg)]
 pub enum FsUserError{
     PolicyNotFound,
-    PathNameNotValid
+    PathNameNotValid,
+    #[err(code = 413)]
+    QuotaExceeded
 }
 
 pub const DEFAULT_POLICY_ID: i32 = 1;
\ No newline at end of file
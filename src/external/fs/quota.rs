@@ -0,0 +1,44 @@
+use serde::Serialize;
+use crate::db::{get_db_pool, quota as quotaDao, quota::{Quota, Usage}};
+use crate::providers::auth::service::get_user_roles;
+use crate::types::err::AppResult;
+
+#[derive(Serialize, Debug)]
+pub struct StorageState{
+    pub quota: Quota,
+    pub usage: Usage
+}
+impl StorageState{
+    pub fn remaining_bytes(&self) -> Option<u64>{
+        self.quota.quota_bytes.map(|q| (q - self.usage.used_bytes).max(0) as u64)
+    }
+    pub fn can_add_file(&self) -> bool{
+        self.quota.quota_files.is_none_or(|q| self.usage.used_files < q as i64)
+    }
+}
+
+// a per-user override wins, otherwise the most generous quota among the user's roles applies.
+// a role without a quota set is unlimited, and so is everyone holding it
+pub async fn get_effective_quota(user: i32) -> AppResult<Quota>{
+    let user_quota = quotaDao::select_user_quota(get_db_pool(), user).await?;
+    if user_quota.quota_bytes.is_some() && user_quota.quota_files.is_some(){
+        return Ok(user_quota);
+    }
+    let roles = get_user_roles(user).await?;
+    let role_quotas = quotaDao::select_role_quotas(get_db_pool(), &roles).await?;
+    Ok(Quota{
+        quota_bytes: user_quota.quota_bytes.or_else(|| most_generous(role_quotas.iter().map(|q| q.quota_bytes))),
+        quota_files: user_quota.quota_files.or_else(|| most_generous(role_quotas.iter().map(|q| q.quota_files))),
+    })
+}
+// None wins over any limit; no roles at all leave nothing to limit by either
+fn most_generous<T: Ord>(quotas: impl Iterator<Item = Option<T>>) -> Option<T>{
+    quotas.collect::<Option<Vec<T>>>()?.into_iter().max()
+}
+
+pub async fn get_storage_state(user: i32) -> AppResult<StorageState>{
+    Ok(StorageState{
+        quota: get_effective_quota(user).await?,
+        usage: quotaDao::select_usage(get_db_pool(), user).await?
+    })
+}
@@ -0,0 +1,305 @@
+use std::error::Error;
+use std::future::ready;
+use std::path::PathBuf;
+use chrono::{DateTime, Utc};
+use futures_util::{stream, Stream, StreamExt};
+use ntex::http::body::SizedStream;
+use ntex::util::Bytes;
+use ntex::web::{HttpRequest, HttpResponse};
+use uuid::Uuid;
+use super::interface::FsProvider;
+use crate::types::err::AppResult;
+use crate::utils::stream::ReaderChunkedStream;
+
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+// more ranges than this are most likely an abuse, the whole file is sent instead
+const MAX_RANGES: usize = 16;
+
+type BoxedBodyStream = std::pin::Pin<Box<dyn Stream<Item = Result<Bytes, Box<dyn Error>>>>>;
+
+// serves a file of any FsProvider with range, conditional request and caching support
+pub struct ServedFile{
+    pub policy_id: i32,
+    pub user_id: i32,
+    pub path: PathBuf,
+    pub content_type: String,
+    // a strong validator like the content hash, otherwise a weak one is derived from size and mtime
+    pub etag: Option<String>,
+    pub cache_control: String,
+}
+
+#[derive(Debug, PartialEq)]
+enum RangeSpec{
+    Full,
+    Partial(Vec<(u64, u64)>),
+    Unsatisfiable
+}
+
+impl ServedFile{
+    pub async fn respond(self, req: &HttpRequest) -> AppResult<HttpResponse>{
+        let stat = FsProvider::stat_file(self.policy_id, self.user_id, &self.path).await?;
+        let etag = match &self.etag{
+            Some(t) => format!("\"{t}\""),
+            None => format!("W/\"{:x}-{:x}\"", stat.size, stat.modified.timestamp())
+        };
+        let last_modified = stat.modified.format(HTTP_DATE_FORMAT).to_string();
+        let not_modified = is_not_modified(req, &etag, &stat.modified);
+        let mut builder = if not_modified{
+            HttpResponse::NotModified()
+        } else {
+            HttpResponse::Ok()
+        };
+        builder.header("etag", etag.as_str())
+            .header("last-modified", last_modified.as_str())
+            .header("cache-control", self.cache_control.as_str())
+            .header("accept-ranges", "bytes");
+        if not_modified{
+            return Ok(builder.finish());
+        }
+
+        let range = match header_str(req, "range"){
+            Some(r) if if_range_allows(req, &etag, &stat.modified) => parse_range(r, stat.size),
+            _ => RangeSpec::Full
+        };
+        match range{
+            RangeSpec::Full => {
+                let body = self.part_stream(0, stat.size).await?;
+                Ok(builder.content_type(self.content_type.as_str())
+                    .body(SizedStream::new(stat.size, body)))
+            },
+            RangeSpec::Unsatisfiable => {
+                Ok(HttpResponse::RangeNotSatisfiable()
+                    .header("content-range", format!("bytes */{}", stat.size))
+                    .finish())
+            },
+            RangeSpec::Partial(ranges) if ranges.len() == 1 => {
+                let (start, end) = ranges[0];
+                let body = self.part_stream(start, end - start + 1).await?;
+                Ok(builder.status(ntex::http::StatusCode::PARTIAL_CONTENT)
+                    .content_type(self.content_type.as_str())
+                    .header("content-range", format!("bytes {start}-{end}/{}", stat.size))
+                    .body(SizedStream::new(end - start + 1, body)))
+            },
+            RangeSpec::Partial(ranges) => {
+                let boundary = Uuid::new_v4().simple().to_string();
+                let mut body_len = 0;
+                let mut parts = Vec::with_capacity(ranges.len());
+                for (start, end) in ranges{
+                    let part_header = format!("\r\n--{boundary}\r\ncontent-type: {}\r\ncontent-range: bytes {start}-{end}/{}\r\n\r\n",
+                        self.content_type, stat.size);
+                    body_len += part_header.len() as u64 + end - start + 1;
+                    parts.push((Bytes::from(part_header), start, end - start + 1));
+                }
+                let closing = Bytes::from(format!("\r\n--{boundary}--\r\n"));
+                body_len += closing.len() as u64;
+                let (policy_id, user_id, path) = (self.policy_id, self.user_id, self.path);
+                // each part is opened only when the previous one has been sent
+                let body = stream::iter(parts).then(move |(part_header, start, len)| {
+                    let path = path.clone();
+                    async move {
+                        let header_stream = stream::once(ready(Ok::<_, Box<dyn Error>>(part_header)));
+                        match open_stream(policy_id, user_id, path, start, len).await{
+                            Ok(s) => header_stream.chain(s).left_stream(),
+                            Err(e) => header_stream.chain(stream::once(ready(Err(e)))).right_stream()
+                        }
+                    }
+                }).flatten().chain(stream::once(ready(Ok(closing))));
+                Ok(builder.status(ntex::http::StatusCode::PARTIAL_CONTENT)
+                    .content_type(format!("multipart/byteranges; boundary={boundary}"))
+                    .body(SizedStream::new(body_len, Box::pin(body) as BoxedBodyStream)))
+            }
+        }
+    }
+
+    async fn part_stream(&self, start: u64, len: u64) -> AppResult<BoxedBodyStream>{
+        let reader = FsProvider::get_file_range(self.policy_id, self.user_id, &self.path, start, len).await?;
+        Ok(Box::pin(ReaderChunkedStream::new(reader).map(|r| r.map_err(|e| Box::new(e) as Box<dyn Error>))))
+    }
+}
+
+async fn open_stream(policy_id: i32, user_id: i32, path: PathBuf, start: u64, len: u64) -> Result<BoxedBodyStream, Box<dyn Error>>{
+    let reader = FsProvider::get_file_range(policy_id, user_id, &path, start, len).await
+        .map_err(|e| Box::new(std::io::Error::other(e.to_string())) as Box<dyn Error>)?;
+    Ok(Box::pin(ReaderChunkedStream::new(reader).map(|r| r.map_err(|e| Box::new(e) as Box<dyn Error>))))
+}
+
+fn header_str<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str>{
+    req.headers().get(name).and_then(|h| h.to_str().ok())
+}
+
+fn parse_http_date(s: &str) -> Option<DateTime<Utc>>{
+    DateTime::parse_from_rfc2822(s.trim()).ok().map(|d| d.with_timezone(&Utc))
+}
+
+fn weak_eq(a: &str, b: &str) -> bool{
+    a.trim_start_matches("W/") == b.trim_start_matches("W/")
+}
+
+fn is_not_modified(req: &HttpRequest, etag: &str, modified: &DateTime<Utc>) -> bool{
+    // If-Modified-Since is ignored when If-None-Match is present
+    if let Some(inm) = header_str(req, "if-none-match"){
+        return inm.trim() == "*" || inm.split(',').any(|t| weak_eq(t.trim(), etag));
+    }
+    match header_str(req, "if-modified-since").and_then(parse_http_date){
+        Some(since) => modified.timestamp() <= since.timestamp(),
+        None => false
+    }
+}
+
+fn if_range_allows(req: &HttpRequest, etag: &str, modified: &DateTime<Utc>) -> bool{
+    match header_str(req, "if-range").map(str::trim){
+        None => true,
+        // If-Range requires the strong comparison
+        Some(v) if v.starts_with('"') || v.starts_with("W/") => !etag.starts_with("W/") && v == etag,
+        Some(v) => parse_http_date(v).is_some_and(|d| d.timestamp() == modified.timestamp())
+    }
+}
+
+// a malformed header is ignored as RFC 9110 suggests, the whole file is sent then
+fn parse_range(header: &str, size: u64) -> RangeSpec{
+    let specs = match header.trim().strip_prefix("bytes="){
+        Some(s) => s,
+        None => return RangeSpec::Full
+    };
+    if size == 0{
+        return RangeSpec::Full;
+    }
+    let mut ranges = vec![];
+    for spec in specs.split(','){
+        let (start, end) = match spec.trim().split_once('-'){
+            Some(t) => t,
+            None => return RangeSpec::Full
+        };
+        if start.is_empty(){
+            let suffix: u64 = match end.parse(){
+                Ok(n) => n,
+                Err(_) => return RangeSpec::Full
+            };
+            if suffix > 0{
+                ranges.push((size.saturating_sub(suffix), size - 1));
+            }
+            continue;
+        }
+        let start: u64 = match start.parse(){
+            Ok(n) => n,
+            Err(_) => return RangeSpec::Full
+        };
+        let end: u64 = if end.is_empty(){
+            size - 1
+        } else {
+            match end.parse::<u64>(){
+                Ok(n) if n >= start => n.min(size - 1),
+                _ => return RangeSpec::Full
+            }
+        };
+        if start < size{
+            ranges.push((start, end));
+        }
+    }
+    if ranges.len() > MAX_RANGES{
+        RangeSpec::Full
+    } else if ranges.is_empty(){
+        RangeSpec::Unsatisfiable
+    } else {
+        RangeSpec::Partial(ranges)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use chrono::TimeZone;
+    use ntex::web::test::TestRequest;
+    use super::*;
+
+    const SIZE: u64 = 1000;
+
+    fn modified() -> DateTime<Utc>{
+        Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap()
+    }
+
+    #[test]
+    fn single_range(){
+        assert_eq!(parse_range("bytes=0-99", SIZE), RangeSpec::Partial(vec![(0, 99)]));
+        assert_eq!(parse_range("bytes=900-", SIZE), RangeSpec::Partial(vec![(900, 999)]));
+    }
+
+    #[test]
+    fn multi_range(){
+        assert_eq!(parse_range("bytes=0-9, 20-29,-5", SIZE), RangeSpec::Partial(vec![(0, 9), (20, 29), (995, 999)]));
+    }
+
+    #[test]
+    fn suffix_range(){
+        assert_eq!(parse_range("bytes=-100", SIZE), RangeSpec::Partial(vec![(900, 999)]));
+        // a suffix longer than the file is the whole file
+        assert_eq!(parse_range("bytes=-5000", SIZE), RangeSpec::Partial(vec![(0, 999)]));
+        assert_eq!(parse_range("bytes=-0", SIZE), RangeSpec::Unsatisfiable);
+    }
+
+    #[test]
+    fn out_of_bounds_range(){
+        assert_eq!(parse_range("bytes=1000-1100", SIZE), RangeSpec::Unsatisfiable);
+        // the end is clamped to the last byte
+        assert_eq!(parse_range("bytes=990-5000", SIZE), RangeSpec::Partial(vec![(990, 999)]));
+        // satisfiable ranges are kept, the others dropped
+        assert_eq!(parse_range("bytes=2000-,0-0", SIZE), RangeSpec::Partial(vec![(0, 0)]));
+    }
+
+    #[test]
+    fn malformed_range(){
+        assert_eq!(parse_range("items=0-9", SIZE), RangeSpec::Full);
+        assert_eq!(parse_range("bytes=9-0", SIZE), RangeSpec::Full);
+        assert_eq!(parse_range("bytes=a-b", SIZE), RangeSpec::Full);
+        assert_eq!(parse_range("bytes=0-9", 0), RangeSpec::Full);
+        let too_many = format!("bytes={}", (0..=MAX_RANGES).map(|i| format!("{i}-{i}")).collect::<Vec<_>>().join(","));
+        assert_eq!(parse_range(&too_many, SIZE), RangeSpec::Full);
+    }
+
+    #[test]
+    fn not_modified_by_etag(){
+        let req = TestRequest::default().header("if-none-match", "\"a\", W/\"b\"").to_http_request();
+        assert!(is_not_modified(&req, "\"a\"", &modified()));
+        // If-None-Match uses the weak comparison
+        assert!(is_not_modified(&req, "\"b\"", &modified()));
+        assert!(!is_not_modified(&req, "\"c\"", &modified()));
+        let req = TestRequest::default().header("if-none-match", "*").to_http_request();
+        assert!(is_not_modified(&req, "\"c\"", &modified()));
+    }
+
+    #[test]
+    fn not_modified_by_date(){
+        let req = TestRequest::default().header("if-modified-since", "Tue, 02 Jan 2024 03:04:05 GMT").to_http_request();
+        assert!(is_not_modified(&req, "\"a\"", &modified()));
+        assert!(!is_not_modified(&req, "\"a\"", &(modified() + chrono::Duration::seconds(1))));
+        // If-None-Match takes precedence
+        let req = TestRequest::default()
+            .header("if-modified-since", "Tue, 02 Jan 2024 03:04:05 GMT")
+            .header("if-none-match", "\"b\"")
+            .to_http_request();
+        assert!(!is_not_modified(&req, "\"a\"", &modified()));
+    }
+
+    #[test]
+    fn if_range_strong_etag(){
+        let req = TestRequest::default().header("if-range", "\"a\"").to_http_request();
+        assert!(if_range_allows(&req, "\"a\"", &modified()));
+        assert!(!if_range_allows(&req, "\"b\"", &modified()));
+    }
+
+    #[test]
+    fn if_range_weak_etag(){
+        // weak validators never match If-Range
+        let req = TestRequest::default().header("if-range", "W/\"a\"").to_http_request();
+        assert!(!if_range_allows(&req, "W/\"a\"", &modified()));
+        let req = TestRequest::default().header("if-range", "\"a\"").to_http_request();
+        assert!(!if_range_allows(&req, "W/\"a\"", &modified()));
+    }
+
+    #[test]
+    fn if_range_date(){
+        let req = TestRequest::default().header("if-range", "Tue, 02 Jan 2024 03:04:05 GMT").to_http_request();
+        assert!(if_range_allows(&req, "\"a\"", &modified()));
+        assert!(!if_range_allows(&req, "\"a\"", &(modified() + chrono::Duration::seconds(1))));
+        assert!(if_range_allows(&TestRequest::default().to_http_request(), "\"a\"", &modified()));
+    }
+}
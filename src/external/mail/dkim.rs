@@ -0,0 +1,82 @@
+use std::fs;
+
+use base64::{Engine as _, engine::general_purpose};
+use lettre::message::dkim::{DkimConfig as Signer, DkimSigningAlgorithm, DkimSigningKey};
+use lettre::Message;
+use once_cell::sync::OnceCell;
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs8::EncodePublicKey;
+use rsa::RsaPrivateKey;
+
+use crate::get_config;
+use crate::types::config::{DkimAlgorithm, DkimConfig, MailEnum};
+use crate::types::err::EmptyErrResult;
+use tracing::error;
+
+static SIGNER: OnceCell<Signer> = OnceCell::new();
+
+fn read_key(config: &DkimConfig) -> Result<String, String> {
+    fs::read_to_string(&config.private_key_path)
+        .map(|k| k.trim().to_string())
+        .map_err(|e| format!("cannot read dkim key {}: {}", config.private_key_path, e))
+}
+
+pub fn load(config: &DkimConfig) -> Result<Signer, String> {
+    let algorithm = match config.algorithm {
+        DkimAlgorithm::Rsa => DkimSigningAlgorithm::Rsa,
+        DkimAlgorithm::Ed25519 => DkimSigningAlgorithm::Ed25519
+    };
+    let key = DkimSigningKey::new(&read_key(config)?, algorithm)
+        .map_err(|e| format!("invalid dkim key {}: {}", config.private_key_path, e))?;
+    Ok(Signer::default_config(config.selector.clone(), config.domain.clone(), key))
+}
+
+pub fn init(config: &DkimConfig) -> Result<(), String> {
+    let signer = load(config)?;
+    let _ = SIGNER.set(signer);
+    Ok(())
+}
+
+// leaves the message alone when dkim is not configured
+pub fn sign(message: &mut Message) {
+    if let Some(signer) = SIGNER.get() {
+        message.sign(signer);
+    }
+}
+
+// the record to publish at <selector>._domainkey.<domain>,
+// split into 255 byte strings as a single TXT string can not be longer
+pub fn dns_record(config: &DkimConfig) -> Result<String, String> {
+    let key = read_key(config)?;
+    let (k, public) = match config.algorithm {
+        DkimAlgorithm::Rsa => {
+            let private = RsaPrivateKey::from_pkcs1_pem(&key).map_err(|e| e.to_string())?;
+            let der = private.to_public_key().to_public_key_der().map_err(|e| e.to_string())?;
+            ("rsa", general_purpose::STANDARD.encode(der.as_bytes()))
+        },
+        DkimAlgorithm::Ed25519 => {
+            let secret: [u8; 32] = general_purpose::STANDARD.decode(&key)
+                .map_err(|e| e.to_string())?
+                .try_into()
+                .map_err(|_| String::from("an ed25519 key is 32 bytes"))?;
+            let public = ed25519_dalek::SigningKey::from_bytes(&secret).verifying_key();
+            ("ed25519", general_purpose::STANDARD.encode(public.as_bytes()))
+        }
+    };
+    let value = format!("v=DKIM1; k={k}; p={public}");
+    let chunks: Vec<String> = value.as_bytes()
+        .chunks(255)
+        .map(|c| format!("\"{}\"", String::from_utf8_lossy(c)))
+        .collect();
+    Ok(format!("{}._domainkey.{}. IN TXT {}", config.selector, config.domain, chunks.join(" ")))
+}
+
+pub fn print_record() -> EmptyErrResult<()> {
+    let MailEnum::Enable(config) = get_config!(mail) else {
+        error!("mail function disabled");
+        return Err(());
+    };
+    let dkim = config.dkim.as_ref().ok_or_else(|| error!("mail.dkim is not configured"))?;
+    println!("{}", dns_record(dkim).map_err(|e| error!("{}", e))?);
+    Ok(())
+}
@@ -0,0 +1,198 @@
+use crate::db::{get_db_pool, mail as mailDao, mail::{MailAttempt, MailState, NewMail, QueuedMail}};
+use crate::get_config;
+use crate::types::config::{BaseConfig, ConfigInitializer, MailEnum};
+use crate::types::err::{AppResult, EmptyErrResult};
+use crate::types::service::AppService;
+use crate::utils::backoff::retry_delay;
+use chrono::Utc;
+use lettre::{message::{header::{HeaderName, HeaderValue}, Mailbox, MultiPart, SinglePart}, Message};
+use once_cell::sync::Lazy;
+use rustle_derive::ErrorHelper;
+use std::sync::atomic::{self, AtomicBool};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{error, info};
+pub static MAILER_ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub mod dkim;
+pub mod template;
+pub mod transport;
+pub use template::{MailEmailChangeTemplate, MailNewPostTemplate, MailNotificationTemplate, MailToLinkTemplate, MailVerifyTemplate, RenderedMail};
+use transport::MailTransport;
+
+pub struct MailConfig;
+impl ConfigInitializer for MailConfig{
+    fn initialize(c: &mut BaseConfig) -> Result<bool, ()> {
+        match &c.mail {
+            MailEnum::Enable(config) => {
+                let _: Mailbox = config.from.parse().map_err(|_| {
+                    error!("config mail.from not valid, you can either use NAME <MAIL> or MAIL");
+                })?;
+                MailTransport::build(config).map_err(|e| {
+                    error!("mail transport invalid: {}", e);
+                })?;
+                if let Some(dkim) = &config.dkim {
+                    dkim::load(dkim).map_err(|e| {
+                        error!("{}", e);
+                    })?;
+                }
+                
+            },
+            MailEnum::Disabled => {
+                info!("mail function disabled");
+            }
+        };
+        Ok(false)
+    }
+}
+
+
+// wakes the worker as soon as something is queued instead of waiting for the next poll
+static WAKE: Lazy<Notify> = Lazy::new(Notify::new);
+const DUE_BATCH: i32 = 50;
+// long enough for a whole batch against a slow server, a claimed mail is only sent again
+// after this if the instance that claimed it is gone
+const CLAIM_LEASE_MINUTES: i64 = 60;
+// the error column holds at most 1024 characters
+const MAX_ERROR_LEN: usize = 1024;
+
+pub struct MailService;
+impl AppService for MailService {
+    fn name() -> &'static str {
+        "MailService"
+    }
+    async fn initialize() -> EmptyErrResult<()> {
+            let config = get_config!(mail);
+            let config = match config{
+                MailEnum::Enable(c) => c,
+                MailEnum::Disabled => return Ok(())
+            };
+            let from: Mailbox = config.from.parse().unwrap(); // checked before
+                let transporter = MailTransport::build(config).unwrap(); // checked before
+                if let Err(e) = transporter.test_connection().await {
+                    error!("mail connection not available, continue anyway: {}", e);
+                }
+                info!("mail transport: {:?}", config.transport);
+                if let Some(d) = &config.dkim {
+                    dkim::init(d).unwrap(); // checked before
+                    info!("outgoing mail is signed for {} with selector {}", d.domain, d.selector);
+                }
+                MAILER_ENABLED.store(true, atomic::Ordering::SeqCst);
+                let poll_interval = Duration::from_secs(config.poll_interval_secs);
+                // mails left over from the last run are picked up by the first round
+                ntex::rt::spawn(async move {
+                    loop {
+                        deliver_due(&transporter, &from).await;
+                        let _ = tokio::time::timeout(poll_interval, WAKE.notified()).await;
+                    }
+                });
+                Ok(())
+    }
+}
+
+#[derive(ErrorHelper)]
+#[err(internal)]
+pub enum MailQueueError {
+    #[err(msg = "error.mail.parse")]
+    Parse,
+}
+pub fn compose(
+    content: RenderedMail,
+    subject: &str,
+    object: Option<&str>,
+    email: &str,
+    list_unsubscribe: Option<String>,
+) -> AppResult<NewMail> {
+    let to = Mailbox::new(object.map(String::from), email.parse().map_err(|e| {
+        error!("mail<to> not valid, possible of program error, {:?}", e);
+        MailQueueError::Parse
+    })?);
+    Ok(NewMail {
+        recipient: to.to_string(),
+        subject: subject.to_string(),
+        body: content.html,
+        text_body: content.text,
+        list_unsubscribe,
+    })
+}
+// the mail is persisted first, so it survives a restart and is retried until it is sent or dead
+pub async fn queue(
+    content: RenderedMail,
+    subject: &str,
+    object: &str,
+    email: &str,
+) -> AppResult<()> {
+    let mail = compose(content, subject, Some(object), email, None)?;
+    mailDao::enqueue(get_db_pool(), &mail).await?;
+    WAKE.notify_one();
+    Ok(())
+}
+
+pub fn wake() {
+    WAKE.notify_one();
+}
+
+async fn deliver_due(transporter: &MailTransport, from: &Mailbox) {
+    let mails = match mailDao::claim_due(get_db_pool(), DUE_BATCH, chrono::Duration::minutes(CLAIM_LEASE_MINUTES)).await {
+        Ok(m) => m,
+        Err(e) => {
+            error!("cannot fetch due mails: {}", e);
+            return;
+        }
+    };
+    for mail in mails {
+        let res = send(transporter, from, &mail).await;
+        if let Err((e, _)) = &res {
+            error!("mail sending error: {}", e);
+        }
+        let attempt = next_attempt(&mail, &res);
+        if let Err(e) = mailDao::record_attempt(get_db_pool(), mail.id, &attempt).await {
+            error!("cannot record attempt of mail {}: {}", mail.id, e);
+        }
+    }
+}
+
+// the flag tells whether the failure is permanent, retrying those is pointless
+async fn send(transporter: &MailTransport, from: &Mailbox, mail: &QueuedMail) -> Result<(), (String, bool)> {
+    let to: Mailbox = mail.recipient.parse().map_err(|e| (format!("invalid recipient: {e}"), true))?;
+    let mut builder = Message::builder()
+        .from(from.clone())
+        .to(to)
+        .subject(mail.subject.as_str());
+    // RFC 8058 one-click unsubscribe, mail clients POST to the link
+    if let Some(link) = &mail.list_unsubscribe {
+        builder = builder
+            .raw_header(HeaderValue::new(HeaderName::new_from_ascii_str("List-Unsubscribe"), format!("<{link}>")))
+            .raw_header(HeaderValue::new(HeaderName::new_from_ascii_str("List-Unsubscribe-Post"), String::from("List-Unsubscribe=One-Click")));
+    }
+    let mut message = match &mail.text_body {
+        Some(text) => builder.multipart(MultiPart::alternative_plain_html(text.clone(), mail.body.clone())),
+        None => builder.singlepart(SinglePart::html(mail.body.clone()))
+    }.map_err(|e| (e.to_string(), true))?;
+    dkim::sign(&mut message);
+    transporter.send(message).await
+}
+
+fn next_attempt<'a>(mail: &QueuedMail, res: &'a Result<(), (String, bool)>) -> MailAttempt<'a> {
+    let MailEnum::Enable(config) = get_config!(mail) else {
+        unreachable!("the worker only runs with mail enabled")
+    };
+    let attempts = mail.attempts + 1;
+    let (state, error) = match res {
+        Ok(_) => (MailState::Sent, None),
+        Err((e, permanent)) if *permanent || attempts >= config.max_attempts => (MailState::Dead, Some(truncate(e))),
+        Err((e, _)) => (MailState::Pending, Some(truncate(e)))
+    };
+    MailAttempt {
+        state,
+        next_attempt_at: Utc::now() + chrono::Duration::seconds(retry_delay(config.retry_base_secs, config.retry_max_secs, attempts)),
+        error
+    }
+}
+
+fn truncate(s: &str) -> &str {
+    match s.char_indices().nth(MAX_ERROR_LEN) {
+        Some((i, _)) => &s[..i],
+        None => s
+    }
+}
@@ -0,0 +1,166 @@
+use std::fs;
+use std::path::Path;
+
+use fluent_templates::{FluentLoader, LanguageIdentifier, Loader};
+use handlebars::{Handlebars, RenderError};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tracing::{error, info};
+
+use crate::external::fs::embed::{MailTemplates, LOCALES};
+use crate::get_config;
+use crate::types::config::MailEnum;
+
+const HTML_EXT: &str = ".hbs";
+const TEXT_EXT: &str = ".txt.hbs";
+
+// every mail `name` is rendered from name.hbs, plus name.txt.hbs for the plain-text part if it exists;
+// files in mail.template_dir replace the embedded ones with the same file name
+static MAIL_HBS: Lazy<Handlebars> = Lazy::new(|| {
+    let mut hbs = Handlebars::new();
+    hbs.register_helper("fluent", Box::new(FluentLoader::new(&*LOCALES)));
+    hbs.register_embed_templates::<MailTemplates>().unwrap();
+    if let MailEnum::Enable(config) = get_config!(mail) {
+        if !config.template_dir.is_empty() {
+            register_overrides(&mut hbs, Path::new(&config.template_dir));
+        }
+    }
+    hbs
+});
+fn register_overrides(hbs: &mut Handlebars, dir: &Path) {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(e) => {
+            error!("cannot read mail template directory {}: {}", dir.display(), e);
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !name.ends_with(HTML_EXT) {
+            continue;
+        }
+        // a broken override keeps the embedded template
+        match hbs.register_template_file(&name, entry.path()) {
+            Ok(_) => info!("mail template {} overridden", name),
+            Err(e) => error!("invalid mail template {}: {}", entry.path().display(), e)
+        }
+    }
+}
+
+pub struct RenderedMail {
+    pub html: String,
+    pub text: Option<String>,
+}
+pub fn render<T: Serialize>(name: &str, data: &T) -> Result<RenderedMail, RenderError> {
+    let html = MAIL_HBS.render(&format!("{name}{HTML_EXT}"), data)?;
+    let text_name = format!("{name}{TEXT_EXT}");
+    let text = if MAIL_HBS.has_template(&text_name) {
+        Some(MAIL_HBS.render(&text_name, data)?)
+    } else {
+        None
+    };
+    Ok(RenderedMail { html, text })
+}
+
+pub fn names() -> Vec<String> {
+    let mut names: Vec<String> = MAIL_HBS.get_templates().keys()
+        .filter(|n| !n.ends_with(TEXT_EXT))
+        .filter_map(|n| n.strip_suffix(HTML_EXT))
+        .map(String::from)
+        .collect();
+    names.sort();
+    names
+}
+// the sample carries every field any built-in template uses, unused ones are ignored
+pub fn preview(name: &str, lang: &LanguageIdentifier) -> Result<RenderedMail, RenderError> {
+    let site_info = get_config!(info);
+    render(name, &serde_json::json!({
+        "site_name": site_info.name,
+        "site_link": site_info.link,
+        "user": "Rustle",
+        "link": "sample-signature.0",
+        "code": "123456",
+        "email": "new@example.com",
+        "title": "Hello, Rustle",
+        "detail": "Nice post!",
+        "alias": "hello-rustle",
+        "unsubscribe": format!("{}/v1/newsletter/unsubscribe/sample-signature.0", site_info.link),
+        "action": LOCALES.lookup(lang, "identity_check").unwrap_or_default(),
+        "lang": lang.to_string()
+    }))
+}
+
+#[derive(Serialize)]
+pub struct MailToLinkTemplate {
+    pub site_name: String,
+    pub site_link: String,
+    pub user: String,
+    pub link: String,
+    pub action: String,
+    pub lang: String,
+}
+impl MailToLinkTemplate {
+    pub fn generate(&self) -> Result<RenderedMail, RenderError> {
+        render("tolink", self)
+    }
+}
+// tells the old address about a change, the link cancels it
+#[derive(Serialize)]
+pub struct MailEmailChangeTemplate {
+    pub site_name: String,
+    pub site_link: String,
+    pub user: String,
+    pub email: String,
+    pub link: String,
+    pub lang: String,
+}
+impl MailEmailChangeTemplate {
+    pub fn generate(&self) -> Result<RenderedMail, RenderError> {
+        render("emailchange", self)
+    }
+}
+#[derive(Serialize)]
+pub struct MailVerifyTemplate {
+    pub site_name: String,
+    pub site_link: String,
+    pub user: String,
+    pub code: String,
+    pub action: String,
+    pub lang: String,
+}
+impl MailVerifyTemplate {
+    pub fn generate(&self) -> Result<RenderedMail, RenderError> {
+        render("verify", self)
+    }
+}
+#[derive(Serialize)]
+pub struct MailNewPostTemplate {
+    pub site_name: String,
+    pub site_link: String,
+    pub title: String,
+    pub alias: String,
+    pub unsubscribe: String,
+    pub lang: String,
+}
+impl MailNewPostTemplate {
+    pub fn generate(&self) -> Result<RenderedMail, RenderError> {
+        render("newpost", self)
+    }
+}
+#[derive(Serialize)]
+pub struct MailNotificationTemplate {
+    pub site_name: String,
+    pub site_link: String,
+    pub user: String,
+    pub title: String,
+    pub detail: Option<String>,
+    // where the notification leads to on the site, e.g. the commented article
+    pub link: Option<String>,
+    pub lang: String,
+}
+impl MailNotificationTemplate {
+    pub fn generate(&self) -> Result<RenderedMail, RenderError> {
+        render("notification", self)
+    }
+}
@@ -0,0 +1,114 @@
+use std::io::Write;
+
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncFileTransport, AsyncSendmailTransport, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+use crate::types::config::{MailConfig, MailTransportKind};
+
+pub enum MailTransport {
+    Smtp(AsyncSmtpTransport<Tokio1Executor>),
+    Sendmail(AsyncSendmailTransport<Tokio1Executor>),
+    File(AsyncFileTransport<Tokio1Executor>),
+    Stdout,
+}
+impl MailTransport {
+    pub fn build(config: &MailConfig) -> Result<Self, String> {
+        let smtp = match config.transport {
+            MailTransportKind::Smtps => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host),
+            MailTransportKind::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host),
+            // for a local relay, nothing is encrypted
+            MailTransportKind::Smtp => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host)),
+            MailTransportKind::Sendmail => {
+                return Ok(MailTransport::Sendmail(if config.sendmail_command.is_empty() {
+                    AsyncSendmailTransport::<Tokio1Executor>::new()
+                } else {
+                    AsyncSendmailTransport::<Tokio1Executor>::new_with_command(&config.sendmail_command)
+                }));
+            },
+            MailTransportKind::File => {
+                std::fs::create_dir_all(&config.file_path)
+                    .map_err(|e| format!("cannot create mail directory {}: {}", config.file_path, e))?;
+                return Ok(MailTransport::File(AsyncFileTransport::<Tokio1Executor>::new(&config.file_path)));
+            },
+            MailTransportKind::Stdout => return Ok(MailTransport::Stdout)
+        };
+        let mut smtp = smtp.map_err(|e| e.to_string())?.port(config.port);
+        if !config.smtp_user_name.is_empty() {
+            smtp = smtp.credentials(Credentials::new(config.smtp_user_name.clone(), config.smtp_password.clone()));
+        }
+        Ok(MailTransport::Smtp(smtp.build()))
+    }
+    // only smtp has something to connect to
+    pub async fn test_connection(&self) -> Result<(), String> {
+        match self {
+            MailTransport::Smtp(t) => t.test_connection().await.map(|_| ()).map_err(|e| e.to_string()),
+            _ => Ok(())
+        }
+    }
+    // the flag tells whether the failure is permanent, retrying those is pointless
+    pub async fn send(&self, message: Message) -> Result<(), (String, bool)> {
+        match self {
+            MailTransport::Smtp(t) => t.send(message).await.map(|_| ()).map_err(|e| (e.to_string(), e.is_permanent())),
+            MailTransport::Sendmail(t) => t.send(message).await.map(|_| ()).map_err(|e| (e.to_string(), false)),
+            MailTransport::File(t) => t.send(message).await.map(|_| ()).map_err(|e| (e.to_string(), false)),
+            MailTransport::Stdout => write_message(&mut std::io::stdout().lock(), &message).map_err(|e| (e.to_string(), false))
+        }
+    }
+}
+
+// the raw message, followed by an empty line so consecutive ones stay apart
+fn write_message(out: &mut impl Write, message: &Message) -> std::io::Result<()> {
+    out.write_all(&message.formatted())?;
+    out.write_all(b"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use lettre::message::header::ContentType;
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn message() -> Message {
+        Message::builder()
+            .from("Rustle <noreply@example.com>".parse().unwrap())
+            .to("Reader <reader@example.com>".parse().unwrap())
+            .subject("Sink test")
+            .header(ContentType::TEXT_PLAIN)
+            .body(String::from("hello from the sink"))
+            .unwrap()
+    }
+
+    #[test]
+    fn stdout_sink_writes_the_whole_message() {
+        let mut out = Vec::new();
+        write_message(&mut out, &message()).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("From: Rustle <noreply@example.com>\r\n"));
+        assert!(out.contains("To: Reader <reader@example.com>\r\n"));
+        assert!(out.contains("Subject: Sink test\r\n"));
+        assert!(out.ends_with("hello from the sink\n"));
+    }
+
+    #[tokio::test]
+    async fn file_sink_writes_one_eml_per_message() {
+        let dir = std::env::temp_dir().join(format!("rustle-mail-{}", Uuid::new_v4()));
+        let config = MailConfig {
+            transport: MailTransportKind::File,
+            file_path: dir.to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        let transport = MailTransport::build(&config).unwrap();
+        transport.send(message()).await.unwrap();
+        transport.send(message()).await.unwrap();
+        let files: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().path()).collect();
+        assert_eq!(files.len(), 2);
+        for file in &files {
+            assert_eq!(file.extension().and_then(|e| e.to_str()), Some("eml"));
+            let content = std::fs::read_to_string(file).unwrap();
+            assert!(content.contains("Subject: Sink test\r\n"));
+            assert!(content.contains("hello from the sink"));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,2 +1,4 @@
 pub mod mail;
-pub mod fs;
\ No newline at end of file
+pub mod fs;
+pub mod extension;
+pub mod webhook;
\ No newline at end of file
@@ -0,0 +1,334 @@
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use ntex::http::client::Client;
+use ntex::http::Uri;
+use once_cell::sync::Lazy;
+use tokio::sync::Notify;
+use tracing::{error, info};
+
+use crate::db::{get_db_pool, webhook as webhookDao, webhook::{DeliveryAttempt, DeliveryState, Webhook, WebhookDelivery}};
+use crate::get_config;
+use crate::internal::event::{self, Event, EventKind};
+use crate::types::err::EmptyErrResult;
+use crate::types::service::AppService;
+use crate::utils::backoff::retry_delay;
+use crate::utils::hmac::hmac_signature;
+
+const DUE_BATCH: i32 = 50;
+// the error column holds at most 1024 characters
+const MAX_ERROR_LEN: usize = 1024;
+
+// wakes the worker as soon as something is queued instead of waiting for the next poll
+static WAKE: Lazy<Notify> = Lazy::new(Notify::new);
+
+pub struct WebhookService;
+impl AppService for WebhookService {
+    fn name() -> &'static str {
+        "WebhookService"
+    }
+    async fn initialize() -> EmptyErrResult<()> {
+        for kind in EventKind::ALL{
+            event::subscribe_action(kind, Arc::new(|e| Box::pin(enqueue(e))));
+        }
+        // the http client is not Send, so the worker stays on the local runtime like every other worker
+        ntex::rt::spawn(async {
+            let poll_interval = Duration::from_secs(get_config!(webhook).poll_interval_secs);
+            loop{
+                deliver_due().await;
+                let _ = tokio::time::timeout(poll_interval, WAKE.notified()).await;
+            }
+        });
+        info!("webhook worker started");
+        Ok(())
+    }
+}
+
+async fn enqueue(event: Arc<Event>) -> Result<(), String>{
+    let name = event.kind().as_str();
+    let webhooks = webhookDao::select_enabled_by_event(get_db_pool(), name).await.map_err(|e| e.to_string())?;
+    if webhooks.is_empty(){
+        return Ok(());
+    }
+    let payload = serde_json::to_string(event.as_ref()).map_err(|e| e.to_string())?;
+    for webhook in webhooks{
+        webhookDao::create_delivery(get_db_pool(), webhook.id, name, &payload).await.map_err(|e| e.to_string())?;
+    }
+    WAKE.notify_one();
+    Ok(())
+}
+
+pub fn wake(){
+    WAKE.notify_one();
+}
+
+// claimed deliveries are not due for anyone else until the batch could have timed out
+fn claim_lease() -> chrono::Duration{
+    chrono::Duration::milliseconds(get_config!(webhook).timeout_millis as i64 * DUE_BATCH as i64) + chrono::Duration::minutes(1)
+}
+
+async fn deliver_due(){
+    let deliveries = match webhookDao::claim_due_deliveries(get_db_pool(), DUE_BATCH, claim_lease()).await{
+        Ok(d) => d,
+        Err(e) => {
+            error!("cannot fetch due webhook deliveries: {}", e);
+            return;
+        }
+    };
+    for delivery in deliveries{
+        let webhook = match webhookDao::select_by_id(get_db_pool(), delivery.webhook).await{
+            Ok(w) => w,
+            Err(e) => {
+                error!("cannot fetch webhook {}: {}", delivery.webhook, e);
+                continue;
+            }
+        };
+        let res = match &webhook{
+            Some(w) if w.enabled => send(w, &delivery).await,
+            Some(_) => Err(String::from("webhook is disabled")),
+            None => Err(String::from("webhook is deleted"))
+        };
+        let attempt = next_attempt(&delivery, webhook.as_ref().is_some_and(|w| w.enabled), &res);
+        if let Err(e) = webhookDao::record_attempt(get_db_pool(), delivery.id, &attempt).await{
+            error!("cannot record attempt of webhook delivery {}: {}", delivery.id, e);
+        }
+    }
+}
+
+fn next_attempt<'a>(delivery: &WebhookDelivery, retryable: bool, res: &'a Result<u16, String>) -> DeliveryAttempt<'a>{
+    let config = get_config!(webhook);
+    let attempts = delivery.attempts + 1;
+    let (state, response_code, error) = match res{
+        Ok(code) if (200..300).contains(code) => (DeliveryState::Delivered, Some(*code as i16), None),
+        Ok(code) => (DeliveryState::Pending, Some(*code as i16), Some("unexpected status code")),
+        Err(e) => (DeliveryState::Pending, None, Some(truncate(e)))
+    };
+    let state = if state == DeliveryState::Pending && (!retryable || attempts >= config.max_attempts){
+        DeliveryState::Failed
+    } else {
+        state
+    };
+    let delay = retry_delay(config.base_delay_secs, config.max_delay_secs, attempts);
+    DeliveryAttempt{
+        state,
+        next_attempt_at: Utc::now() + chrono::Duration::seconds(delay),
+        response_code,
+        error
+    }
+}
+
+fn truncate(s: &str) -> &str{
+    match s.char_indices().nth(MAX_ERROR_LEN){
+        Some((i, _)) => &s[..i],
+        None => s
+    }
+}
+
+// receivers check x-rustle-signature against HMAC-SHA256(secret, "{timestamp}.{body}") in base64
+// and reject stale timestamps to prevent replays
+pub async fn send(webhook: &Webhook, delivery: &WebhookDelivery) -> Result<u16, String>{
+    // the name may point somewhere else by now than when the webhook was saved
+    if !get_config!(webhook).allow_private_targets{
+        let uri: Uri = webhook.url.parse().map_err(|_| String::from("invalid url"))?;
+        if is_private_target(&uri).await?{
+            return Err(String::from("target resolves to a private address"));
+        }
+    }
+    let timestamp = Utc::now().timestamp().to_string();
+    let signature = hmac_signature(&webhook.secret, &format!("{}.{}", timestamp, delivery.payload));
+    let client = Client::build()
+        .timeout(ntex::time::Millis(get_config!(webhook).timeout_millis))
+        .finish();
+    let res = client.post(&webhook.url)
+        .header("content-type", "application/json")
+        .header("user-agent", concat!("rustle-blog/", env!("CARGO_PKG_VERSION")))
+        .header("x-rustle-event", delivery.event.as_str())
+        .header("x-rustle-delivery", delivery.id.to_string())
+        .header("x-rustle-timestamp", timestamp.as_str())
+        .header("x-rustle-signature", format!("sha256={signature}"))
+        .send_body(delivery.payload.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(res.status().as_u16())
+}
+
+// the literal host and every address it resolves to must be public
+pub async fn is_private_target(uri: &Uri) -> Result<bool, String>{
+    let host = uri.host().ok_or_else(|| String::from("url without host"))?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.eq_ignore_ascii_case("localhost") || host.to_ascii_lowercase().ends_with(".localhost"){
+        return Ok(true);
+    }
+    if let Ok(ip) = host.parse::<IpAddr>(){
+        return Ok(is_private_ip(ip));
+    }
+    let port = uri.port_u16().unwrap_or(if uri.scheme_str() == Some("https") { 443 } else { 80 });
+    let mut addrs = tokio::net::lookup_host((host, port)).await
+        .map_err(|e| format!("cannot resolve {host}: {e}"))?
+        .peekable();
+    if addrs.peek().is_none(){
+        return Err(format!("{host} resolves to nothing"));
+    }
+    Ok(addrs.any(|a| is_private_ip(a.ip())))
+}
+
+fn is_private_ip(ip: IpAddr) -> bool{
+    match ip{
+        IpAddr::V4(ip) => is_private_ipv4(ip),
+        IpAddr::V6(ip) => {
+            if let Some(v4) = ip.to_ipv4_mapped(){
+                return is_private_ipv4(v4);
+            }
+            let s = ip.segments();
+            // 64:ff9b::/96 is nat64 and 2002::/16 is 6to4, both carry an ipv4 address
+            if s[..6] == [0x64, 0xff9b, 0, 0, 0, 0]{
+                return is_private_ipv4(Ipv4Addr::new((s[6] >> 8) as u8, s[6] as u8, (s[7] >> 8) as u8, s[7] as u8));
+            }
+            if s[0] == 0x2002{
+                return is_private_ipv4(Ipv4Addr::new((s[1] >> 8) as u8, s[1] as u8, (s[2] >> 8) as u8, s[2] as u8));
+            }
+            // fc00::/7 is unique local, fe80::/10 link local, 2001:db8::/32 documentation
+            ip.is_loopback() || ip.is_unspecified() || ip.is_multicast()
+                || (s[0] & 0xfe00) == 0xfc00 || (s[0] & 0xffc0) == 0xfe80 || (s[0] == 0x2001 && s[1] == 0xdb8)
+        }
+    }
+}
+fn is_private_ipv4(ip: Ipv4Addr) -> bool{
+    let o = ip.octets();
+    ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified() || ip.is_broadcast()
+        || ip.is_documentation() || ip.is_multicast()
+        // this network, shared address space (cgnat), ietf protocol assignments, benchmarking, reserved
+        || o[0] == 0
+        || (o[0] == 100 && (o[1] & 0xc0) == 64)
+        || (o[0] == 192 && o[1] == 0 && o[2] == 0)
+        || (o[0] == 198 && (o[1] & 0xfe) == 18)
+        || o[0] >= 240
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    use chrono::Utc;
+
+    use crate::db::webhook::{DeliveryState, Webhook, WebhookDelivery};
+    use crate::utils::hmac::hmac_signature;
+    use super::*;
+
+    fn private(uri: &str) -> bool{
+        futures_util::FutureExt::now_or_never(is_private_target(&uri.parse().unwrap())).unwrap().unwrap()
+    }
+
+    #[test]
+    fn refuses_non_public_literals(){
+        for uri in [
+            "http://127.0.0.1/", "http://10.1.2.3/", "http://100.64.0.1/", "http://100.127.255.254/",
+            "http://169.254.169.254/", "http://0.0.0.0/", "http://198.18.0.1/", "http://[::1]/",
+            "http://[::ffff:127.0.0.1]/", "http://[::ffff:10.0.0.1]/", "http://[64:ff9b::7f00:1]/",
+            "http://[2002:7f00:1::]/", "http://[fd00::1]/", "http://[fe80::1]/", "http://localhost/",
+            "http://api.localhost:8080/"
+        ]{
+            assert!(private(uri), "{uri}");
+        }
+    }
+
+    #[test]
+    fn passes_public_literals(){
+        for uri in ["http://1.1.1.1/", "https://100.128.0.1/", "http://[2606:4700:4700::1111]/", "http://[::ffff:8.8.8.8]/"]{
+            assert!(!private(uri), "{uri}");
+        }
+    }
+
+    fn fixture(url: String) -> (Webhook, WebhookDelivery){
+        let webhook = Webhook{
+            id: 1,
+            url,
+            secret: String::from("secret"),
+            events: String::from("article_published"),
+            enabled: true,
+            created_by: 1,
+            created_at: Utc::now()
+        };
+        let delivery = WebhookDelivery{
+            id: 7,
+            webhook: 1,
+            event: String::from("article_published"),
+            payload: String::from(r#"{"event":"article_published"}"#),
+            state: DeliveryState::Pending,
+            attempts: 0,
+            next_attempt_at: Utc::now(),
+            response_code: None,
+            error: None,
+            created_at: Utc::now(),
+            delivered_at: None
+        };
+        (webhook, delivery)
+    }
+
+    // answers one request with the given status and hands back what it received
+    fn receiver(status: &'static str) -> (String, std::thread::JoinHandle<String>){
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = stream.read(&mut buf).unwrap();
+                received.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&received);
+                if let Some((head, body)) = text.split_once("\r\n\r\n"){
+                    let len = head.lines()
+                        .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                        .unwrap_or(0);
+                    if body.len() >= len{
+                        break;
+                    }
+                }
+                if n == 0{
+                    break;
+                }
+            }
+            stream.write_all(format!("HTTP/1.1 {status}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n").as_bytes()).unwrap();
+            String::from_utf8(received).unwrap()
+        });
+        (url, handle)
+    }
+    fn header<'a>(request: &'a str, name: &str) -> Option<&'a str>{
+        request.lines().find_map(|l| {
+            let (k, v) = l.split_once(':')?;
+            k.eq_ignore_ascii_case(name).then(|| v.trim())
+        })
+    }
+
+    #[ntex::test]
+    async fn signs_deliveries(){
+        crate::internal::config::SETTINGS.edit(|c| c.webhook.allow_private_targets = true);
+        let (url, handle) = receiver("204 No Content");
+        let (webhook, delivery) = fixture(url);
+        assert_eq!(send(&webhook, &delivery).await, Ok(204));
+        let request = handle.join().unwrap();
+        assert!(request.starts_with("POST /hook HTTP/1.1"));
+        assert!(request.ends_with(&delivery.payload));
+        assert_eq!(header(&request, "x-rustle-event"), Some("article_published"));
+        assert_eq!(header(&request, "x-rustle-delivery"), Some("7"));
+        let timestamp = header(&request, "x-rustle-timestamp").unwrap();
+        let expected = format!("sha256={}", hmac_signature("secret", &format!("{}.{}", timestamp, delivery.payload)));
+        assert_eq!(header(&request, "x-rustle-signature"), Some(expected.as_str()));
+    }
+
+    #[ntex::test]
+    async fn reports_failing_receivers(){
+        crate::internal::config::SETTINGS.edit(|c| c.webhook.allow_private_targets = true);
+        let (url, handle) = receiver("500 Internal Server Error");
+        let (webhook, delivery) = fixture(url);
+        assert_eq!(send(&webhook, &delivery).await, Ok(500));
+        handle.join().unwrap();
+        let attempt = next_attempt(&delivery, true, &Ok(500));
+        assert_eq!(attempt.state, DeliveryState::Pending);
+        assert_eq!(attempt.response_code, Some(500));
+    }
+}
@@ -0,0 +1,156 @@
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use futures_util::FutureExt;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::types::err::{AppError, AppResult};
+
+// id is none while filters run, the record is only written once they all passed
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ArticlePublished{
+    pub id: Option<i32>,
+    pub author: i32,
+    pub title: String,
+    pub alias: String,
+    pub content: String
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UserRegistered{
+    pub id: Option<i32>,
+    pub name: String,
+    pub email: String
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CommentPosted{
+    pub id: Option<i32>,
+    pub article: i32,
+    pub author: i32,
+    // the comment this one replies to and its author, both taken from the stored parent
+    #[serde(default)]
+    pub parent: Option<i32>,
+    #[serde(default)]
+    pub parent_author: Option<i32>,
+    pub content: String
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum Event{
+    ArticlePublished(ArticlePublished),
+    // carries the state after the update
+    ArticleUpdated(ArticlePublished),
+    UserRegistered(UserRegistered),
+    CommentPosted(CommentPosted)
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind{
+    ArticlePublished,
+    ArticleUpdated,
+    UserRegistered,
+    CommentPosted
+}
+impl Event{
+    pub fn kind(&self) -> EventKind{
+        match self{
+            Event::ArticlePublished(_) => EventKind::ArticlePublished,
+            Event::ArticleUpdated(_) => EventKind::ArticleUpdated,
+            Event::UserRegistered(_) => EventKind::UserRegistered,
+            Event::CommentPosted(_) => EventKind::CommentPosted
+        }
+    }
+}
+impl EventKind{
+    pub const ALL: [EventKind; 4] = [
+        EventKind::ArticlePublished,
+        EventKind::ArticleUpdated,
+        EventKind::UserRegistered,
+        EventKind::CommentPosted
+    ];
+    pub fn as_str(&self) -> &'static str{
+        match self{
+            EventKind::ArticlePublished => "article_published",
+            EventKind::ArticleUpdated => "article_updated",
+            EventKind::UserRegistered => "user_registered",
+            EventKind::CommentPosted => "comment_posted"
+        }
+    }
+    pub fn parse(s: &str) -> Option<Self>{
+        match s{
+            "article_published" => Some(EventKind::ArticlePublished),
+            "article_updated" => Some(EventKind::ArticleUpdated),
+            "user_registered" => Some(EventKind::UserRegistered),
+            "comment_posted" => Some(EventKind::CommentPosted),
+            _ => None
+        }
+    }
+}
+
+pub enum HookError{
+    // stops the operation, the reason is shown to the user
+    Veto(String),
+    // the hook is skipped and the event goes on unchanged
+    Failed(String)
+}
+pub type HookFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+pub type FilterHook = Arc<dyn Fn(Event) -> HookFuture<Result<Event, HookError>> + Send + Sync>;
+pub type ActionHook = Arc<dyn Fn(Arc<Event>) -> HookFuture<Result<(), String>> + Send + Sync>;
+
+static FILTERS: Lazy<DashMap<EventKind, Vec<FilterHook>>> = Lazy::new(|| DashMap::new());
+static ACTIONS: Lazy<DashMap<EventKind, Vec<ActionHook>>> = Lazy::new(|| DashMap::new());
+
+pub fn subscribe_filter(kind: EventKind, hook: FilterHook){
+    FILTERS.entry(kind).or_default().push(hook);
+}
+pub fn subscribe_action(kind: EventKind, hook: ActionHook){
+    ACTIONS.entry(kind).or_default().push(hook);
+}
+
+// filters are awaited in subscription order before the operation is committed,
+// each one sees what the previous returned
+pub async fn filter(mut event: Event) -> AppResult<Event>{
+    let kind = event.kind();
+    let hooks = match FILTERS.get(&kind){
+        Some(h) => h.clone(),
+        None => return Ok(event)
+    };
+    for hook in hooks{
+        let input = event.clone();
+        match AssertUnwindSafe(async { hook(input).await }).catch_unwind().await{
+            Ok(Ok(e)) if e.kind() == kind => event = e,
+            Ok(Ok(_)) => error!("a filter hook of {} returned another event, it is ignored", kind.as_str()),
+            Ok(Err(HookError::Veto(reason))) => {
+                warn!("{} vetoed: {}", kind.as_str(), reason);
+                return Err(AppError::new_user(ntex::http::StatusCode::FORBIDDEN, reason));
+            },
+            Ok(Err(HookError::Failed(e))) => error!("a filter hook of {} failed: {}", kind.as_str(), e),
+            Err(_) => error!("a filter hook of {} panicked", kind.as_str())
+        }
+    }
+    Ok(event)
+}
+
+// actions run detached after the operation is committed, the request never waits for them
+pub fn dispatch(event: Event){
+    let kind = event.kind();
+    let hooks = match ACTIONS.get(&kind){
+        Some(h) => h.clone(),
+        None => return
+    };
+    let event = Arc::new(event);
+    for hook in hooks{
+        let event = event.clone();
+        tokio::spawn(async move {
+            match AssertUnwindSafe(async { hook(event).await }).catch_unwind().await{
+                Ok(Ok(_)) => {},
+                Ok(Err(e)) => error!("an action hook of {} failed: {}", kind.as_str(), e),
+                Err(_) => error!("an action hook of {} panicked", kind.as_str())
+            }
+        });
+    }
+}
@@ -1,3 +1,4 @@
-pub mod config;
+pub mod config;
+pub mod event;
 pub mod log;
 pub mod arg;
\ No newline at end of file
@@ -1,10 +1,18 @@
 use crate::db::DBService;
+use crate::external::extension::ExtensionService;
 use crate::external::fs::FsService;
-use crate::external::mail::MailService;
+use crate::external::mail::{dkim, MailService};
+use crate::external::webhook::WebhookService;
 use crate::internal::log;
 use crate::internal::config::ConfigService;
 use crate::providers::auth::service::RBACService;
+use crate::providers::cache::service::CacheService;
+use crate::providers::newsletter::service::NewsletterService;
+use crate::providers::notification::service::NotificationService;
+use crate::providers::user::service::UserService;
+use crate::types::arg::Command;
 use crate::types::service;
+use crate::get_args;
 
 mod internal;
 mod providers;
@@ -20,12 +28,26 @@ async fn main()  {
     tracing::info!("Rustle Blog {}({}), compiled on {}",
         env!("BUILD_VERSION"), env!("GIT_HASH"), env!("BUILD_TIME"));
     tracing::info!("running on: {}", os_info::get());
+    if let Some(command) = &get_args!().command {
+        if internal::config::load_config().is_ok() {
+            let _ = match command {
+                Command::DkimRecord => dkim::print_record()
+            };
+        }
+        return;
+    }
     if !service::init_services!(
         ConfigService,
         DBService,
+        CacheService,
         RBACService,
+        UserService,
         MailService,
-        FsService
+        ExtensionService,
+        FsService,
+        WebhookService,
+        NewsletterService,
+        NotificationService
     ) {
         return;
     }
@@ -1,8 +1,11 @@
 use ntex::{web, Middleware, Service, ServiceCtx};
+use crate::providers::user::service::{check_restriction, UserUserError::PasswordResetRequired};
 use crate::types::err::GlobalUserError::StatusUnauthorized;
 use crate::utils::paseto;
 use crate::get_config;
 pub struct Auth;
+// the only thing a user with a forced password reset may do
+const PASSWORD_CHANGE_PATH: [&str; 3] = ["v1", "user", "change_password"];
 pub struct UserIdentity{
     pub id: i32
 }
@@ -40,8 +43,30 @@ where
             &(get_config!(security).auth_token_secret),
             token
         ).map_err(|_| StatusUnauthorized.to_error())?;
+        // a token outlives a ban, so the status is checked on every request
+        if check_restriction(user_id)? && !is_password_change(req.path()) {
+            return Err(PasswordResetRequired.to_error().into());
+        }
         req.extensions_mut().insert(UserIdentity{id: user_id});
         let res = ctx.call(&self.service, req).await?;
         Ok(res)
     }
-}
\ No newline at end of file
+}
+// by segment, so that doubled or trailing slashes routed to the same handler are let through as well
+fn is_password_change(path: &str) -> bool {
+    path.split('/').filter(|s| !s.is_empty()).eq(PASSWORD_CHANGE_PATH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn password_change_path_is_normalized() {
+        assert!(is_password_change("/v1/user/change_password"));
+        assert!(is_password_change("/v1/user/change_password/"));
+        assert!(is_password_change("/v1/user//change_password"));
+        assert!(!is_password_change("/v1/user/change_password/x"));
+        assert!(!is_password_change("/v1/user/me"));
+    }
+}
@@ -0,0 +1,56 @@
+use std::sync::Arc;
+
+use ntex::http::StatusCode;
+use ntex::{web, Middleware, Service, ServiceCtx};
+
+use crate::external::extension::{has_http_hooks, run_http_hooks, HookRequest};
+
+pub struct ExtensionHook;
+
+impl<S> Middleware<S> for ExtensionHook {
+    type Service = ExtensionHookMiddleware<S>;
+
+    fn create(&self, service: S) -> Self::Service {
+        ExtensionHookMiddleware { service }
+    }
+}
+
+pub struct ExtensionHookMiddleware<S> {
+    service: S,
+}
+
+impl<S, Err> Service<web::WebRequest<Err>> for ExtensionHookMiddleware<S>
+where
+    S: Service<web::WebRequest<Err>, Response = web::WebResponse, Error = web::Error>,
+    Err: web::ErrorRenderer,
+{
+    type Response = web::WebResponse;
+    type Error = web::Error;
+
+    ntex::forward_poll_ready!(service);
+
+    async fn call(&self, req: web::WebRequest<Err>, ctx: ServiceCtx<'_, Self>) -> Result<Self::Response, Self::Error> {
+        if !has_http_hooks(){
+            return ctx.call(&self.service, req).await;
+        }
+        // hooks only see the head of the request, the body is left to the handler
+        let hook_request = HookRequest{
+            method: req.method().to_string(),
+            path: req.path().to_string(),
+            query: req.query_string().to_string(),
+            headers: req.headers().iter()
+                .filter_map(|(k, v)| Some((k.to_string(), v.to_str().ok()?.to_string())))
+                .collect()
+        };
+        let Some(hook_response) = run_http_hooks(Arc::new(hook_request)).await else {
+            return ctx.call(&self.service, req).await;
+        };
+        let mut builder = web::HttpResponse::build(
+            StatusCode::from_u16(hook_response.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+        );
+        for (name, value) in hook_response.headers.iter(){
+            builder.header(name.as_str(), value.as_str());
+        }
+        Ok(req.into_response(builder.body(hook_response.body)))
+    }
+}
@@ -1,5 +1,7 @@
 pub mod auth;
+pub mod extension;
 pub mod log;
 
 pub use auth::Auth;
+pub use extension::ExtensionHook;
 pub use log::Log;
\ No newline at end of file
@@ -3,13 +3,19 @@ use chrono::Utc;
 use ntex::web::{self, Responder};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use crate::providers::auth::permission::{COMMENT, CREATE_ARTICLE};
+use crate::providers::audit::service::{self as audit, Target};
+use crate::providers::auth::policy::{self, Action, Resource};
 use crate::providers::auth::service::check_permission_api;
-use crate::db::{get_db_pool, article::{ArticleFilterable, ArticleSortable, ArticlePublicBrief}};
-use crate::db::{article as articleDao, article::Article};
+use crate::db::{get_db_pool, article::{ArticleAcl, ArticleFilterable, ArticleGrant, ArticleSortable, ArticlePublicBrief}};
+use crate::db::{article as articleDao, article::Article, file as fileDao, file::FileRefKind, user as userDao};
+use crate::external::extension::apply_content_filters;
+use crate::providers::file::service::get_accessible_file;
+use super::service::{attach_file, post_comment, publish_article, update_article};
 use crate::utils::request::{get_user_id, RequestPayload};
 use validator::Validate;
 use crate::types::err::AppResult;
-use crate::types::err::GlobalUserError::TooMaxParameter;
+use crate::types::err::GlobalUserError::{NotFound, TooMaxParameter};
 use crate::middlewares::Auth;
 
 pub fn init(cfg: &mut web::ServiceConfig){
@@ -19,6 +25,14 @@ pub fn init(cfg: &mut web::ServiceConfig){
             .service(
                 web::scope("/").wrap(Auth)
                     .service(create)
+                    .service(update)
+                    .service(comment)
+                    .service(manage_list)
+                    .service(attach)
+                    .service(detach)
+                    .service(grants)
+                    .service(grant)
+                    .service(revoke)
             )
     );
 }
@@ -43,6 +57,11 @@ struct CreateReq<'a> {
     #[validate(length(min = 0, max = 1073741823))]
     #[serde(borrow)]
     pub generated: Cow<'a,str>,
+    #[serde(default)]
+    pub cover: Option<i32>,
+    #[serde(default)]
+    #[validate(length(max = 100))]
+    pub attachments: Vec<i32>,
 }
 #[web::post("/create")]
 async fn create(req: web::HttpRequest, mut payload: web::types::Payload) -> AppResult<impl Responder> {
@@ -51,9 +70,13 @@ async fn create(req: web::HttpRequest, mut payload: web::types::Payload) -> AppR
     req_data.validate()?;
     
     let user_id = get_user_id(&req);
-    check_permission_api(Some(user_id), "CREATE_ARTICLE").await?;
+    check_permission_api(Some(user_id), CREATE_ARTICLE).await?;
+    // check every referenced file before anything is written
+    for file_id in req_data.cover.iter().chain(req_data.attachments.iter()){
+        get_accessible_file(user_id, *file_id).await?;
+    }
     let draft_content_id = articleDao::save_content(get_db_pool(), &req_data.draft).await?;
-    let content_id = articleDao::save_content(get_db_pool(), &ammonia::clean(&req_data.generated)).await?;
+    let generated = apply_content_filters("article", req_data.generated.to_string()).await;
     let article_object = Article{
         author: user_id,
         title: req_data.title.to_string(),
@@ -61,12 +84,86 @@ async fn create(req: web::HttpRequest, mut payload: web::types::Payload) -> AppR
         is_pinned: req_data.is_pinned,
         is_commentable: req_data.is_commentable,
         draft_content_id,
-        content_id,
         created_at: Utc::now(),
         updated_at: Utc::now(),
         ..Default::default()
     };
-    let id = articleDao::create(get_db_pool(), &article_object).await?;
+    let id = publish_article(article_object, generated, req_data.cover, &req_data.attachments).await?;
+    audit::record(&req, "article.create", Some(Target::Article(id)), json!({
+        "title": { "new": req_data.title },
+        "alias": { "new": req_data.alias }
+    })).await;
+    Ok(web::HttpResponse::Ok().body(
+        json!({
+            "id": id
+        })
+    ))
+}
+
+#[derive(Debug, Validate, Deserialize)]
+struct UpdateReq<'a> {
+    pub id: i32,
+    #[validate(length(min = 1, max = 255))]
+    #[serde(borrow)]
+    pub title: Cow<'a,str>,
+    #[validate(length(min = 1, max = 255))]
+    #[serde(borrow)]
+    pub alias: Cow<'a,str>,
+    #[validate(range(min = 1, max = 3))]
+    pub public_state: i16,
+    pub is_pinned: bool,
+    pub is_commentable: bool,
+    #[validate(length(min = 0, max = 1073741823))]
+    #[serde(borrow)]
+    pub draft: Cow<'a,str>,
+    #[validate(length(min = 0, max = 1073741823))]
+    #[serde(borrow)]
+    pub generated: Cow<'a,str>,
+}
+#[web::post("/update")]
+async fn update(req: web::HttpRequest, mut payload: web::types::Payload) -> AppResult<impl Responder> {
+    let mut payload = RequestPayload::new(&mut payload);
+    let req_data: UpdateReq<'_> = payload.parse().await?;
+    req_data.validate()?;
+
+    policy::authorize(get_user_id(&req), Action::Edit, Resource::Article(req_data.id)).await?;
+    let old = articleDao::select_by_id(get_db_pool(), req_data.id).await?.ok_or(NotFound)?;
+    let draft_content_id = articleDao::save_content(get_db_pool(), &req_data.draft).await?;
+    let generated = apply_content_filters("article", req_data.generated.to_string()).await;
+    let article_object = Article{
+        title: req_data.title.to_string(),
+        alias: req_data.alias.to_string(),
+        public_state: req_data.public_state,
+        is_pinned: req_data.is_pinned,
+        is_commentable: req_data.is_commentable,
+        draft_content_id,
+        updated_at: Utc::now(),
+        ..old
+    };
+    update_article(article_object, generated).await?;
+    audit::record(&req, "article.update", Some(Target::Article(req_data.id)), json!({
+        "title": { "old": old.title, "new": req_data.title },
+        "alias": { "old": old.alias, "new": req_data.alias }
+    })).await;
+    Ok(web::HttpResponse::Ok().finish())
+}
+
+#[derive(Debug, Validate, Deserialize)]
+struct CommentReq {
+    article: i32,
+    // the comment replied to, its author is told about the reply
+    #[serde(default)]
+    parent: Option<i32>,
+    #[validate(length(min = 1, max = 10000))]
+    content: String,
+}
+#[web::post("/comment")]
+async fn comment(req: web::HttpRequest, req_data: web::types::Json<CommentReq>) -> AppResult<impl Responder> {
+    req_data.validate()?;
+    let user_id = get_user_id(&req);
+    check_permission_api(Some(user_id), COMMENT).await?;
+    let req_data = req_data.into_inner();
+    let id = post_comment(req_data.article, user_id, req_data.parent, req_data.content).await?;
     Ok(web::HttpResponse::Ok().body(
         json!({
             "id": id
@@ -96,6 +193,7 @@ async fn list(mut payload: web::types::Payload) -> AppResult<impl Responder> {
     let db_res = articleDao::list::<ArticlePublicBrief>(get_db_pool(),
         req_data.limit,
         (req_data.page-1).checked_mul(req_data.limit).ok_or(TooMaxParameter)?,
+        None,
         req_data.filter,
         req_data.sort).await?;
     Ok(web::HttpResponse::Ok().json(
@@ -105,4 +203,94 @@ async fn list(mut payload: web::types::Payload) -> AppResult<impl Responder> {
         }
     ))
 }
+#[derive(Debug, Serialize)]
+struct ManageListRes {
+    total: i32,
+    articles: Vec<Article>
+}
+// the articles the user may work on: all with MANAGE_ARTICLE, otherwise own and granted ones
+#[web::post("/manage_list")]
+async fn manage_list(mut payload: web::types::Payload, req: web::HttpRequest) -> AppResult<impl Responder> {
+    let mut payload = RequestPayload::new(&mut payload);
+    let req_data: ListReq = payload.parse().await?;
+    req_data.validate()?;
+    let scope = policy::article_scope(get_user_id(&req)).await;
+    let db_res = articleDao::list::<Article>(get_db_pool(),
+        req_data.limit,
+        (req_data.page-1).checked_mul(req_data.limit).ok_or(TooMaxParameter)?,
+        scope.accessible_by(),
+        req_data.filter,
+        req_data.sort).await?;
+    Ok(web::HttpResponse::Ok().json(
+        &ManageListRes{
+            total: db_res.0,
+            articles: db_res.1
+        }
+    ))
+}
 
+
+#[derive(Debug, Deserialize)]
+struct AttachReq {
+    article: i32,
+    file: i32,
+    kind: FileRefKind
+}
+#[web::post("/attach")]
+async fn attach(req: web::HttpRequest, req_data: web::types::Json<AttachReq>) -> AppResult<impl Responder> {
+    let user_id = get_user_id(&req);
+    policy::authorize(user_id, Action::Edit, Resource::Article(req_data.article)).await?;
+    attach_file(user_id, req_data.article, req_data.file, req_data.kind).await?;
+    Ok(web::HttpResponse::Ok().finish())
+}
+#[web::post("/detach")]
+async fn detach(req: web::HttpRequest, req_data: web::types::Json<AttachReq>) -> AppResult<impl Responder> {
+    policy::authorize(get_user_id(&req), Action::Edit, Resource::Article(req_data.article)).await?;
+    fileDao::detach(get_db_pool(), req_data.article, req_data.file, req_data.kind).await?;
+    Ok(web::HttpResponse::Ok().finish())
+}
+
+#[web::get("/grants/{article}")]
+async fn grants(path: web::types::Path<i32>, req: web::HttpRequest) -> AppResult<impl Responder> {
+    let article = path.into_inner();
+    policy::authorize(get_user_id(&req), Action::Read, Resource::Article(article)).await?;
+    let grants: Vec<ArticleAcl> = articleDao::list_grants(get_db_pool(), article).await?;
+    Ok(web::HttpResponse::Ok().json(&grants))
+}
+#[derive(Debug, Deserialize)]
+struct GrantReq {
+    article: i32,
+    user: i32,
+    level: ArticleGrant
+}
+#[web::post("/grant")]
+async fn grant(req: web::HttpRequest, req_data: web::types::Json<GrantReq>) -> AppResult<impl Responder> {
+    let user_id = get_user_id(&req);
+    policy::authorize(user_id, Action::Share, Resource::Article(req_data.article)).await?;
+    // the author already has full access
+    let author = articleDao::select_author(get_db_pool(), req_data.article).await?.ok_or(NotFound)?;
+    if author == req_data.user || userDao::select_by_id(get_db_pool(), req_data.user).await?.is_none() {
+        return Err(NotFound.into());
+    }
+    let old = articleDao::select_grant(get_db_pool(), req_data.article, req_data.user).await?;
+    articleDao::set_grant(get_db_pool(), req_data.article, req_data.user, req_data.level, user_id).await?;
+    audit::record(&req, "article.grant", Some(Target::Article(req_data.article)), json!({
+        "user": req_data.user,
+        "level": { "old": old, "new": req_data.level }
+    })).await;
+    Ok(web::HttpResponse::Ok().finish())
+}
+#[derive(Debug, Deserialize)]
+struct RevokeReq {
+    article: i32,
+    user: i32
+}
+#[web::post("/revoke")]
+async fn revoke(req: web::HttpRequest, req_data: web::types::Json<RevokeReq>) -> AppResult<impl Responder> {
+    policy::authorize(get_user_id(&req), Action::Share, Resource::Article(req_data.article)).await?;
+    if !articleDao::delete_grant(get_db_pool(), req_data.article, req_data.user).await? {
+        return Err(NotFound.into());
+    }
+    audit::record(&req, "article.revoke", Some(Target::Article(req_data.article)), json!({ "user": req_data.user })).await;
+    Ok(web::HttpResponse::Ok().finish())
+}
@@ -0,0 +1,113 @@
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use rustle_derive::ErrorHelper;
+
+use crate::db::{get_db_pool, article as articleDao, article::Article, comment as commentDao, file as fileDao, file::FileRefKind};
+use crate::internal::event::{self, ArticlePublished, CommentPosted, Event};
+use crate::providers::file::service::get_accessible_file;
+use crate::types::err::AppResult;
+use crate::types::err::GlobalUserError::NotFound;
+use crate::utils::rate_limit::RateLimiter;
+
+// comments a user may post in ten minutes, every one of them may notify someone
+static COMMENT_LIMIT: Lazy<RateLimiter> = Lazy::new(|| RateLimiter::new(Duration::from_secs(10 * 60), 30));
+
+#[derive(ErrorHelper)]
+#[err(user, default_msg)]
+pub enum ArticleUserError{
+    #[err(code = 403)]
+    NotCommentable,
+    #[err(code = 429)]
+    TooManyComments,
+}
+
+// the file must be accessible to the user, otherwise anyone could pin others' uploads
+pub async fn attach_file(user_id: i32, article_id: i32, file_id: i32, kind: FileRefKind) -> AppResult<()>{
+    let file = get_accessible_file(user_id, file_id).await?;
+    fileDao::attach(get_db_pool(), article_id, file.id, kind).await?;
+    Ok(())
+}
+
+// event filters may rewrite the title, alias and content or veto, the result is still sanitized
+pub async fn publish_article(mut article: Article, content: String, cover: Option<i32>, attachments: &[i32]) -> AppResult<i32>{
+    let Event::ArticlePublished(mut published) = event::filter(Event::ArticlePublished(ArticlePublished{
+        id: None,
+        author: article.author,
+        title: article.title.clone(),
+        alias: article.alias.clone(),
+        content
+    })).await? else {
+        unreachable!("filters keep the kind of an event")
+    };
+    published.content = ammonia::clean(&published.content);
+    article.title = published.title.clone();
+    article.alias = published.alias.clone();
+    article.content_id = articleDao::save_content(get_db_pool(), &published.content).await?;
+    let id = articleDao::create(get_db_pool(), &article, cover, attachments).await?;
+    published.id = Some(id);
+    event::dispatch(Event::ArticlePublished(published));
+    Ok(id)
+}
+
+// the same filters as on publishing run on the new state, actions get it once it is saved
+pub async fn update_article(mut article: Article, content: String) -> AppResult<()>{
+    let Event::ArticleUpdated(mut updated) = event::filter(Event::ArticleUpdated(ArticlePublished{
+        id: Some(article.id),
+        author: article.author,
+        title: article.title.clone(),
+        alias: article.alias.clone(),
+        content
+    })).await? else {
+        unreachable!("filters keep the kind of an event")
+    };
+    updated.content = ammonia::clean(&updated.content);
+    article.title = updated.title.clone();
+    article.alias = updated.alias.clone();
+    article.content_id = articleDao::save_content(get_db_pool(), &updated.content).await?;
+    if !articleDao::update(get_db_pool(), &article).await?{
+        return Err(NotFound.into());
+    }
+    event::dispatch(Event::ArticleUpdated(updated));
+    Ok(())
+}
+
+// filters may rewrite the content or veto, everything else is decided here;
+// actions are told once the comment is stored
+pub async fn post_comment(article: i32, author: i32, parent: Option<i32>, content: String) -> AppResult<i32>{
+    if !COMMENT_LIMIT.check(&author.to_string()){
+        return Err(ArticleUserError::TooManyComments.into());
+    }
+    let article = articleDao::select_by_id(get_db_pool(), article).await?.ok_or(NotFound)?;
+    if !article.is_commentable{
+        return Err(ArticleUserError::NotCommentable.into());
+    }
+    let parent_author = match parent {
+        Some(parent) => Some(commentDao::select_by_id(get_db_pool(), parent).await?
+            .filter(|p| p.article == article.id)
+            .ok_or(NotFound)?
+            .author),
+        None => None
+    };
+    let Event::CommentPosted(filtered) = event::filter(Event::CommentPosted(CommentPosted{
+        id: None,
+        article: article.id,
+        author,
+        parent,
+        parent_author,
+        content
+    })).await? else {
+        unreachable!("filters keep the kind of an event")
+    };
+    let content = ammonia::clean(&filtered.content);
+    let id = commentDao::create(get_db_pool(), article.id, author, parent, &content).await?;
+    event::dispatch(Event::CommentPosted(CommentPosted{
+        id: Some(id),
+        article: article.id,
+        author,
+        parent,
+        parent_author,
+        content
+    }));
+    Ok(id)
+}
@@ -0,0 +1,130 @@
+use std::future::ready;
+use futures_util::{stream, Stream, StreamExt};
+use ntex::util::Bytes;
+use ntex::web::{self, Responder};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+use crate::db::{audit as auditDao, audit::{AuditEntry, AuditFilter}, get_db_pool};
+use crate::middlewares::Auth;
+use crate::providers::auth::permission::VIEW_AUDIT_LOG;
+use crate::providers::auth::service::check_permission_api;
+use crate::types::err::AppResult;
+use crate::types::err::GlobalUserError::TooMaxParameter;
+use crate::utils::csv::csv_field;
+use crate::utils::request::get_user_id;
+
+const EXPORT_BATCH: i32 = 1000;
+
+pub fn init(cfg: &mut web::ServiceConfig){
+    cfg.service(
+        web::scope("/v1/audit").wrap(Auth)
+            .service(list)
+            .service(export)
+    );
+}
+
+#[derive(Debug, Validate, Deserialize)]
+struct ListReq {
+    #[serde(default)]
+    filter: AuditFilter,
+    #[validate(range(min = 0, max = 100))]
+    limit: i32,
+    #[validate(range(min = 1,))]
+    page: i32
+}
+#[derive(Debug, Serialize)]
+struct ListRes {
+    total: i32,
+    entries: Vec<AuditEntry>
+}
+#[web::post("/list")]
+async fn list(req: web::HttpRequest, req_data: web::types::Json<ListReq>) -> AppResult<impl Responder> {
+    req_data.validate()?;
+    check_permission_api(Some(get_user_id(&req)), VIEW_AUDIT_LOG).await?;
+    let (total, entries) = auditDao::list(get_db_pool(),
+        &req_data.filter,
+        req_data.limit,
+        (req_data.page-1).checked_mul(req_data.limit).ok_or(TooMaxParameter)?).await?;
+    Ok(web::HttpResponse::Ok().json(&ListRes{ total, entries }))
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum ExportFormat {
+    Csv,
+    Json
+}
+#[derive(Debug, Deserialize)]
+struct ExportReq {
+    #[serde(default)]
+    filter: AuditFilter,
+    format: ExportFormat
+}
+// oldest first, written out batch by batch so that the size of the log does not matter
+#[web::post("/export")]
+async fn export(req: web::HttpRequest, req_data: web::types::Json<ExportReq>) -> AppResult<impl Responder> {
+    check_permission_api(Some(get_user_id(&req)), VIEW_AUDIT_LOG).await?;
+    let ExportReq { filter, format } = req_data.into_inner();
+    let (content_type, file_name) = match format {
+        ExportFormat::Csv => ("text/csv; charset=utf-8", "audit.csv"),
+        ExportFormat::Json => ("application/json", "audit.json")
+    };
+    Ok(web::HttpResponse::Ok()
+        .content_type(content_type)
+        .header("content-disposition", format!("attachment; filename=\"{}\"", file_name))
+        .streaming(Box::pin(export_stream(filter, format))))
+}
+
+struct ExportCursor {
+    filter: AuditFilter,
+    format: ExportFormat,
+    // 0 until the first row is written
+    after: i64,
+}
+fn export_stream(filter: AuditFilter, format: ExportFormat) -> impl Stream<Item = Result<Bytes, sqlx::Error>> {
+    let header = match format {
+        ExportFormat::Csv => "id,created_at,actor,action,target_type,target_id,request_id,ip,user_agent,diff\n",
+        ExportFormat::Json => "["
+    };
+    let cursor = ExportCursor { filter, format, after: 0 };
+    let rows = stream::unfold(Some(cursor), |cursor| async move {
+        let mut cursor = cursor?;
+        // the response has started already, a failure can only cut it short
+        let batch = match auditDao::select_after(get_db_pool(), &cursor.filter, cursor.after, EXPORT_BATCH).await {
+            Ok(b) => b,
+            Err(e) => return Some((Err(e), None))
+        };
+        if batch.is_empty() {
+            let footer = if cursor.format == ExportFormat::Json { "]" } else { "" };
+            return Some((Ok(Bytes::from_static(footer.as_bytes())), None));
+        }
+        let mut chunk = String::new();
+        for e in &batch {
+            match cursor.format {
+                ExportFormat::Csv => chunk.push_str(&csv_row(e)),
+                ExportFormat::Json => {
+                    if cursor.after != 0 {
+                        chunk.push(',');
+                    }
+                    chunk.push_str(&serde_json::to_string(e).unwrap_or_else(|_| "null".to_string()));
+                }
+            }
+            cursor.after = e.id;
+        }
+        Some((Ok(Bytes::from(chunk)), Some(cursor)))
+    });
+    stream::once(ready(Ok(Bytes::from_static(header.as_bytes())))).chain(rows)
+}
+fn csv_row(e: &AuditEntry) -> String {
+    format!("{},{},{},{},{},{},{},{},{},{}\n",
+        e.id,
+        e.created_at.to_rfc3339(),
+        e.actor.map(|a| a.to_string()).unwrap_or_default(),
+        csv_field(&e.action),
+        csv_field(e.target_type.as_deref().unwrap_or_default()),
+        e.target_id.map(|t| t.to_string()).unwrap_or_default(),
+        csv_field(&e.request_id),
+        csv_field(e.ip.as_deref().unwrap_or_default()),
+        csv_field(e.user_agent.as_deref().unwrap_or_default()),
+        csv_field(&e.diff.0.to_string()))
+}
@@ -0,0 +1,52 @@
+use ntex::web;
+use tracing::error;
+
+use crate::db::{audit as auditDao, audit::NewAuditEntry, get_db_pool};
+use crate::middlewares::log::RequestId;
+use crate::utils::request::{get_client_ip, get_user_id};
+
+// the user agent column holds at most 255 characters
+const MAX_USER_AGENT_LEN: usize = 255;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Target {
+    User(i32),
+    Role(i32),
+    Article(i32),
+}
+impl Target {
+    fn split(self) -> (&'static str, i32) {
+        match self {
+            Target::User(id) => ("user", id),
+            Target::Role(id) => ("role", id),
+            Target::Article(id) => ("article", id)
+        }
+    }
+}
+
+// the signed in user is the actor
+pub async fn record(req: &web::HttpRequest, action: &str, target: Option<Target>, diff: serde_json::Value) {
+    let actor = Some(get_user_id(req)).filter(|&id| id != 0);
+    record_as(req, actor, action, target, diff).await
+}
+// the change has already happened, so a failed write is logged instead of failing the request
+pub async fn record_as(req: &web::HttpRequest, actor: Option<i32>, action: &str, target: Option<Target>, diff: serde_json::Value) {
+    let request_id = req.extensions().get::<RequestId>().cloned().unwrap_or_default();
+    let ip = get_client_ip(req).map(|ip| ip.to_string());
+    let user_agent = req.headers().get("user-agent")
+        .and_then(|h| h.to_str().ok())
+        .map(|ua| ua.chars().take(MAX_USER_AGENT_LEN).collect::<String>());
+    let (target_type, target_id) = target.map(Target::split).unzip();
+    if let Err(e) = auditDao::create(get_db_pool(), &NewAuditEntry {
+        actor,
+        action,
+        target_type,
+        target_id,
+        request_id: &request_id.0,
+        ip: ip.as_deref(),
+        user_agent: user_agent.as_deref(),
+        diff: &diff,
+    }).await {
+        error!("cannot write audit log {} by {:?}: {}", action, actor, e);
+    }
+}
@@ -1,19 +1,29 @@
 use std::sync;
 use ntex::web::{self, Responder};
-use crate::db::rbac::{self as rbacDao, role_vec_to_str, Role, DEFAULT_ROLE_STR};
+use crate::db::rbac::{self as rbacDao, RBACUserError, Role};
 use crate::middlewares::Auth;
-use crate::providers::auth::service::check_permission_api;
-use crate::types::err::GlobalUserError::{CredentialUnauthorized, TooMaxParameter};
-use crate::db::{user as userDao, get_db_pool};
+use crate::db::notification::NotificationKind;
+use crate::external::fs::embed::LOCALES;
+use crate::providers::auth::permission::{self, MANAGE_ROLE};
+use crate::providers::auth::service::{self as authService, check_permission_api};
+use crate::providers::cache::service::{self as cacheService, CacheChange};
+use crate::providers::audit::service::{self as audit, Target};
+use crate::providers::notification;
+use crate::providers::user::service::{check_restriction, register_user};
+use crate::types::err::GlobalUserError::{CredentialUnauthorized, NotFound, TooMaxParameter, UnknownLang};
+use crate::db::user::UserIdName;
+use crate::db::{user as userDao, quota as quotaDao, quota::Quota, get_db_pool};
 use crate::utils::request::{get_user_id, RequestPayload};
 use crate::utils::{paseto, password_salt};
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use serde_json::json;
+use fluent_templates::{LanguageIdentifier, Loader};
 use validator::Validate;
 use crate::{get_args, get_config};
 use crate::types::err::AppResult;
 use crate::utils::paseto::generate_access_token;
+use tracing::error;
 
 pub static ONCE_INIT: sync::Once = sync::Once::new();
 pub fn init(cfg: &mut web::ServiceConfig){
@@ -28,9 +38,20 @@ pub fn init(cfg: &mut web::ServiceConfig){
                 }).service(
                     web::scope("/").wrap(Auth)
                             .service(modify_user_roles)
+                            .service(assign_role)
+                            .service(unassign_role)
+                            .service(get_role)
+                            .service(update_role)
+                            .service(role_users)
                             .service(remove_role)
                             .service(list_roles)
                             .service(add_role)
+                            .service(modify_role_permission)
+                            .service(add_role_parent)
+                            .service(remove_role_parent)
+                            .service(explain_permission)
+                            .service(set_role_quota)
+                            .service(list_permissions)
                 )
         );
         if get_args!(debug) {
@@ -50,48 +71,113 @@ struct SignInReq<'a> {
     pub password: Cow<'a, str>,
 }
 #[web::post("/sign_in")]
-async fn sign_in(mut payload: web::types::Payload) -> AppResult<impl Responder> {
+async fn sign_in(mut payload: web::types::Payload, req: web::HttpRequest) -> AppResult<impl Responder> {
     let mut payload = RequestPayload::new(&mut payload);
     let req_data: SignInReq<'_> = payload.parse().await?;
     req_data.validate()?;
     let user_data = userDao::select_by_identity_with_password(get_db_pool(), &req_data.name).await?;
     if user_data.is_none() {
+        audit::record_as(&req, None, "auth.sign_in_failed", None, json!({ "name": req_data.name })).await;
         return Err(CredentialUnauthorized.into());
     }
 
     let user_data = user_data.unwrap();
     if !password_salt::compare_password(&user_data.password.unwrap(), &req_data.password) {
+        audit::record_as(&req, None, "auth.sign_in_failed", Some(Target::User(user_data.id)), json!({ "name": req_data.name })).await;
         return Err(CredentialUnauthorized.into());
     }
+    let password_reset_required = match check_restriction(user_data.id) {
+        Ok(t) => t,
+        Err(e) => {
+            audit::record_as(&req, None, "auth.sign_in_blocked", Some(Target::User(user_data.id)), json!({ "name": req_data.name })).await;
+            return Err(e);
+        }
+    };
+    audit::record_as(&req, Some(user_data.id), "auth.sign_in", Some(Target::User(user_data.id)), json!({})).await;
     let token = paseto::generate_access_token(&get_config!(security).auth_token_secret, user_data.id)?;
     Ok(web::HttpResponse::Ok().body(
         json!({
             "id": user_data.id,
             "name": user_data.name,
             "email": user_data.email,
+            "password_reset_required": password_reset_required,
             "access_token": token
         })
     ))
 }
 #[derive(Deserialize, Debug)]
 struct ModifyUserRolesReq{
+    pub user: i32,
     pub roles: Vec<i32>
 }
+// replaces all roles of the target user
 #[web::post("/modify_user_roles")]
 async fn modify_user_roles(req_data: web::types::Json<ModifyUserRolesReq>, req: web::HttpRequest) -> AppResult<impl Responder> {
-    let user_id = get_user_id(&req);
-    check_permission_api(Some(user_id), "MANAGE_ROLE").await?;
-    let req_data = req_data.into_inner();
-    
-    rbacDao::update_user_roles(get_db_pool(), user_id, &role_vec_to_str(req_data.roles)?).await?;
+    check_permission_api(Some(get_user_id(&req)), MANAGE_ROLE).await?;
+    let mut req_data = req_data.into_inner();
+    req_data.roles.sort_unstable();
+    req_data.roles.dedup();
+    if userDao::select_by_id(get_db_pool(), req_data.user).await?.is_none() {
+        return Err(NotFound.into());
+    }
+    if rbacDao::count_existing_roles(get_db_pool(), &req_data.roles).await? != req_data.roles.len() as i32 {
+        return Err(NotFound.into());
+    }
+    let old_roles = rbacDao::select_user_roles(get_db_pool(), req_data.user).await?;
+    rbacDao::update_user_roles(get_db_pool(), req_data.user, &req_data.roles).await?;
+    audit::record(&req, "role.set_user_roles", Some(Target::User(req_data.user)), json!({
+        "roles": { "old": old_roles, "new": req_data.roles }
+    })).await;
+    cacheService::publish(CacheChange::UserRoles(Some(req_data.user))).await?;
+    notify_roles_changed(req_data.user, &req_data.roles).await;
     Ok(web::HttpResponse::Ok().finish())
 }
+#[derive(Deserialize, Debug)]
+struct AssignRoleReq{
+    pub user: i32,
+    pub role: i32
+}
+#[web::post("/assign_role")]
+async fn assign_role(req_data: web::types::Json<AssignRoleReq>, req: web::HttpRequest) -> AppResult<impl Responder> {
+    check_permission_api(Some(get_user_id(&req)), MANAGE_ROLE).await?;
+    let roles = rbacDao::modify_user_role(get_db_pool(), req_data.user, req_data.role, true).await??;
+    audit::record(&req, "role.assign", Some(Target::User(req_data.user)), json!({ "role": req_data.role })).await;
+    cacheService::publish(CacheChange::UserRoles(Some(req_data.user))).await?;
+    notify_roles_changed(req_data.user, &roles).await;
+    Ok(web::HttpResponse::Ok().json(&roles))
+}
+#[web::post("/unassign_role")]
+async fn unassign_role(req_data: web::types::Json<AssignRoleReq>, req: web::HttpRequest) -> AppResult<impl Responder> {
+    check_permission_api(Some(get_user_id(&req)), MANAGE_ROLE).await?;
+    let roles = rbacDao::modify_user_role(get_db_pool(), req_data.user, req_data.role, false).await??;
+    audit::record(&req, "role.unassign", Some(Target::User(req_data.user)), json!({ "role": req_data.role })).await;
+    cacheService::publish(CacheChange::UserRoles(Some(req_data.user))).await?;
+    notify_roles_changed(req_data.user, &roles).await;
+    Ok(web::HttpResponse::Ok().json(&roles))
+}
+// the roles are already saved, a failed notification is not worth an error
+async fn notify_roles_changed(user: i32, roles: &[i32]){
+    if let Err(e) = notification::service::notify(user, NotificationKind::RoleChanged, json!({
+        "roles": roles
+    })).await {
+        error!("cannot notify user {} about changed roles: {}", user, e);
+    }
+}
 #[web::post("/remove_role/{role_id}")]
 async fn remove_role(path: web::types::Path<i32>, req: web::HttpRequest) -> AppResult<impl Responder> {
-    check_permission_api(Some(get_user_id(&req)), "MANAGE_ROLE").await?;
+    check_permission_api(Some(get_user_id(&req)), MANAGE_ROLE).await?;
     let role_id = path.into_inner();
+    let role = rbacDao::select_role(get_db_pool(), role_id).await?.ok_or(NotFound)?;
+    let permissions = rbacDao::select_role_permissions(get_db_pool(), role_id).await?;
     rbacDao::delete_role(get_db_pool(), role_id).await??;
-    super::service::delete_role_cache(role_id);
+    audit::record(&req, "role.delete", Some(Target::Role(role_id)), json!({
+        "name": { "old": role.name },
+        "alias": { "old": role.alias },
+        "permissions": { "old": permissions }
+    })).await;
+    cacheService::publish(CacheChange::Permissions).await?;
+    // the role was taken from every user holding it
+    cacheService::publish(CacheChange::UserRoles(None)).await?;
     Ok(web::HttpResponse::Ok().finish())
 }
 #[derive(Debug, Validate, Deserialize)]
@@ -108,7 +194,7 @@ struct RoleListRes {
 }
 #[web::get("/list_roles")]
 async fn list_roles(req: web::HttpRequest, req_data: web::types::Json<RoleListReq>) -> AppResult<impl Responder> {
-    check_permission_api(Some(get_user_id(&req)), "MANAGE_ROLE").await?;
+    check_permission_api(Some(get_user_id(&req)), MANAGE_ROLE).await?;
     let db_data = rbacDao::list_roles(get_db_pool(), 
     req_data.limit, 
     (req_data.page - 1).checked_mul(req_data.limit).ok_or(TooMaxParameter)?).await?;
@@ -120,6 +206,72 @@ async fn list_roles(req: web::HttpRequest, req_data: web::types::Json<RoleListRe
         }
     ))
 }
+#[derive(Serialize)]
+struct RoleDetailRes {
+    #[serde(flatten)]
+    role: Role,
+    // assigned to the role itself, inherited ones are not repeated
+    permissions: Vec<String>,
+    parents: Vec<i32>,
+}
+#[web::get("/role/{role_id}")]
+async fn get_role(path: web::types::Path<i32>, req: web::HttpRequest) -> AppResult<impl Responder> {
+    check_permission_api(Some(get_user_id(&req)), MANAGE_ROLE).await?;
+    let role_id = path.into_inner();
+    let role = rbacDao::select_role(get_db_pool(), role_id).await?.ok_or(NotFound)?;
+    Ok(web::HttpResponse::Ok().json(&RoleDetailRes{
+        role,
+        permissions: rbacDao::select_role_permissions(get_db_pool(), role_id).await?,
+        parents: rbacDao::select_parents_of(get_db_pool(), role_id).await?,
+    }))
+}
+#[derive(Debug, Validate, Deserialize)]
+struct UpdateRoleReq<'a> {
+    role: i32,
+    #[validate(length(min = 0, max = 50))]
+    name: Cow<'a, str>,
+    #[validate(length(min = 0, max = 50))]
+    alias: &'a str,
+}
+// system roles keep their names
+#[web::post("/update_role")]
+async fn update_role(req: web::HttpRequest, mut payload: web::types::Payload) -> AppResult<impl Responder> {
+    let mut payload = RequestPayload::new(&mut payload);
+    let req_data: UpdateRoleReq<'_> = payload.parse().await?;
+
+    req_data.validate()?;
+    check_permission_api(Some(get_user_id(&req)), MANAGE_ROLE).await?;
+    let old = rbacDao::select_role(get_db_pool(), req_data.role).await?.ok_or(NotFound)?;
+    rbacDao::update_role(get_db_pool(), req_data.role, &req_data.name, req_data.alias).await??;
+    audit::record(&req, "role.update", Some(Target::Role(req_data.role)), json!({
+        "name": { "old": old.name, "new": req_data.name },
+        "alias": { "old": old.alias, "new": req_data.alias }
+    })).await;
+    Ok(web::HttpResponse::Ok().finish())
+}
+#[derive(Debug, Validate, Deserialize)]
+struct RoleUsersReq {
+    role: i32,
+    #[validate(range(min = 0, max = 100))]
+    limit: i32,
+    #[validate(range(min = 1,))]
+    page: i32,
+}
+#[derive(Serialize)]
+struct RoleUsersRes {
+    total: i32,
+    users: Vec<UserIdName>,
+}
+#[web::get("/role_users")]
+async fn role_users(req: web::HttpRequest, req_data: web::types::Json<RoleUsersReq>) -> AppResult<impl Responder> {
+    req_data.validate()?;
+    check_permission_api(Some(get_user_id(&req)), MANAGE_ROLE).await?;
+    let (total, users) = rbacDao::list_role_users(get_db_pool(),
+        req_data.role,
+        req_data.limit,
+        (req_data.page - 1).checked_mul(req_data.limit).ok_or(TooMaxParameter)?).await?;
+    Ok(web::HttpResponse::Ok().json(&RoleUsersRes{ total, users }))
+}
 #[derive(Debug, Validate, Deserialize)]
 struct AddRoleReq<'a> {
     #[validate(length(min = 0, max = 50))]
@@ -127,7 +279,6 @@ struct AddRoleReq<'a> {
     #[validate(length(min = 0, max = 50))]
     alias: &'a str,
     permissions: Vec<&'a str>
-    // todo: further check
 }
 #[web::post("/add_role")]
 async fn add_role(req: web::HttpRequest, mut payload: web::types::Payload) -> AppResult<impl Responder> {
@@ -135,9 +286,23 @@ async fn add_role(req: web::HttpRequest, mut payload: web::types::Payload) -> Ap
     let req_data: AddRoleReq<'_> = payload.parse().await?;
 
     req_data.validate()?;
-    check_permission_api(Some(get_user_id(&req)), "MANAGE_ROLE").await?;
-    let _ = rbacDao::add_role(get_db_pool(), &req_data.name, req_data.alias, req_data.permissions).await?;
-    Ok(web::HttpResponse::Ok().finish())
+    check_permission_api(Some(get_user_id(&req)), MANAGE_ROLE).await?;
+    let mut permissions = req_data.permissions;
+    if !permissions.iter().all(|p| permission::is_known(p)) {
+        return Err(RBACUserError::UnknownPermission.into());
+    }
+    permissions.sort_unstable();
+    permissions.dedup();
+    let role_id = rbacDao::add_role(get_db_pool(), &req_data.name, req_data.alias, &permissions).await??;
+    audit::record(&req, "role.create", Some(Target::Role(role_id)), json!({
+        "name": { "new": req_data.name },
+        "alias": { "new": req_data.alias },
+        "permissions": { "new": permissions }
+    })).await;
+    cacheService::publish(CacheChange::Permissions).await?;
+    Ok(web::HttpResponse::Ok().body(json!({
+        "id": role_id
+    })))
 }
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -157,12 +322,103 @@ async fn modify_role_permission(req: web::HttpRequest, mut payload: web::types::
     let req_data: ModifyRolePermissionReq<'_> = payload.parse().await?;
 
     req_data.validate()?;
-    check_permission_api(Some(get_user_id(&req)), "MANAGE_ROLE").await?;
+    check_permission_api(Some(get_user_id(&req)), MANAGE_ROLE).await?;
     match req_data.action{
-        ModifyRolePermissionAction::Remove => rbacDao::delete_role_permission(get_db_pool(), req_data.role, req_data.permission).await?,
-        ModifyRolePermissionAction::Add => rbacDao::add_role_permission(get_db_pool(), req_data.role, req_data.permission).await?
+        ModifyRolePermissionAction::Remove => {
+            // an unknown permission left over from older versions can still be taken away
+            if rbacDao::delete_role_permission(get_db_pool(), req_data.role, req_data.permission).await?? {
+                audit::record(&req, "role.revoke_permission", Some(Target::Role(req_data.role)), json!({ "permission": req_data.permission })).await;
+                cacheService::publish(CacheChange::Permissions).await?;
+            }
+        },
+        ModifyRolePermissionAction::Add => {
+            if !permission::is_known(req_data.permission) {
+                return Err(RBACUserError::UnknownPermission.into());
+            }
+            if rbacDao::add_role_permission(get_db_pool(), req_data.role, req_data.permission).await?? {
+                audit::record(&req, "role.grant_permission", Some(Target::Role(req_data.role)), json!({ "permission": req_data.permission })).await;
+                cacheService::publish(CacheChange::Permissions).await?;
+            }
+        }
     };
-    
+
+    Ok(web::HttpResponse::Ok().finish())
+}
+
+#[derive(Debug, Deserialize)]
+struct RoleParentReq {
+    role: i32,
+    parent: i32
+}
+#[web::post("/add_role_parent")]
+async fn add_role_parent(req: web::HttpRequest, req_data: web::types::Json<RoleParentReq>) -> AppResult<impl Responder> {
+    check_permission_api(Some(get_user_id(&req)), MANAGE_ROLE).await?;
+    authService::add_role_parent(req_data.role, req_data.parent).await?;
+    audit::record(&req, "role.add_parent", Some(Target::Role(req_data.role)), json!({ "parent": req_data.parent })).await;
+    cacheService::publish(CacheChange::Permissions).await?;
+    Ok(web::HttpResponse::Ok().finish())
+}
+#[web::post("/remove_role_parent")]
+async fn remove_role_parent(req: web::HttpRequest, req_data: web::types::Json<RoleParentReq>) -> AppResult<impl Responder> {
+    check_permission_api(Some(get_user_id(&req)), MANAGE_ROLE).await?;
+    authService::remove_role_parent(req_data.role, req_data.parent).await?;
+    audit::record(&req, "role.remove_parent", Some(Target::Role(req_data.role)), json!({ "parent": req_data.parent })).await;
+    cacheService::publish(CacheChange::Permissions).await?;
+    Ok(web::HttpResponse::Ok().finish())
+}
+#[derive(Debug, Validate, Deserialize)]
+struct ExplainPermissionReq {
+    user: i32,
+    #[validate(length(min = 1, max = 64))]
+    permission: String,
+}
+// an empty list means the user does not hold the permission
+#[web::post("/explain_permission")]
+async fn explain_permission(req: web::HttpRequest, req_data: web::types::Json<ExplainPermissionReq>) -> AppResult<impl Responder> {
+    req_data.validate()?;
+    check_permission_api(Some(get_user_id(&req)), MANAGE_ROLE).await?;
+    if !permission::is_known(&req_data.permission) {
+        return Err(RBACUserError::UnknownPermission.into());
+    }
+    Ok(web::HttpResponse::Ok().json(&authService::explain_permission(req_data.user, &req_data.permission).await?))
+}
+#[derive(Debug, Validate, Deserialize)]
+struct ListPermissionsReq {
+    #[validate(length(min = 1, max = 10))]
+    lang: String,
+}
+#[web::get("/permissions")]
+async fn list_permissions(req: web::HttpRequest, req_data: web::types::Query<ListPermissionsReq>) -> AppResult<impl Responder> {
+    req_data.validate()?;
+    check_permission_api(Some(get_user_id(&req)), MANAGE_ROLE).await?;
+    let li: LanguageIdentifier = req_data.lang.parse().map_err(|_| UnknownLang)?;
+    if !LOCALES.locales().any(|l| *l == li) {
+        return Err(UnknownLang.into());
+    }
+    Ok(web::HttpResponse::Ok().json(&permission::localized(&li)))
+}
+
+#[derive(Debug, Validate, Deserialize)]
+struct SetRoleQuotaReq {
+    role: i32,
+    #[validate(range(min = 0))]
+    quota_bytes: Option<i64>,
+    #[validate(range(min = 0))]
+    quota_files: Option<i32>,
+}
+#[web::post("/set_role_quota")]
+async fn set_role_quota(req: web::HttpRequest, req_data: web::types::Json<SetRoleQuotaReq>) -> AppResult<impl Responder> {
+    req_data.validate()?;
+    check_permission_api(Some(get_user_id(&req)), MANAGE_ROLE).await?;
+    let old = quotaDao::select_role_quota(get_db_pool(), req_data.role).await?.ok_or(NotFound)?;
+    quotaDao::update_role_quota(get_db_pool(), req_data.role, &Quota{
+        quota_bytes: req_data.quota_bytes,
+        quota_files: req_data.quota_files
+    }).await?;
+    audit::record(&req, "role.set_quota", Some(Target::Role(req_data.role)), json!({
+        "quota_bytes": { "old": old.quota_bytes, "new": req_data.quota_bytes },
+        "quota_files": { "old": old.quota_files, "new": req_data.quota_files }
+    })).await;
     Ok(web::HttpResponse::Ok().finish())
 }
 
@@ -184,14 +440,7 @@ async fn __test_add_user__(mut payload: web::types::Payload) -> AppResult<impl R
     let req_data: TestAddUser<'_> = payload.parse().await?;
     req_data.validate()?;
     let hashed_password = password_salt::generate_password(&req_data.password, &get_config!(security).password_salt)?;
-    let user_insert_res = userDao::create(
-        get_db_pool(),
-        &req_data.name,
-        &req_data.email,
-        &hashed_password,
-        DEFAULT_ROLE_STR
-    )
-    .await?;
+    let user_insert_res = register_user(&req_data.name, req_data.email, &hashed_password).await?;
     Ok(web::HttpResponse::Ok().body(json!({
         "id": user_insert_res
     })))
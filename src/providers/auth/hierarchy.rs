@@ -0,0 +1,112 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+// parent links between roles, a role holds every permission of its ancestors
+pub struct RoleGraph {
+    parents: HashMap<i32, Vec<i32>>,
+}
+impl RoleGraph {
+    pub fn new(edges: impl IntoIterator<Item = (i32, i32)>) -> Self {
+        let mut parents: HashMap<i32, Vec<i32>> = HashMap::new();
+        for (role, parent) in edges {
+            parents.entry(role).or_default().push(parent);
+        }
+        Self { parents }
+    }
+    pub fn roles(&self) -> impl Iterator<Item = &i32> {
+        self.parents.keys()
+    }
+    // the role itself first, then its ancestors nearest first.
+    // a cycle that slipped into the table is walked only once
+    pub fn ancestors(&self, role: i32) -> Vec<i32> {
+        let mut seen = HashSet::from([role]);
+        let mut order = vec![role];
+        let mut queue = VecDeque::from([role]);
+        while let Some(current) = queue.pop_front() {
+            for &parent in self.parents.get(&current).into_iter().flatten() {
+                if seen.insert(parent) {
+                    order.push(parent);
+                    queue.push_back(parent);
+                }
+            }
+        }
+        order
+    }
+    pub fn would_cycle(&self, role: i32, parent: i32) -> bool {
+        self.ancestors(parent).contains(&role)
+    }
+    // the shortest chain from role up to ancestor, both included
+    pub fn path(&self, role: i32, ancestor: i32) -> Option<Vec<i32>> {
+        let mut previous: HashMap<i32, i32> = HashMap::new();
+        let mut queue = VecDeque::from([role]);
+        while let Some(current) = queue.pop_front() {
+            if current == ancestor {
+                let mut path = vec![current];
+                let mut step = current;
+                while let Some(&p) = previous.get(&step) {
+                    path.push(p);
+                    step = p;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for &parent in self.parents.get(&current).into_iter().flatten() {
+                if parent != role && !previous.contains_key(&parent) {
+                    previous.insert(parent, current);
+                    queue.push_back(parent);
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RoleGraph;
+
+    // 4 -> 3 -> 1, 4 -> 2 -> 1, 5 on its own
+    fn diamond() -> RoleGraph {
+        RoleGraph::new([(4, 3), (4, 2), (3, 1), (2, 1)])
+    }
+
+    #[test]
+    fn ancestors_start_with_the_role_nearest_first() {
+        assert_eq!(diamond().ancestors(4), vec![4, 3, 2, 1]);
+        assert_eq!(diamond().ancestors(3), vec![3, 1]);
+        assert_eq!(diamond().ancestors(1), vec![1]);
+        assert_eq!(diamond().ancestors(5), vec![5]);
+    }
+
+    #[test]
+    fn ancestors_walk_a_stored_cycle_once() {
+        let graph = RoleGraph::new([(1, 2), (2, 3), (3, 1)]);
+        assert_eq!(graph.ancestors(1), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn would_cycle_detects_direct_and_indirect_loops() {
+        let graph = diamond();
+        assert!(graph.would_cycle(1, 4));
+        assert!(graph.would_cycle(3, 4));
+        assert!(graph.would_cycle(4, 4));
+        assert!(!graph.would_cycle(4, 1));
+        assert!(!graph.would_cycle(2, 3));
+        assert!(!graph.would_cycle(5, 4));
+    }
+
+    #[test]
+    fn path_is_the_shortest_chain() {
+        let graph = RoleGraph::new([(4, 3), (3, 2), (2, 1), (4, 1)]);
+        assert_eq!(graph.path(4, 1), Some(vec![4, 1]));
+        assert_eq!(graph.path(3, 1), Some(vec![3, 2, 1]));
+        assert_eq!(graph.path(4, 4), Some(vec![4]));
+        assert_eq!(graph.path(1, 4), None);
+    }
+
+    #[test]
+    fn path_ends_on_a_stored_cycle() {
+        let graph = RoleGraph::new([(1, 2), (2, 3), (3, 1)]);
+        assert_eq!(graph.path(1, 3), Some(vec![1, 2, 3]));
+        assert_eq!(graph.path(1, 4), None);
+    }
+}
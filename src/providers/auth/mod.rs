@@ -1,2 +1,5 @@
 pub mod api;
+pub mod hierarchy;
+pub mod permission;
+pub mod policy;
 pub mod service;
\ No newline at end of file
@@ -0,0 +1,72 @@
+use fluent_templates::{LanguageIdentifier, Loader};
+use serde::Serialize;
+
+use crate::external::fs::embed::LOCALES;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Category {
+    Console,
+    Article,
+    Comment,
+    File,
+    User,
+    Role,
+    Mail,
+    Newsletter,
+    Webhook,
+    System,
+}
+pub struct Permission {
+    pub id: &'static str,
+    pub category: Category,
+}
+
+// every permission a handler can check, with a constant named after it;
+// name and description come from builtin_permission.ftl under the lowercase id
+macro_rules! permissions {
+    ($($id:ident => $category:ident),* $(,)?) => {
+        $(pub const $id: &str = stringify!($id);)*
+        pub const PERMISSIONS: &[Permission] = &[
+            $(Permission { id: $id, category: Category::$category }),*
+        ];
+    };
+}
+permissions! {
+    ENTER_CONSOLE => Console,
+    CREATE_ARTICLE => Article,
+    MANAGE_ARTICLE => Article,
+    COMMENT => Comment,
+    UPLOAD_FILE => File,
+    MANAGE_FILE => File,
+    MANAGE_USER => User,
+    MANAGE_ROLE => Role,
+    MANAGE_MAIL => Mail,
+    MANAGE_NEWSLETTER => Newsletter,
+    MANAGE_WEBHOOK => Webhook,
+    MANAGE_SYSTEM => System,
+    VIEW_AUDIT_LOG => System,
+}
+
+pub fn is_known(id: &str) -> bool {
+    PERMISSIONS.iter().any(|p| p.id == id)
+}
+
+#[derive(Serialize)]
+pub struct LocalizedPermission {
+    pub id: &'static str,
+    pub category: Category,
+    pub name: String,
+    pub description: String,
+}
+pub fn localized(lang: &LanguageIdentifier) -> Vec<LocalizedPermission> {
+    PERMISSIONS.iter().map(|p| {
+        let key = p.id.to_lowercase();
+        LocalizedPermission {
+            id: p.id,
+            category: p.category,
+            name: LOCALES.lookup(lang, &key).unwrap_or_else(|| p.id.to_string()),
+            description: LOCALES.lookup(lang, &format!("{key}.desc")).unwrap_or_default(),
+        }
+    }).collect()
+}
@@ -0,0 +1,71 @@
+use crate::db::{get_db_pool, article as articleDao, article::ArticleGrant};
+use crate::providers::auth::permission::{CREATE_ARTICLE, MANAGE_ARTICLE};
+use crate::providers::auth::service::check_permission_api;
+use crate::types::err::AppResult;
+use crate::types::err::GlobalUserError::{NotFound, PermissionDenied};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    // see unpublished state and who else works on it
+    Read,
+    Edit,
+    // grant or revoke access for others
+    Share,
+}
+#[derive(Debug, Clone, Copy)]
+pub enum Resource {
+    Article(i32),
+}
+// which rows a list may show the user
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scope {
+    All,
+    Accessible(i32),
+}
+impl Scope {
+    pub fn accessible_by(self) -> Option<i32> {
+        match self {
+            Scope::All => None,
+            Scope::Accessible(user) => Some(user)
+        }
+    }
+}
+
+// global permissions answer first, then ownership, then the per-resource acl
+pub async fn authorize(user: i32, action: Action, resource: Resource) -> AppResult<()> {
+    match resource {
+        Resource::Article(id) => authorize_article(user, action, id).await
+    }
+}
+
+async fn authorize_article(user: i32, action: Action, id: i32) -> AppResult<()> {
+    let author = articleDao::select_author(get_db_pool(), id).await?
+        .ok_or(NotFound)?;
+    if check_permission_api(Some(user), MANAGE_ARTICLE).await.is_ok() {
+        return Ok(());
+    }
+    let allowed = if author == user {
+        true
+    } else {
+        match articleDao::select_grant(get_db_pool(), id, user).await? {
+            None => false,
+            Some(ArticleGrant::Reviewer) => action == Action::Read,
+            Some(ArticleGrant::CoAuthor) => matches!(action, Action::Read | Action::Edit),
+        }
+    };
+    if !allowed {
+        return Err(PermissionDenied.into());
+    }
+    // authors and co-authors who lost the right to write keep read access only
+    if action != Action::Read {
+        check_permission_api(Some(user), CREATE_ARTICLE).await?;
+    }
+    Ok(())
+}
+
+pub async fn article_scope(user: i32) -> Scope {
+    match check_permission_api(Some(user), MANAGE_ARTICLE).await {
+        Ok(_) => Scope::All,
+        Err(_) => Scope::Accessible(user)
+    }
+}
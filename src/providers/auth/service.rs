@@ -1,21 +1,75 @@
+use std::collections::{HashMap, HashSet};
+use std::num::NonZeroUsize;
+use std::sync::{Mutex, PoisonError};
+
 use dashmap::DashMap;
-use once_cell::sync::Lazy;
-use tracing::error;
+use lru::LruCache;
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Serialize;
+use tracing::{error, warn};
 use crate::db::{get_db_pool, rbac as rbacDao};
+use crate::get_config;
+use crate::providers::auth::hierarchy::RoleGraph;
+use crate::providers::auth::permission::is_known;
 use crate::types::err::{AppResult, EmptyErrResult};
-use crate::types::err::GlobalUserError::PermissionDenied;
+use crate::types::err::GlobalUserError::{NotFound, PermissionDenied};
 use crate::types::service::AppService;
 
 pub static PERMISSION_CACHE: Lazy<DashMap<String, Vec<i32>>> = Lazy::new(|| DashMap::new());
 
-// pub static ROLE_CACHE: OnceCell<LruCache<i32, String>> = OnceCell::new(); 
+// role ids of recently checked users, the generation is bumped on every invalidation
+// so that a lookup racing with a role change does not put the old roles back
+struct RoleCache{
+    entries: LruCache<i32, Vec<i32>>,
+    generation: u64,
+}
+static ROLE_CACHE: OnceCell<Mutex<RoleCache>> = OnceCell::new();
+
+pub async fn get_user_roles(user: i32) -> AppResult<Vec<i32>> {
+    let Some(cache) = ROLE_CACHE.get() else {
+        return Ok(rbacDao::select_user_roles(get_db_pool(), user).await?);
+    };
+    let generation = {
+        let mut cache = cache.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(roles) = cache.entries.get(&user) {
+            return Ok(roles.clone());
+        }
+        cache.generation
+    };
+    let roles = rbacDao::select_user_roles(get_db_pool(), user).await?;
+    let mut cache = cache.lock().unwrap_or_else(PoisonError::into_inner);
+    if cache.generation == generation {
+        cache.entries.put(user, roles.clone());
+    }
+    Ok(roles)
+}
+pub fn invalidate_user_roles(user: i32){
+    if let Some(cache) = ROLE_CACHE.get() {
+        let mut cache = cache.lock().unwrap_or_else(PoisonError::into_inner);
+        cache.generation += 1;
+        cache.entries.pop(&user);
+    }
+}
+pub fn clear_user_roles(){
+    if let Some(cache) = ROLE_CACHE.get() {
+        let mut cache = cache.lock().unwrap_or_else(PoisonError::into_inner);
+        cache.generation += 1;
+        cache.entries.clear();
+    }
+}
+
 pub async fn check_permission_api(user_id: Option<i32>, permission: &str) -> AppResult<()> {
+    // a typo in a handler must not grant anything, whatever roles hold the string
+    if !is_known(permission) {
+        error!("permission {permission} is not registered, access is denied");
+        return Err(PermissionDenied.into());
+    }
     let user_roles = match user_id {
         None => {
             vec![0]
         },
         Some(id) => {
-            rbacDao::role_str_to_vec(rbacDao::select_user_role(get_db_pool(), id).await?)?
+            get_user_roles(id).await?
         }
     };
     if user_roles.len() == 0{
@@ -36,27 +90,109 @@ pub async fn check_permission_api(user_id: Option<i32>, permission: &str) -> App
     Ok(())
 }
 
-pub fn delete_role_cache(role: i32){
-    PERMISSION_CACHE.iter_mut().for_each(|mut arr| {
-        arr.retain(|&r| r != role)
-    })
+// only one rebuild at a time, so an older snapshot never overwrites a newer one
+static REBUILD_LOCK: Lazy<tokio::sync::Mutex<()>> = Lazy::new(|| tokio::sync::Mutex::new(()));
+
+// the roles holding each permission, directly or through an ancestor
+async fn load_permission_map() -> Result<HashMap<String, Vec<i32>>, sqlx::Error> {
+    let permission_to_roles = rbacDao::select_permission_to_roles(get_db_pool()).await?;
+    let graph = RoleGraph::new(rbacDao::select_role_parents(get_db_pool()).await?);
+    let mut direct: HashMap<i32, Vec<String>> = HashMap::new();
+    for entry in permission_to_roles {
+        // still loaded, so that nothing changes for a role until someone cleans it up
+        if !is_known(&entry.permission) {
+            warn!("role {} holds unknown permission {}", entry.role, entry.permission);
+        }
+        direct.entry(entry.role).or_default().push(entry.permission);
+    }
+    let roles: HashSet<i32> = direct.keys().chain(graph.roles()).copied().collect();
+    let mut map: HashMap<String, Vec<i32>> = HashMap::new();
+    for role in roles {
+        for ancestor in graph.ancestors(role) {
+            for permission in direct.get(&ancestor).into_iter().flatten() {
+                map.entry(permission.clone()).or_default().push(role);
+            }
+        }
+    }
+    // sort role vec for binary search
+    for roles in map.values_mut() {
+        roles.sort_unstable();
+        roles.dedup();
+    }
+    Ok(map)
+}
+// entries are replaced one by one, a check never sees an empty cache
+fn apply_permission_map(mut map: HashMap<String, Vec<i32>>){
+    PERMISSION_CACHE.retain(|permission, _| map.contains_key(permission));
+    for (permission, roles) in map.drain() {
+        PERMISSION_CACHE.insert(permission, roles);
+    }
+}
+// local only, changes go through cache::service::publish so that other instances follow
+pub async fn reload_permission_cache() -> AppResult<()> {
+    let _guard = REBUILD_LOCK.lock().await;
+    apply_permission_map(load_permission_map().await?);
+    Ok(())
+}
+
+// the cycle is judged inside the transaction of the insert, the caller publishes the change
+pub async fn add_role_parent(role: i32, parent: i32) -> AppResult<()> {
+    rbacDao::add_role_parent(get_db_pool(), role, parent, |edges| RoleGraph::new(edges).would_cycle(role, parent)).await??;
+    Ok(())
+}
+pub async fn remove_role_parent(role: i32, parent: i32) -> AppResult<()> {
+    if !rbacDao::delete_role_parent(get_db_pool(), role, parent).await?? {
+        return Err(NotFound.into());
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct PermissionSource {
+    // the role of the user
+    pub role: i32,
+    // the role the permission is assigned to
+    pub granted_by: i32,
+    // role, parent, ..., granted_by
+    pub path: Vec<i32>,
+}
+// every role of the user that leads to the permission, read from the database rather than the caches
+pub async fn explain_permission(user: i32, permission: &str) -> AppResult<Vec<PermissionSource>> {
+    let user_roles = rbacDao::select_user_roles(get_db_pool(), user).await?;
+    let graph = RoleGraph::new(rbacDao::select_role_parents(get_db_pool()).await?);
+    let granted: HashSet<i32> = rbacDao::select_permission_to_roles(get_db_pool()).await?
+        .into_iter()
+        .filter(|entry| entry.permission == permission)
+        .map(|entry| entry.role)
+        .collect();
+    let mut sources = vec![];
+    for role in user_roles {
+        for ancestor in graph.ancestors(role) {
+            if !granted.contains(&ancestor) {
+                continue;
+            }
+            if let Some(path) = graph.path(role, ancestor) {
+                sources.push(PermissionSource{ role, granted_by: ancestor, path });
+            }
+        }
+    }
+    Ok(sources)
 }
 pub struct RBACService;
 impl AppService for RBACService{
 
     async fn initialize() -> EmptyErrResult<()> {
-        let permission_to_roles = rbacDao::select_permission_to_roles(get_db_pool()).await.map_err(|e|{
+        // a capacity of 0 disables the cache
+        if let Some(capacity) = NonZeroUsize::new(get_config!(cache).max_user_role_entity) {
+            let _ = ROLE_CACHE.set(Mutex::new(RoleCache{
+                entries: LruCache::new(capacity),
+                generation: 0,
+            }));
+        }
+        let map = load_permission_map().await.map_err(|e|{
             error!("failed to get permission-role-relation cache: {}", e);
-            ()
         })?;
-        for entry in permission_to_roles {
-            let _ = &PERMISSION_CACHE.entry(entry.permission).or_insert(vec![]).
-                push(entry.role);
-        }
-        // sort role vec for binary search
-        PERMISSION_CACHE.iter_mut().for_each(| mut roles | {
-            roles.sort();
-        });
+        apply_permission_map(map);
         Ok(())
     }
     fn name() -> &'static str {
@@ -0,0 +1,52 @@
+use ntex::web::{self, Responder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use crate::middlewares::Auth;
+use crate::providers::audit::service as audit;
+use crate::providers::auth::permission::MANAGE_SYSTEM;
+use crate::providers::auth::service::check_permission_api;
+use crate::types::err::AppResult;
+use crate::utils::request::get_user_id;
+use super::service::{self as cacheService, CacheChange};
+
+pub fn init(cfg: &mut web::ServiceConfig){
+    cfg.service(
+        web::scope("/v1/cache").wrap(Auth)
+            .service(reload)
+    );
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+enum ReloadTarget {
+    #[default]
+    All,
+    Permissions,
+    // storage policies have no api, they are only ever edited in the database
+    FsPolicies,
+}
+#[derive(Debug, Deserialize)]
+struct ReloadReq {
+    // otherwise only the instance handling the request reloads
+    #[serde(default)]
+    all_instances: bool,
+    #[serde(default)]
+    target: ReloadTarget,
+}
+// for changes made to the database by hand
+#[web::post("/reload")]
+async fn reload(req: web::HttpRequest, req_data: web::types::Json<ReloadReq>) -> AppResult<impl Responder> {
+    check_permission_api(Some(get_user_id(&req)), MANAGE_SYSTEM).await?;
+    let change = match req_data.target {
+        ReloadTarget::All => CacheChange::All,
+        ReloadTarget::Permissions => CacheChange::Permissions,
+        ReloadTarget::FsPolicies => CacheChange::FsPolicies,
+    };
+    if req_data.all_instances {
+        cacheService::publish(change).await?;
+    } else {
+        cacheService::reload_local(change).await?;
+    }
+    audit::record(&req, "system.reload_cache", None, json!({ "all_instances": req_data.all_instances, "target": req_data.target })).await;
+    Ok(web::HttpResponse::Ok().finish())
+}
@@ -0,0 +1,3 @@
+pub mod api;
+pub mod service;
+mod sync;
\ No newline at end of file
@@ -0,0 +1,168 @@
+use std::time::Duration;
+
+use once_cell::sync::{Lazy, OnceCell};
+use rustle_derive::ErrorHelper;
+use tracing::{error, info};
+
+use crate::external::fs;
+use crate::get_config;
+use crate::providers::auth::service::{clear_user_roles, invalidate_user_roles, reload_permission_cache};
+use crate::providers::user::service::reload_restrictions;
+use crate::types::config::CacheSyncBackend;
+use crate::types::err::{AppResult, EmptyErrResult};
+use crate::types::service::AppService;
+use super::sync::{CacheSync, DatabaseSync, LocalSync};
+
+// tells the changes of this process apart from those of other instances
+static INSTANCE_ID: Lazy<String> = Lazy::new(|| uuid::Uuid::new_v4().to_string());
+static SYNC: OnceCell<Box<dyn CacheSync>> = OnceCell::new();
+
+fn sync() -> &'static dyn CacheSync {
+    SYNC.get_or_init(|| Box::new(LocalSync)).as_ref()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CacheChange {
+    // permissions or parents of some role
+    Permissions,
+    // the roles of one user, or of every user
+    UserRoles(Option<i32>),
+    // disabled, banned or forced password reset, of one user or of every user
+    UserStatus(Option<i32>),
+    FsPolicies,
+    All,
+}
+impl CacheChange {
+    pub(super) fn kind(&self) -> &'static str {
+        match self {
+            CacheChange::Permissions => "permissions",
+            CacheChange::UserRoles(_) => "user_roles",
+            CacheChange::UserStatus(_) => "user_status",
+            CacheChange::FsPolicies => "fs_policies",
+            CacheChange::All => "all"
+        }
+    }
+    pub(super) fn target(&self) -> Option<i32> {
+        match self {
+            CacheChange::UserRoles(user) | CacheChange::UserStatus(user) => *user,
+            _ => None
+        }
+    }
+    pub(super) fn parse(kind: &str, target: Option<i32>) -> Option<Self> {
+        match kind {
+            "permissions" => Some(CacheChange::Permissions),
+            "user_roles" => Some(CacheChange::UserRoles(target)),
+            "user_status" => Some(CacheChange::UserStatus(target)),
+            "fs_policies" => Some(CacheChange::FsPolicies),
+            "all" => Some(CacheChange::All),
+            _ => None
+        }
+    }
+}
+
+#[derive(ErrorHelper)]
+#[err(internal)]
+pub enum CacheInternalError {
+    FsPolicyReload
+}
+
+pub struct CacheService;
+impl AppService for CacheService {
+    fn name() -> &'static str {
+        "CacheService"
+    }
+    // runs before the caches are filled, so nothing recorded meanwhile is missed
+    async fn initialize() -> EmptyErrResult<()> {
+        #[cfg(unix)]
+        ntex::rt::spawn(async {
+            use tokio::signal::unix::{signal, SignalKind};
+            let Ok(mut hangup) = signal(SignalKind::hangup()).inspect_err(|e| error!("cannot listen for SIGHUP: {}", e)) else {
+                return;
+            };
+            while hangup.recv().await.is_some() {
+                info!("SIGHUP received, reloading caches");
+                if let Err(e) = apply(CacheChange::All).await {
+                    error!("cannot reload caches: {}", e);
+                }
+            }
+        });
+        let backend = get_config!(cache).sync;
+        let overlap_secs = get_config!(cache).overlap_secs;
+        let backend: Box<dyn CacheSync> = match backend {
+            CacheSyncBackend::Local => Box::new(LocalSync),
+            CacheSyncBackend::Database => Box::new(DatabaseSync::new(overlap_secs)),
+        };
+        let _ = SYNC.set(backend);
+        if !sync().is_shared() {
+            return Ok(());
+        }
+        sync().fetch(&INSTANCE_ID).await.map_err(|e| {
+            error!("cannot read the cache change log: {}", e);
+        })?;
+        ntex::rt::spawn(async {
+            let poll_interval = Duration::from_secs(get_config!(cache).poll_interval_secs);
+            loop {
+                tokio::time::sleep(poll_interval).await;
+                poll().await;
+            }
+        });
+        ntex::rt::spawn(async {
+            let purge_interval = Duration::from_secs(get_config!(cache).purge_interval_secs);
+            loop {
+                tokio::time::sleep(purge_interval).await;
+                let retention_secs = get_config!(cache).retention_secs;
+                if let Err(e) = sync().purge(retention_secs).await {
+                    error!("cannot purge the cache change log: {}", e);
+                }
+            }
+        });
+        info!("cache sync started as instance {}", INSTANCE_ID.as_str());
+        Ok(())
+    }
+}
+
+async fn apply(change: CacheChange) -> AppResult<()> {
+    match change {
+        CacheChange::Permissions => reload_permission_cache().await?,
+        CacheChange::UserRoles(Some(user)) => invalidate_user_roles(user),
+        CacheChange::UserRoles(None) => clear_user_roles(),
+        CacheChange::UserStatus(user) => reload_restrictions(user).await?,
+        CacheChange::FsPolicies => fs::load_policies().await.map_err(|_| CacheInternalError::FsPolicyReload)?,
+        CacheChange::All => {
+            reload_permission_cache().await?;
+            clear_user_roles();
+            reload_restrictions(None).await?;
+            fs::load_policies().await.map_err(|_| CacheInternalError::FsPolicyReload)?;
+        }
+    }
+    Ok(())
+}
+
+// recorded before it is applied, so other instances learn of it even if applying fails or this process dies;
+// the change itself is committed by then, so a failed record is only logged instead of failing the request
+pub async fn publish(change: CacheChange) -> AppResult<()> {
+    if let Err(e) = sync().record(change, &INSTANCE_ID).await {
+        error!("cannot record cache change {:?}, other instances keep stale caches until reloaded: {}", change, e);
+    }
+    apply(change).await
+}
+
+pub async fn reload_local(change: CacheChange) -> AppResult<()> {
+    apply(change).await
+}
+
+async fn poll() {
+    let changes = match sync().fetch(&INSTANCE_ID).await {
+        Ok(c) => c,
+        Err(e) => {
+            error!("cannot fetch cache changes: {}", e);
+            return;
+        }
+    };
+    for change in changes {
+        // a failed reload is retried with the next change of the same kind or a forced reload
+        if let Err(e) = apply(change).await {
+            error!("cannot apply cache change {:?}: {}", change, e);
+        }
+    }
+}
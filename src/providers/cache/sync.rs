@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use tracing::error;
+
+use crate::db::{cache as cacheDao, get_db_pool};
+use crate::types::err::AppResult;
+use super::service::CacheChange;
+
+pub type SyncFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+// how the changes of one instance reach the others
+pub trait CacheSync: Send + Sync {
+    // whether there is anything to poll at all
+    fn is_shared(&self) -> bool;
+    // called before the change is applied here, so that a crash in between cannot lose it for the others
+    fn record<'a>(&'a self, change: CacheChange, origin: &'a str) -> SyncFuture<'a, AppResult<()>>;
+    // the changes of other instances that have not been handed out by this one yet
+    fn fetch<'a>(&'a self, origin: &'a str) -> SyncFuture<'a, AppResult<Vec<CacheChange>>>;
+    // drops what even the slowest instance no longer needs
+    fn purge(&self, retention_secs: i64) -> SyncFuture<'_, AppResult<()>>;
+}
+
+// a single instance, changes only apply to this process
+pub struct LocalSync;
+impl CacheSync for LocalSync {
+    fn is_shared(&self) -> bool {
+        false
+    }
+    fn record<'a>(&'a self, _change: CacheChange, _origin: &'a str) -> SyncFuture<'a, AppResult<()>> {
+        Box::pin(async { Ok(()) })
+    }
+    fn fetch<'a>(&'a self, _origin: &'a str) -> SyncFuture<'a, AppResult<Vec<CacheChange>>> {
+        Box::pin(async { Ok(Vec::new()) })
+    }
+    fn purge(&self, _retention_secs: i64) -> SyncFuture<'_, AppResult<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[derive(Default)]
+struct Cursor {
+    // the time of the database when the last fetch started
+    read_at: Option<DateTime<Utc>>,
+    // changes inside the overlap window that were already handed out
+    seen: HashMap<i64, DateTime<Utc>>,
+}
+
+// changes are written to a table that every instance polls
+pub struct DatabaseSync {
+    // ids are taken at insert but become visible at commit, so a lower id can show up after a higher one;
+    // every fetch reads this far behind the previous one and skips what it has seen
+    overlap: chrono::Duration,
+    cursor: Mutex<Cursor>,
+}
+impl DatabaseSync {
+    pub fn new(overlap_secs: i64) -> Self {
+        Self {
+            overlap: chrono::Duration::seconds(overlap_secs),
+            cursor: Mutex::new(Cursor::default()),
+        }
+    }
+    fn take_unseen(&self, read_at: DateTime<Utc>, rows: Vec<cacheDao::CacheChangeRow>) -> Vec<CacheChange> {
+        let mut cursor = self.cursor.lock().unwrap();
+        let first = cursor.read_at.is_none();
+        let window_start = read_at - self.overlap;
+        cursor.seen.retain(|_, created_at| *created_at >= window_start);
+        cursor.read_at = Some(read_at);
+        let mut changes = Vec::new();
+        for row in rows {
+            if cursor.seen.insert(row.id, row.created_at).is_some() || first {
+                continue;
+            }
+            match CacheChange::parse(&row.kind, row.target) {
+                Some(change) => changes.push(change),
+                None => error!("unknown cache change {} of kind {}", row.id, row.kind)
+            }
+        }
+        changes
+    }
+}
+impl CacheSync for DatabaseSync {
+    fn is_shared(&self) -> bool {
+        true
+    }
+    fn record<'a>(&'a self, change: CacheChange, origin: &'a str) -> SyncFuture<'a, AppResult<()>> {
+        Box::pin(async move {
+            cacheDao::record(get_db_pool(), change.kind(), change.target(), origin).await?;
+            Ok(())
+        })
+    }
+    // the first fetch only fills the cursor, the caches are loaded after it and cover everything before
+    fn fetch<'a>(&'a self, origin: &'a str) -> SyncFuture<'a, AppResult<Vec<CacheChange>>> {
+        Box::pin(async move {
+            let read_at = cacheDao::select_now(get_db_pool()).await?;
+            let since = self.cursor.lock().unwrap().read_at.unwrap_or(read_at) - self.overlap;
+            let rows = cacheDao::select_since(get_db_pool(), since, origin).await?;
+            Ok(self.take_unseen(read_at, rows))
+        })
+    }
+    fn purge(&self, retention_secs: i64) -> SyncFuture<'_, AppResult<()>> {
+        Box::pin(async move {
+            cacheDao::purge_older_than(get_db_pool(), retention_secs).await?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(id: i64, kind: &str, created_at: DateTime<Utc>) -> cacheDao::CacheChangeRow {
+        cacheDao::CacheChangeRow { id, kind: kind.to_string(), target: None, created_at }
+    }
+
+    #[test]
+    fn late_commits_are_picked_up_once() {
+        let sync = DatabaseSync::new(60);
+        let start = Utc::now();
+        assert!(sync.take_unseen(start, vec![row(1, "permissions", start)]).is_empty());
+        let next = start + chrono::Duration::seconds(5);
+        // id 3 committed before id 2
+        assert_eq!(sync.take_unseen(next, vec![row(1, "permissions", start), row(3, "all", next)]), vec![CacheChange::All]);
+        let later = next + chrono::Duration::seconds(5);
+        assert_eq!(
+            sync.take_unseen(later, vec![row(2, "fs_policies", next), row(3, "all", next)]),
+            vec![CacheChange::FsPolicies]
+        );
+    }
+
+    #[test]
+    fn seen_ids_leave_with_the_window() {
+        let sync = DatabaseSync::new(10);
+        let start = Utc::now();
+        sync.take_unseen(start, vec![row(1, "permissions", start)]);
+        sync.take_unseen(start + chrono::Duration::seconds(60), Vec::new());
+        assert!(sync.cursor.lock().unwrap().seen.is_empty());
+    }
+}
@@ -0,0 +1,141 @@
+use std::path::PathBuf;
+use futures_util::TryStreamExt;
+use ntex::web::{self, Responder};
+use serde::{Deserialize, Serialize};
+use tokio_util::io::StreamReader;
+use validator::Validate;
+use super::service::{self, get_accessible_file};
+use crate::db::{get_db_pool, file as fileDao, file::{File, FileFilterable, FileSortable}};
+use crate::external::fs::serve::ServedFile;
+use crate::get_config;
+use crate::middlewares::Auth;
+use crate::providers::auth::permission::{MANAGE_FILE, UPLOAD_FILE};
+use crate::providers::auth::service::check_permission_api;
+use crate::types::err::AppResult;
+use crate::types::err::GlobalUserError::{NotFound, TooMaxParameter};
+use crate::utils::request::{check_content_length, get_user_id, RequestPayload, ALLOWED_IMAGE_MIME};
+
+pub fn init(cfg: &mut web::ServiceConfig){
+    cfg.service(
+        web::scope("/v1/file")
+            .service(get)
+            .service(
+                web::scope("/").wrap(Auth)
+                    .service(upload)
+                    .service(list)
+                    .service(rename)
+                    .service(delete)
+            )
+    );
+}
+
+#[derive(Debug, Validate, Deserialize)]
+struct UploadReq {
+    #[validate(length(min = 1, max = 255))]
+    pub name: String,
+}
+#[web::post("/upload")]
+async fn upload(payload: web::types::Payload, query: web::types::Query<UploadReq>,
+    req: web::HttpRequest) -> AppResult<impl Responder> {
+    query.validate()?;
+    let user_id = get_user_id(&req);
+    check_permission_api(Some(user_id), UPLOAD_FILE).await?;
+    check_content_length(&req, get_config!(http).max_upload_size)?;
+
+    let stream = StreamReader::new(payload.map_err(std::io::Error::other));
+    let declared = req.headers().get("content-type").and_then(|t| t.to_str().ok()).unwrap_or_default();
+    let file = service::save_upload(stream, user_id, &query.name, declared).await?;
+    Ok(web::HttpResponse::Ok().json(&file))
+}
+
+#[derive(Debug, Validate, Deserialize)]
+struct ListReq {
+    filter: Vec<FileFilterable>,
+    sort: Vec<FileSortable>,
+    #[validate(range(min = 0, max = 100))]
+    limit: i32,
+    #[validate(range(min = 1,))]
+    page: i32
+}
+#[derive(Debug, Serialize)]
+struct ListRes {
+    total: i32,
+    files: Vec<File>
+}
+#[web::post("/list")]
+async fn list(mut payload: web::types::Payload, req: web::HttpRequest) -> AppResult<impl Responder> {
+    let mut payload = RequestPayload::new(&mut payload);
+    let req_data: ListReq = payload.parse().await?;
+    req_data.validate()?;
+    let user_id = get_user_id(&req);
+    // users without MANAGE_FILE only see their own uploads
+    let owner = match check_permission_api(Some(user_id), MANAGE_FILE).await{
+        Ok(_) => None,
+        Err(_) => Some(user_id)
+    };
+    let db_res = fileDao::list(get_db_pool(),
+        owner,
+        req_data.limit,
+        (req_data.page-1).checked_mul(req_data.limit).ok_or(TooMaxParameter)?,
+        req_data.filter,
+        req_data.sort).await?;
+    Ok(web::HttpResponse::Ok().json(
+        &ListRes{
+            total: db_res.0,
+            files: db_res.1
+        }
+    ))
+}
+
+#[derive(Debug, Validate, Deserialize)]
+struct RenameReq<'a> {
+    id: i32,
+    #[validate(length(min = 1, max = 255))]
+    name: &'a str,
+}
+#[web::post("/rename")]
+async fn rename(mut payload: web::types::Payload, req: web::HttpRequest) -> AppResult<impl Responder> {
+    let mut payload = RequestPayload::new(&mut payload);
+    let req_data: RenameReq<'_> = payload.parse().await?;
+    req_data.validate()?;
+    let file = get_accessible_file(get_user_id(&req), req_data.id).await?;
+    fileDao::rename(get_db_pool(), file.id, req_data.name).await?;
+    Ok(web::HttpResponse::Ok().finish())
+}
+
+#[web::post("/delete/{file_id}")]
+async fn delete(path: web::types::Path<i32>, req: web::HttpRequest) -> AppResult<impl Responder> {
+    let file = get_accessible_file(get_user_id(&req), path.into_inner()).await?;
+    service::delete(&file).await?;
+    Ok(web::HttpResponse::Ok().finish())
+}
+
+#[derive(Debug, Deserialize)]
+struct GetReq {
+    size: Option<u32>,
+}
+// anyone holding the access key may fetch the file, that is how covers and attachments are embedded
+#[web::get("/get/{access_key}")]
+async fn get(path: web::types::Path<String>, query: web::types::Query<GetReq>,
+    req: web::HttpRequest) -> AppResult<impl Responder> {
+    let file = fileDao::select_by_access_key(get_db_pool(), &path).await?
+        .ok_or(NotFound)?;
+    let thumbnail = match query.size{
+        Some(size) if ALLOWED_IMAGE_MIME.exact_match(&file.mime) => service::get_thumbnail(&file, size).await,
+        _ => None
+    };
+    // the format is part of the etag, the same size may exist as webp and as the source format
+    let (path, content_type, etag) = match thumbnail{
+        Some((path, size, format)) => (path, format.to_mime_type().to_string(),
+            format!("{}-{size}.{}", file.hash, format.extensions_str()[0])),
+        None => (PathBuf::from(&file.path), file.mime.clone(), file.hash.clone())
+    };
+    ServedFile{
+        policy_id: file.policy,
+        user_id: file.owner,
+        path,
+        content_type,
+        etag: Some(etag),
+        cache_control: get_config!(http).file_cache_control.clone(),
+    }.respond(&req).await
+}
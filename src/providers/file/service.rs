@@ -0,0 +1,168 @@
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use chrono::Utc;
+use ::image::ImageFormat;
+use rustle_derive::ErrorHelper;
+use tokio::io::AsyncRead;
+use tracing::{error, warn};
+use uuid::Uuid;
+use crate::db::{get_db_pool, file as fileDao, file::File};
+use crate::external::fs::interface::FsProvider;
+use crate::external::fs::{DEFAULT_POLICY_ID, FsUserError};
+use crate::external::fs::quota::{get_effective_quota, get_storage_state};
+use crate::providers::auth::permission::MANAGE_FILE;
+use crate::providers::auth::service::check_permission_api;
+use crate::types::err::{AppResult, GlobalInternalError, GlobalUserError};
+use crate::get_config;
+use crate::utils::{image, sniffer};
+use crate::utils::sniffer::FileType;
+use crate::utils::request::ALLOWED_IMAGE_MIME;
+use crate::utils::stream::{read_head, AsyncReadMerger, HashReader};
+
+#[derive(ErrorHelper)]
+#[err(user, default_msg)]
+pub enum FileUserError{
+    #[err(code = 409)]
+    FileInUse,
+    #[err(code = 415)]
+    UnsupportedType,
+}
+
+pub async fn save_upload<T: AsyncRead + Unpin>(mut stream: T, owner: i32, name: &str, declared: &str) -> AppResult<File>{
+    if !get_storage_state(owner).await?.can_add_file(){
+        return Err(FsUserError::QuotaExceeded.into());
+    }
+    let head = read_head(&mut stream, sniffer::SNIFF_LEN).await.map_err(|e| {
+        error!("cannot read upload head: {:?}", e);
+        GlobalInternalError::IO
+    })?;
+    let file_type = sniffer::sniff(&head);
+    if !sniffer::declared_matches(file_type, declared){
+        return Err(GlobalUserError::InvalidMime.into());
+    }
+    // svg may carry scripts and there is no sanitizer we trust, so it is refused
+    if file_type == FileType::Svg{
+        return Err(FileUserError::UnsupportedType.into());
+    }
+    let mime = file_type.mime();
+    let stream = AsyncReadMerger::new(Cursor::new(head), stream);
+    if !ALLOWED_IMAGE_MIME.exact_match(mime){
+        return store(HashReader::new(stream), mime, owner, name).await;
+    }
+    // images are decoded to be validated and re-encoded to get rid of their metadata
+    let image_config = get_config!(image).clone();
+    let data = image::read_image_input(stream, &image_config).await?;
+    let sanitized = image::run_blocking(move || image::sanitize(data, &image_config)).await?;
+    let mime = sanitized.mime();
+    let file = store(HashReader::new(Cursor::new(&sanitized.data)), mime, owner, name).await?;
+    create_thumbnails(&file, sanitized.data).await;
+    Ok(file)
+}
+
+async fn store<T: AsyncRead + Unpin>(mut stream: HashReader<T>, mime: &str, owner: i32, name: &str) -> AppResult<File>{
+    let path = format!("__file/{}", Uuid::new_v4());
+    let size = FsProvider::upload_file_internal(
+        &mut stream,
+        DEFAULT_POLICY_ID,
+        owner,
+        Path::new(&path)
+    ).await?;
+    let mut file = File{
+        id: 0,
+        policy: DEFAULT_POLICY_ID,
+        owner,
+        name: name.to_string(),
+        path,
+        size: size as i64,
+        mime: mime.to_string(),
+        hash: stream.finalize(),
+        access_key: Uuid::new_v4().simple().to_string(),
+        ref_count: 0,
+        created_at: Utc::now(),
+    };
+    // the size was only checked against the quota before; another upload may have taken the rest by now
+    let created = match get_effective_quota(owner).await{
+        Ok(quota) => fileDao::create(get_db_pool(), &file, &quota).await.map_err(Into::into),
+        Err(e) => Err(e)
+    };
+    file.id = match created{
+        Ok(Some(id)) => id,
+        Ok(None) => {
+            let _ = FsProvider::delete_file(file.policy, owner, Path::new(&file.path)).await;
+            return Err(FsUserError::QuotaExceeded.into());
+        },
+        Err(e) => {
+            let _ = FsProvider::delete_file(file.policy, owner, Path::new(&file.path)).await;
+            return Err(e);
+        }
+    };
+    Ok(file)
+}
+
+fn thumbnail_path(file: &File, size: u32, format: ImageFormat) -> String{
+    format!("{}_{size}.{}", file.path, format.extensions_str()[0])
+}
+
+// made along with the upload and stored next to the original on its storage policy, a file
+// without them is still served, just in full
+async fn create_thumbnails(file: &File, data: Vec<u8>){
+    let image_config = get_config!(image).clone();
+    let sizes = image_config.thumbnail_sizes.clone();
+    let thumbnails = match image::run_blocking(move || image::thumbnails(&data, &sizes, &image_config)).await{
+        Ok(t) => t,
+        Err(e) => {
+            warn!("cannot create thumbnails of file {}: {}", file.id, e);
+            return;
+        }
+    };
+    for (size, thumbnail) in thumbnails{
+        let path = thumbnail_path(file, size, thumbnail.format);
+        if let Err(e) = FsProvider::put_file(&mut Cursor::new(thumbnail.data), file.policy, file.owner, Path::new(&path)).await{
+            warn!("cannot store thumbnail {} of file {}: {}", size, file.id, e);
+        }
+    }
+}
+
+// never generated here, the request may come from anyone; the configured format is preferred,
+// thumbnails made before transcode_webp was toggled are still found
+pub async fn get_thumbnail(file: &File, requested: u32) -> Option<(PathBuf, u32, ImageFormat)>{
+    let image_config = get_config!(image).clone();
+    let size = image::nearest_size(&image_config.thumbnail_sizes, requested)?;
+    let source_format = ImageFormat::from_mime_type(&file.mime)?;
+    let preferred = image::output_format(source_format, &image_config);
+    for format in [preferred, ImageFormat::WebP, ImageFormat::Jpeg, ImageFormat::Png]{
+        let path = PathBuf::from(thumbnail_path(file, size, format));
+        if FsProvider::stat_file(file.policy, file.owner, &path).await.is_ok(){
+            return Some((path, size, format));
+        }
+    }
+    None
+}
+
+// the owner can always manage their files, others need MANAGE_FILE
+pub async fn check_file_access(user_id: i32, file: &File) -> AppResult<()>{
+    if file.owner == user_id{
+        return Ok(());
+    }
+    check_permission_api(Some(user_id), MANAGE_FILE).await
+}
+
+pub async fn get_accessible_file(user_id: i32, file_id: i32) -> AppResult<File>{
+    let file = fileDao::select_by_id(get_db_pool(), file_id).await?
+        .ok_or(GlobalUserError::NotFound)?;
+    check_file_access(user_id, &file).await?;
+    Ok(file)
+}
+
+pub async fn delete(file: &File) -> AppResult<()>{
+    if !fileDao::delete_unreferenced(get_db_pool(), file.id).await?{
+        return Err(FileUserError::FileInUse.into());
+    }
+    FsProvider::delete_file(file.policy, file.owner, Path::new(&file.path)).await?;
+    for size in &get_config!(image).thumbnail_sizes{
+        for format in [ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::WebP]{
+            FsProvider::delete_file(file.policy, file.owner, Path::new(&thumbnail_path(file, *size, format))).await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use fluent_templates::{LanguageIdentifier, Loader};
+use ntex::web::{self, Responder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::error;
+use validator::Validate;
+use crate::db::{get_db_pool, mail as mailDao, mail::{MailState, QueuedMail}};
+use crate::external::fs::embed::LOCALES;
+use crate::external::mail::{self, template};
+use crate::middlewares::Auth;
+use crate::providers::auth::permission::MANAGE_MAIL;
+use crate::providers::auth::service::check_permission_api;
+use crate::providers::user::service::MailInternalError;
+use crate::types::err::AppResult;
+use crate::types::err::GlobalUserError::{NotFound, TooMaxParameter, UnknownLang};
+use crate::utils::request::get_user_id;
+
+pub fn init(cfg: &mut web::ServiceConfig){
+    cfg.service(
+        web::scope("/v1/mail").wrap(Auth)
+            .service(list)
+            .service(retry)
+            .service(purge)
+            .service(templates)
+            .service(preview)
+    );
+}
+
+#[derive(Debug, Validate, Deserialize)]
+struct ListReq {
+    state: Option<MailState>,
+    #[validate(range(min = 0, max = 100))]
+    limit: i32,
+    #[validate(range(min = 1,))]
+    page: i32
+}
+#[derive(Debug, Serialize)]
+struct ListRes {
+    total: i32,
+    mails: Vec<QueuedMail>
+}
+#[web::post("/list")]
+async fn list(req: web::HttpRequest, req_data: web::types::Json<ListReq>) -> AppResult<impl Responder> {
+    req_data.validate()?;
+    check_permission_api(Some(get_user_id(&req)), MANAGE_MAIL).await?;
+    let db_res = mailDao::list(get_db_pool(),
+        req_data.state,
+        req_data.limit,
+        (req_data.page-1).checked_mul(req_data.limit).ok_or(TooMaxParameter)?).await?;
+    Ok(web::HttpResponse::Ok().json(
+        &ListRes{
+            total: db_res.0,
+            mails: db_res.1
+        }
+    ))
+}
+
+#[web::post("/retry/{mail_id}")]
+async fn retry(path: web::types::Path<i32>, req: web::HttpRequest) -> AppResult<impl Responder> {
+    check_permission_api(Some(get_user_id(&req)), MANAGE_MAIL).await?;
+    if !mailDao::retry(get_db_pool(), path.into_inner()).await?{
+        return Err(NotFound.into());
+    }
+    mail::wake();
+    Ok(web::HttpResponse::Ok().finish())
+}
+
+#[derive(Debug, Deserialize)]
+struct PurgeReq {
+    state: MailState
+}
+#[web::post("/purge")]
+async fn purge(req: web::HttpRequest, req_data: web::types::Json<PurgeReq>) -> AppResult<impl Responder> {
+    check_permission_api(Some(get_user_id(&req)), MANAGE_MAIL).await?;
+    let purged = mailDao::purge(get_db_pool(), req_data.state).await?;
+    Ok(web::HttpResponse::Ok().body(
+        json!({
+            "purged": purged
+        })
+    ))
+}
+
+#[web::post("/templates")]
+async fn templates(req: web::HttpRequest) -> AppResult<impl Responder> {
+    check_permission_api(Some(get_user_id(&req)), MANAGE_MAIL).await?;
+    Ok(web::HttpResponse::Ok().json(&template::names()))
+}
+
+#[derive(Debug, Validate, Deserialize)]
+struct PreviewReq {
+    #[validate(length(min = 1, max = 64))]
+    template: String,
+    #[validate(length(min = 1, max = 10))]
+    lang: String,
+}
+#[web::post("/preview")]
+async fn preview(req: web::HttpRequest, req_data: web::types::Json<PreviewReq>) -> AppResult<impl Responder> {
+    req_data.validate()?;
+    check_permission_api(Some(get_user_id(&req)), MANAGE_MAIL).await?;
+    let li: LanguageIdentifier = req_data.lang.parse().map_err(|_| UnknownLang)?;
+    if !LOCALES.locales().any(|l| *l == li) {
+        return Err(UnknownLang.into());
+    }
+    if !template::names().contains(&req_data.template) {
+        return Err(NotFound.into());
+    }
+    let rendered = template::preview(&req_data.template, &li).map_err(|e| {
+        error!("failed to generate mail content, {:?}", e);
+        MailInternalError::Render
+    })?;
+    Ok(web::HttpResponse::Ok().body(
+        json!({
+            "html": rendered.html,
+            "text": rendered.text
+        })
+    ))
+}
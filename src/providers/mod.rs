@@ -5,16 +5,32 @@ use crate::{get_config, middlewares};
 pub mod auth;
 pub mod user;
 pub mod article;
+pub mod file;
 pub mod renderer;
+pub mod webhook;
+pub mod mail;
+pub mod newsletter;
+pub mod notification;
+pub mod cache;
+pub mod audit;
 
 
 pub async fn run() -> std::io::Result<()>{
     let http_config = get_config!(http);
         web::HttpServer::new(|| {
-            web::App::new().wrap(middlewares::Log)
+            web::App::new()
+            .wrap(middlewares::ExtensionHook)
+            .wrap(middlewares::Log)
             .configure(auth::api::init)
             .configure(user::api::init)
             .configure(article::api::init)
+            .configure(file::api::init)
+            .configure(webhook::api::init)
+            .configure(mail::api::init)
+            .configure(newsletter::api::init)
+            .configure(notification::api::init)
+            .configure(cache::api::init)
+            .configure(audit::api::init)
         })
         .bind((http_config.host.as_str(), http_config.port))?
         .run()
@@ -0,0 +1,125 @@
+use fluent_templates::LanguageIdentifier;
+use ntex::web::{self, Responder};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+use super::service::{self, check_unsubscribe_code};
+use crate::db::{get_db_pool, subscriber as subscriberDao, subscriber::Subscriber};
+use crate::middlewares::Auth;
+use crate::providers::auth::permission::MANAGE_NEWSLETTER;
+use crate::providers::auth::service::check_permission_api;
+use crate::types::err::AppResult;
+use crate::types::err::GlobalUserError::{CredentialUnauthorized, NotFound, TooMaxParameter, UnknownLang};
+use crate::utils::csv::csv_field;
+use crate::utils::request::{get_client_ip, get_user_id, RequestPayload};
+
+pub fn init(cfg: &mut web::ServiceConfig){
+    cfg.service(
+        web::scope("/v1/newsletter")
+            .service(subscribe)
+            .service(unsubscribe)
+            .service(unsubscribe_one_click)
+            .service(
+                web::scope("/").wrap(Auth)
+                .service(list)
+                .service(export)
+                .service(remove)
+            )
+    );
+}
+
+#[derive(Debug, Validate, Deserialize)]
+struct SubscribeReq<'a> {
+    #[validate(email, length(min = 3, max = 100))]
+    pub email: &'a str,
+    #[validate(length(min = 1, max = 10))]
+    pub lang: &'a str,
+}
+#[web::post("/subscribe")]
+async fn subscribe(req: web::HttpRequest, mut payload: web::types::Payload) -> AppResult<impl Responder> {
+    let mut payload = RequestPayload::new(&mut payload);
+    let req_data = payload.parse::<SubscribeReq>().await?;
+    req_data.validate()?;
+    let li: LanguageIdentifier = req_data.lang.parse().map_err(|_| UnknownLang)?;
+    let client = get_client_ip(&req).map(|ip| ip.to_string()).unwrap_or_default();
+    service::subscribe(&client, req_data.email, &li).await?;
+    Ok(web::HttpResponse::Ok().finish())
+}
+
+// opened from the link in the mail body, only asks for confirmation
+#[web::get("/unsubscribe/{code}")]
+async fn unsubscribe(path: web::types::Path<String>) -> AppResult<impl Responder> {
+    let id = check_unsubscribe_code(&path).ok_or(CredentialUnauthorized)?;
+    Ok(web::HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(service::unsubscribe_page(id, false).await?))
+}
+// the confirmation form, and RFC 8058 one-click from the List-Unsubscribe header
+#[web::post("/unsubscribe/{code}")]
+async fn unsubscribe_one_click(path: web::types::Path<String>) -> AppResult<impl Responder> {
+    let id = check_unsubscribe_code(&path).ok_or(CredentialUnauthorized)?;
+    let page = service::unsubscribe_page(id, true).await?;
+    subscriberDao::delete(get_db_pool(), id).await?;
+    Ok(web::HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(page))
+}
+
+#[derive(Debug, Validate, Deserialize)]
+struct ListReq {
+    #[validate(range(min = 0, max = 100))]
+    limit: i32,
+    #[validate(range(min = 1,))]
+    page: i32
+}
+#[derive(Debug, Serialize)]
+struct ListRes {
+    total: i32,
+    subscribers: Vec<Subscriber>
+}
+#[web::post("/list")]
+async fn list(req: web::HttpRequest, req_data: web::types::Json<ListReq>) -> AppResult<impl Responder> {
+    req_data.validate()?;
+    check_permission_api(Some(get_user_id(&req)), MANAGE_NEWSLETTER).await?;
+    let db_res = subscriberDao::list(get_db_pool(),
+        req_data.limit,
+        (req_data.page-1).checked_mul(req_data.limit).ok_or(TooMaxParameter)?).await?;
+    Ok(web::HttpResponse::Ok().json(
+        &ListRes{
+            total: db_res.0,
+            subscribers: db_res.1
+        }
+    ))
+}
+
+// confirmed subscribers as csv
+#[web::post("/export")]
+async fn export(req: web::HttpRequest) -> AppResult<impl Responder> {
+    check_permission_api(Some(get_user_id(&req)), MANAGE_NEWSLETTER).await?;
+    let mut csv = String::from("email,lang,confirmed_at\n");
+    let mut after = 0;
+    loop {
+        let subscribers = subscriberDao::select_confirmed_after(get_db_pool(), after, 1000).await?;
+        let Some(last) = subscribers.last() else {
+            break;
+        };
+        after = last.id;
+        for s in &subscribers {
+            // a quoted local part may hold commas, quotes and line breaks
+            csv.push_str(&format!("{},{},{}\n",
+                csv_field(&s.email), csv_field(&s.lang), s.confirmed_at.map(|t| t.to_rfc3339()).unwrap_or_default()));
+        }
+    }
+    Ok(web::HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .header("content-disposition", "attachment; filename=\"subscribers.csv\"")
+        .body(csv))
+}
+
+#[web::post("/remove/{subscriber_id}")]
+async fn remove(path: web::types::Path<i32>, req: web::HttpRequest) -> AppResult<impl Responder> {
+    check_permission_api(Some(get_user_id(&req)), MANAGE_NEWSLETTER).await?;
+    if !subscriberDao::delete(get_db_pool(), path.into_inner()).await?{
+        return Err(NotFound.into());
+    }
+    Ok(web::HttpResponse::Ok().finish())
+}
@@ -0,0 +1,171 @@
+use std::sync::atomic;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use fluent_templates::{LanguageIdentifier, Loader};
+use handlebars::html_escape;
+use once_cell::sync::Lazy;
+use rustle_derive::ErrorHelper;
+use tracing::{error, info};
+
+use crate::db::{get_db_pool, subscriber as subscriberDao, verification as verificationDao};
+use crate::db::mail::NewMail;
+use crate::db::verification::ACTION_SUBSCRIBE;
+use crate::external::fs::embed::LOCALES;
+use crate::external::mail::{self, MailNewPostTemplate, MailToLinkTemplate, MAILER_ENABLED};
+use crate::get_config;
+use crate::internal::event::{self, ArticlePublished, Event, EventKind};
+use crate::providers::user::service::{sign_verification, MailInternalError};
+use crate::types::err::{AppResult, EmptyErrResult};
+use crate::types::err::GlobalUserError::FeatureNotEnabled;
+use crate::types::service::AppService;
+use crate::utils::hmac::{hmac_signature_url_safe, hmac_verify_url_safe};
+use crate::utils::rate_limit::RateLimiter;
+
+// subscribers rendered and queued per transaction
+const BATCH: i32 = 200;
+// one confirmation mail per address in this many minutes
+const CONFIRMATION_INTERVAL_MINUTES: i64 = 10;
+// subscriptions a single client may ask for in an hour
+static SUBSCRIBE_LIMIT: Lazy<RateLimiter> = Lazy::new(|| RateLimiter::new(Duration::from_secs(60 * 60), 10));
+
+#[derive(ErrorHelper)]
+#[err(user, default_msg)]
+pub enum NewsletterUserError{
+    #[err(code = 429)]
+    TooManyRequests,
+}
+
+pub struct NewsletterService;
+impl AppService for NewsletterService {
+    fn name() -> &'static str {
+        "NewsletterService"
+    }
+    async fn initialize() -> EmptyErrResult<()> {
+        event::subscribe_action(EventKind::ArticlePublished, Arc::new(|e| Box::pin(notify(e))));
+        Ok(())
+    }
+}
+
+// an address that is already confirmed or was just sent a confirmation gets no mail,
+// so the answer does not tell who subscribed
+pub async fn subscribe(client: &str, email: &str, lang: &LanguageIdentifier) -> AppResult<()>{
+    if !MAILER_ENABLED.load(atomic::Ordering::Relaxed) {
+        return Err(FeatureNotEnabled.into());
+    }
+    if !SUBSCRIBE_LIMIT.check(client) {
+        return Err(NewsletterUserError::TooManyRequests.into());
+    }
+    if subscriberDao::select_by_email(get_db_pool(), email).await?.is_some_and(|s| s.confirmed) {
+        return Ok(());
+    }
+    let since = (Utc::now() - chrono::Duration::minutes(CONFIRMATION_INTERVAL_MINUTES)).naive_utc();
+    if verificationDao::count_since(get_db_pool(), email, ACTION_SUBSCRIBE, since).await? > 0 {
+        return Ok(());
+    }
+    subscriberDao::upsert(get_db_pool(), email, &lang.to_string()).await?;
+    // there is no user behind a subscription
+    let verification_id = verificationDao::create(get_db_pool(), 0, email, "", ACTION_SUBSCRIBE).await?;
+    let site_info = get_config!(info);
+    let content = MailToLinkTemplate {
+        site_name: site_info.name.clone(),
+        site_link: site_info.link.clone(),
+        user: email.to_string(),
+        link: sign_verification(verification_id),
+        action: LOCALES.lookup(lang, "newsletter_subscribe").unwrap(),
+        lang: lang.to_string(),
+    }.generate().map_err(|x| {
+        error!("failed to generate mail content, {:?}", x);
+        MailInternalError::Render
+    })?;
+    mail::queue(content,
+        LOCALES.lookup(lang, "email_check").unwrap().as_str(),
+        email,
+        email).await
+}
+
+// served by the api itself, mail clients POST to it for one-click unsubscribe
+fn unsubscribe_link(id: i32) -> String{
+    format!("{}/v1/newsletter/unsubscribe/{}.{}",
+        get_config!(info).link,
+        hmac_signature_url_safe(&get_config!(security).credential_secret, &format!("unsubscribe.{id}")),
+        id)
+}
+pub fn check_unsubscribe_code(code: &str) -> Option<i32>{
+    let (sig, id) = code.split_once('.')?;
+    if !hmac_verify_url_safe(&get_config!(security).credential_secret, &format!("unsubscribe.{id}"), sig) {
+        return None;
+    }
+    id.parse().ok()
+}
+
+// the link in the mail only asks, a prefetching mail scanner must not unsubscribe anyone;
+// the form posts back to the same address, like a one-click client does
+pub async fn unsubscribe_page(id: i32, done: bool) -> AppResult<String>{
+    let lang: LanguageIdentifier = subscriberDao::select_by_id(get_db_pool(), id).await?
+        .and_then(|s| s.lang.parse().ok())
+        .unwrap_or_else(|| "en-US".parse().unwrap());
+    let text = |key: &str| html_escape(&LOCALES.lookup(&lang, key).unwrap_or_else(|| key.to_string()));
+    let body = if done {
+        format!("<p>{}</p>", text("unsubscribe_done"))
+    } else {
+        format!("<form method=\"post\"><p>{}</p><button type=\"submit\">{}</button></form>",
+            text("unsubscribe_confirm"), text("unsubscribe_button"))
+    };
+    Ok(format!("<!DOCTYPE html><html lang=\"{}\"><head><meta charset=\"utf-8\"><meta name=\"robots\" content=\"noindex\"><title>{}</title></head><body>{}</body></html>",
+        lang, html_escape(&get_config!(info).name), body))
+}
+
+async fn notify(event: Arc<Event>) -> Result<(), String>{
+    let Event::ArticlePublished(article) = event.as_ref() else {
+        return Ok(());
+    };
+    let Some(article_id) = article.id else {
+        return Ok(());
+    };
+    if !MAILER_ENABLED.load(atomic::Ordering::Relaxed) {
+        return Ok(());
+    }
+    // subscribers queued for by an earlier attempt are skipped, so a failed one can be run again
+    let mut after = 0;
+    let mut total = 0;
+    loop {
+        let subscribers = subscriberDao::select_unnotified_after(get_db_pool(), article_id, after, BATCH).await
+            .map_err(|e| e.to_string())?;
+        let Some(last) = subscribers.last() else {
+            break;
+        };
+        after = last.id;
+        let mails = subscribers.iter()
+            .filter_map(|s| {
+                let lang: LanguageIdentifier = s.lang.parse().unwrap_or_else(|_| "en-US".parse().unwrap());
+                new_post_mail(article, &lang, &s.email, s.id)
+                    .inspect_err(|e| error!("cannot compose new post mail to subscriber {}: {}", s.id, e))
+                    .ok()
+                    .map(|m| (s.id, m))
+            })
+            .collect::<Vec<_>>();
+        total += subscriberDao::queue_new_post(get_db_pool(), article_id, &mails).await.map_err(|e| e.to_string())?;
+        mail::wake();
+    }
+    info!("new post {} queued for {} subscribers", article.alias, total);
+    Ok(())
+}
+fn new_post_mail(article: &ArticlePublished, lang: &LanguageIdentifier, email: &str, subscriber: i32) -> AppResult<NewMail>{
+    let site_info = get_config!(info);
+    let unsubscribe = unsubscribe_link(subscriber);
+    let content = MailNewPostTemplate {
+        site_name: site_info.name.clone(),
+        site_link: site_info.link.clone(),
+        title: article.title.clone(),
+        alias: article.alias.clone(),
+        unsubscribe: unsubscribe.clone(),
+        lang: lang.to_string(),
+    }.generate().map_err(|x| {
+        error!("failed to generate mail content, {:?}", x);
+        MailInternalError::Render
+    })?;
+    let subject = format!("{}: {}", LOCALES.lookup(lang, "new_post").unwrap(), article.title);
+    mail::compose(content, &subject, None, email, Some(unsubscribe))
+}
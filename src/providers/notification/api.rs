@@ -0,0 +1,108 @@
+use ntex::web::{self, Responder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use validator::Validate;
+use crate::db::{get_db_pool, notification as notificationDao, notification::{Channel, Notification, NotificationKind}};
+use crate::middlewares::Auth;
+use crate::types::err::AppResult;
+use crate::types::err::GlobalUserError::TooMaxParameter;
+use crate::utils::request::get_user_id;
+
+pub fn init(cfg: &mut web::ServiceConfig){
+    cfg.service(
+        web::scope("/v1/notification").wrap(Auth)
+            .service(list)
+            .service(unread_count)
+            .service(mark_read)
+            .service(mark_all_read)
+            .service(preferences)
+            .service(set_preference)
+    );
+}
+
+#[derive(Debug, Validate, Deserialize)]
+struct ListReq {
+    #[serde(default)]
+    unread_only: bool,
+    #[validate(range(min = 0, max = 100))]
+    limit: i32,
+    #[validate(range(min = 1,))]
+    page: i32
+}
+#[derive(Debug, Serialize)]
+struct ListRes {
+    total: i32,
+    notifications: Vec<Notification>
+}
+#[web::post("/list")]
+async fn list(req: web::HttpRequest, req_data: web::types::Json<ListReq>) -> AppResult<impl Responder> {
+    req_data.validate()?;
+    let db_res = notificationDao::list(get_db_pool(),
+        get_user_id(&req),
+        req_data.unread_only,
+        req_data.limit,
+        (req_data.page-1).checked_mul(req_data.limit).ok_or(TooMaxParameter)?).await?;
+    Ok(web::HttpResponse::Ok().json(
+        &ListRes{
+            total: db_res.0,
+            notifications: db_res.1
+        }
+    ))
+}
+
+#[web::post("/unread_count")]
+async fn unread_count(req: web::HttpRequest) -> AppResult<impl Responder> {
+    let count = notificationDao::unread_count(get_db_pool(), get_user_id(&req)).await?;
+    Ok(web::HttpResponse::Ok().body(
+        json!({
+            "count": count
+        })
+    ))
+}
+
+#[derive(Debug, Validate, Deserialize)]
+struct MarkReadReq {
+    #[validate(length(min = 1, max = 100))]
+    ids: Vec<i32>
+}
+#[web::post("/mark_read")]
+async fn mark_read(req: web::HttpRequest, req_data: web::types::Json<MarkReadReq>) -> AppResult<impl Responder> {
+    req_data.validate()?;
+    notificationDao::mark_read(get_db_pool(), get_user_id(&req), &req_data.ids).await?;
+    Ok(web::HttpResponse::Ok().finish())
+}
+
+#[web::post("/mark_all_read")]
+async fn mark_all_read(req: web::HttpRequest) -> AppResult<impl Responder> {
+    notificationDao::mark_all_read(get_db_pool(), get_user_id(&req)).await?;
+    Ok(web::HttpResponse::Ok().finish())
+}
+
+#[derive(Debug, Serialize)]
+struct PreferenceRes {
+    kind: NotificationKind,
+    channel: Channel
+}
+// every kind with the channel in effect, defaults included
+#[web::post("/preferences")]
+async fn preferences(req: web::HttpRequest) -> AppResult<impl Responder> {
+    let stored = notificationDao::select_preferences(get_db_pool(), get_user_id(&req)).await?;
+    let res: Vec<PreferenceRes> = NotificationKind::ALL.into_iter()
+        .map(|kind| PreferenceRes{
+            kind,
+            channel: stored.iter().find(|p| p.kind == kind).map_or(kind.default_channel(), |p| p.channel)
+        })
+        .collect();
+    Ok(web::HttpResponse::Ok().json(&res))
+}
+
+#[derive(Debug, Deserialize)]
+struct SetPreferenceReq {
+    kind: NotificationKind,
+    channel: Channel
+}
+#[web::post("/set_preference")]
+async fn set_preference(req: web::HttpRequest, req_data: web::types::Json<SetPreferenceReq>) -> AppResult<impl Responder> {
+    notificationDao::set_preference(get_db_pool(), get_user_id(&req), req_data.kind, req_data.channel).await?;
+    Ok(web::HttpResponse::Ok().finish())
+}
@@ -0,0 +1,101 @@
+use std::sync::atomic;
+use std::sync::Arc;
+
+use fluent_templates::{LanguageIdentifier, Loader};
+use serde_json::json;
+use tracing::error;
+
+use crate::db::{article as articleDao, get_db_pool, notification as notificationDao, profile as profileDao, user as userDao};
+use crate::db::notification::NotificationKind;
+use crate::external::fs::embed::LOCALES;
+use crate::external::mail::{self, MailNotificationTemplate, MAILER_ENABLED};
+use crate::get_config;
+use crate::internal::event::{self, Event, EventKind};
+use crate::providers::user::service::MailInternalError;
+use crate::types::err::{AppResult, EmptyErrResult};
+use crate::types::service::AppService;
+
+// for users without a usable locale in their profile
+const FALLBACK_LANG: &str = "en-US";
+const EXCERPT_CHARS: usize = 140;
+
+pub struct NotificationService;
+impl AppService for NotificationService {
+    fn name() -> &'static str {
+        "NotificationService"
+    }
+    async fn initialize() -> EmptyErrResult<()> {
+        event::subscribe_action(EventKind::CommentPosted, Arc::new(|e| Box::pin(on_comment(e))));
+        Ok(())
+    }
+}
+
+// the channel comes from the preferences of the user, or the default of the kind
+pub async fn notify(user: i32, kind: NotificationKind, payload: serde_json::Value) -> AppResult<()>{
+    let channel = notificationDao::select_channel(get_db_pool(), user, kind).await?;
+    if channel.in_app() {
+        notificationDao::create(get_db_pool(), user, kind, &payload).await?;
+    }
+    if channel.email() && MAILER_ENABLED.load(atomic::Ordering::Relaxed) {
+        send_email(user, kind, &payload).await?;
+    }
+    Ok(())
+}
+
+async fn send_email(user: i32, kind: NotificationKind, payload: &serde_json::Value) -> AppResult<()>{
+    let Some(user) = userDao::select_by_id(get_db_pool(), user).await? else {
+        return Ok(());
+    };
+    let lang: LanguageIdentifier = profileDao::select(get_db_pool(), user.id).await?.locale
+        .and_then(|l| l.parse().ok())
+        .unwrap_or_else(|| FALLBACK_LANG.parse().unwrap_or_default());
+    // a locale without translations falls back inside the loader, a missing key shows as itself
+    let key = format!("notify_{}", kind.as_str());
+    let title = LOCALES.lookup(&lang, &key).unwrap_or(key);
+    let site_info = get_config!(info);
+    let content = MailNotificationTemplate {
+        site_name: site_info.name.clone(),
+        site_link: site_info.link.clone(),
+        user: user.name.clone(),
+        title: title.clone(),
+        detail: payload.get("excerpt").and_then(|e| e.as_str()).map(String::from),
+        link: payload.get("link").and_then(|e| e.as_str()).map(String::from),
+        lang: lang.to_string(),
+    }.generate().map_err(|x| {
+        error!("failed to generate mail content, {:?}", x);
+        MailInternalError::Render
+    })?;
+    mail::queue(content, &title, &user.name, &user.email).await
+}
+
+fn excerpt(content: &str) -> String{
+    match content.char_indices().nth(EXCERPT_CHARS) {
+        Some((i, _)) => format!("{}…", &content[..i]),
+        None => content.to_string()
+    }
+}
+
+// nobody is told about their own comment, and a reply on the own article is only reported once
+async fn on_comment(event: Arc<Event>) -> Result<(), String>{
+    let Event::CommentPosted(comment) = event.as_ref() else {
+        return Ok(());
+    };
+    let Some(article) = articleDao::select_by_id(get_db_pool(), comment.article).await.map_err(|e| e.to_string())? else {
+        return Ok(());
+    };
+    let payload = json!({
+        "article": comment.article,
+        "comment": comment.id,
+        "author": comment.author,
+        "excerpt": excerpt(&comment.content),
+        "link": format!("{}/article/{}", get_config!(info).link, article.alias)
+    });
+    let parent_author = comment.parent_author.filter(|p| *p != comment.author);
+    if let Some(parent_author) = parent_author {
+        notify(parent_author, NotificationKind::CommentReply, payload.clone()).await.map_err(|e| e.to_string())?;
+    }
+    if article.author != comment.author && Some(article.author) != parent_author {
+        notify(article.author, NotificationKind::ArticleComment, payload).await.map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
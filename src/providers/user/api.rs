@@ -1,30 +1,42 @@
-use super::service::{send_tolink_email, send_verify_email};
+use super::export::{self as exportService, check_download_code};
+use super::service::{self as userService, check_verification_code, register_user, send_verify_email, UserUserError};
 use crate::db::rbac::RoleSimple;
-use crate::db::user::User;
-use crate::db::{get_db_pool, rbac as rbacDao, user as userDao, verification as verificationDao};
+use crate::db::user::{User, UserFilterable, UserSortable, STATUS_ACTIVE, STATUS_BANNED};
+use crate::db::{export as exportDao, get_db_pool, profile as profileDao, quota as quotaDao, rbac as rbacDao, subscriber as subscriberDao, user as userDao, verification as verificationDao};
+use crate::db::profile::{Profile, ProfileLink};
+use crate::db::export::{DataExport, EXPORT_READY};
+use crate::db::verification::{ACTION_CANCEL_EMAIL_CHANGE, ACTION_CHANGE_EMAIL, ACTION_DELETE_ACCOUNT, ACTION_REVERT_EMAIL_CHANGE, ACTION_SUBSCRIBE};
+use crate::db::quota::Quota;
+use crate::external::fs::quota::{get_effective_quota, get_storage_state, StorageState};
 use crate::external::fs::interface::FsProvider;
-use crate::external::fs::DEFAULT_POLICY_ID;
+use crate::external::fs::{DEFAULT_POLICY_ID, FsUserError};
 use crate::get_config;
 use crate::middlewares::Auth;
+use crate::providers::auth::permission::MANAGE_USER;
+use crate::providers::audit::service::{self as audit, Target};
 use crate::providers::auth::service::check_permission_api;
+use crate::providers::cache::service::{self as cacheService, CacheChange};
 use crate::types::err::GlobalUserError::{
-    CredentialUnauthorized, NotFound, TooMaxParameter, UnknownLang
+    CredentialUnauthorized, InvalidMime, NotFound, PermissionDenied, TooMaxParameter, UnknownLang
 };
+use crate::external::fs::embed::LOCALES;
 use crate::types::err::{AppResult, GlobalInternalError};
-use crate::utils::hmac::hmac_verify;
-use crate::utils::{password_salt, sniffer};
+use crate::utils::{image, password_salt, sniffer};
+use crate::utils::stream::read_head;
 use crate::utils::request::{check_content_length, check_mime, get_user_id, RequestPayload, ALLOWED_IMAGE_MIME};
-use fluent_templates::LanguageIdentifier;
+use fluent_templates::{LanguageIdentifier, Loader};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use sqlx::types::Json;
 use futures_util::TryStreamExt;
 use rustle_derive::JoinHelper;
-use crate::utils::stream::{AsyncReadMerger, ReaderChunkedStream};
+use crate::external::fs::serve::ServedFile;
 use ntex::web::{self, Responder};
 use serde::{Deserialize, Serialize};
-use tokio::io::AsyncReadExt;
+use serde_json::json;
 use tokio_util::io::StreamReader;
 use std::borrow::Cow;
 use std::io::Cursor;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use validator::Validate;
 
 pub fn init(cfg: &mut web::ServiceConfig){
@@ -32,19 +44,33 @@ pub fn init(cfg: &mut web::ServiceConfig){
         web::scope("/v1/user")
             .service(verify_email)
             .service(get_avatar)
+            .service(public_profile)
+            .service(download_export)
             .service(
                 web::scope("/").wrap(Auth)
                 .service(change_email)
                 .service(change_password) 
                 .service(get_all_list) 
                 .service(upload_avatar)
+                .service(me)
+                .service(update_me)
+                .service(set_quota)
+                .service(create_user)
+                .service(update_user)
+                .service(force_password_reset)
+                .service(set_status)
+                .service(delete_user)
+                .service(request_export)
+                .service(list_exports)
+                .service(request_deletion)
+                .service(cancel_deletion)
             )
     );
 }
 
 #[derive(Debug, Validate, Deserialize)]
 struct ChangeEmailReq<'a> {
-    #[validate(length(min = 3, max = 100))]
+    #[validate(email, length(min = 3, max = 100))]
     pub email: &'a str, // as email should not contain any special characters, it's ok to use raw str
     #[validate(length(min = 1, max = 50))]
     pub password: Cow<'a, str>,
@@ -56,6 +82,7 @@ struct ChangeEmailReq<'a> {
 async fn change_email(mut payload: web::types::Payload, req: web::HttpRequest) -> AppResult<impl Responder> {
     let mut payload = RequestPayload::new(&mut payload);
     let req_data = payload.parse::<ChangeEmailReq>().await?;
+    req_data.validate()?;
     let li: LanguageIdentifier = req_data.lang.parse().map_err(|_| UnknownLang)?;
 
     let user_id: i32 = get_user_id(&req);
@@ -65,7 +92,10 @@ async fn change_email(mut payload: web::types::Payload, req: web::HttpRequest) -
     if !password_salt::compare_password(user.password.as_ref().unwrap(), &req_data.password) {
         return Err(CredentialUnauthorized.into());
     }
-    send_tolink_email(req_data.email, &user, "change_email", &li).await?;
+    userService::request_email_change(&user, req_data.email, &li).await?;
+    audit::record(&req, "user.request_email_change", Some(Target::User(user_id)), json!({
+        "email": { "old": user.email, "new": req_data.email }
+    })).await;
     Ok(web::HttpResponse::Ok().finish())
 }
 
@@ -77,7 +107,7 @@ struct ForgotPasswordReq<'a> {
     pub lang: &'a str,
 }
 #[web::post("/forgot_password")]
-async fn forgot_password(mut payload: web::types::Payload) -> AppResult<impl Responder> {
+async fn forgot_password(mut payload: web::types::Payload, req: web::HttpRequest) -> AppResult<impl Responder> {
     let mut payload = RequestPayload::new(&mut payload);
     let req_data = payload.parse::<ForgotPasswordReq>().await?;
     let li: LanguageIdentifier = req_data.lang.parse().map_err(|_| UnknownLang)?;
@@ -85,6 +115,7 @@ async fn forgot_password(mut payload: web::types::Payload) -> AppResult<impl Res
         .await?
         .ok_or(NotFound)?;
     send_verify_email(&user, "change_password", &li).await?;
+    audit::record_as(&req, None, "user.request_password_reset", Some(Target::User(user.id)), json!({})).await;
     Ok(web::HttpResponse::Ok().finish())
 }
 
@@ -107,7 +138,11 @@ async fn change_password(mut payload: web::types::Payload, req: web::HttpRequest
     if !password_salt::compare_password(user.password.as_ref().unwrap(), &req_data.old_password) {
         return Err(CredentialUnauthorized.into());
     }
-    userDao::update_password(get_db_pool(), user_id, &req_data.new_password).await?;
+    let hashed_password = password_salt::generate_password(req_data.new_password, &get_config!(security).password_salt)?;
+    userDao::update_password(get_db_pool(), user_id, &hashed_password).await?;
+    audit::record(&req, "user.change_password", Some(Target::User(user_id)), json!({})).await;
+    // lifts a forced reset
+    cacheService::publish(CacheChange::UserStatus(Some(user_id))).await?;
     Ok(web::HttpResponse::Ok().finish())
 }
 #[derive(Debug, Validate, Deserialize)]
@@ -116,38 +151,80 @@ struct VerifyEmailReq<'a> {
     pub code: &'a str,
 }
 #[web::post("/verify_email")]
-async fn verify_email(mut payload: web::types::Payload) -> AppResult<impl Responder> {
+async fn verify_email(mut payload: web::types::Payload, req: web::HttpRequest) -> AppResult<impl Responder> {
     let mut payload = RequestPayload::new(&mut payload);
     let req_data = payload.parse::<VerifyEmailReq>().await?;
-    let code_vec: Vec<&str> = req_data.code.split('.').collect();
-    if code_vec.len() != 2 {
-        return Err(CredentialUnauthorized.into());
-    }
-    if !hmac_verify(
-        &get_config!(security).credential_secret,
-        code_vec[1],
-        code_vec[0],
-    ) {
-        return Err(CredentialUnauthorized.into());
-    }
-    let verification_id: i32 = code_vec[1].parse().unwrap();
+    let verification_id = check_verification_code(req_data.code).ok_or(CredentialUnauthorized)?;
     let ver = verificationDao::select_by_id(get_db_pool(), verification_id)
         .await?
         .ok_or(CredentialUnauthorized)?;
-    userDao::update_email(get_db_pool(), ver.user, &ver.identity).await?;
+    match ver.action {
+        ACTION_CHANGE_EMAIL => {
+            if userService::is_email_change_expired(&ver) {
+                userService::cancel_email_change(ver.user).await?;
+                return Err(CredentialUnauthorized.into());
+            }
+            // the address may have been taken since the change was requested
+            let old = userService::confirm_email_change(&ver).await?;
+            // following the link proves the user, nobody is signed in here
+            audit::record_as(&req, Some(ver.user), "user.change_email", Some(Target::User(ver.user)), json!({
+                "email": { "old": old, "new": ver.identity }
+            })).await;
+        },
+        ACTION_CANCEL_EMAIL_CHANGE => {
+            userService::cancel_email_change(ver.user).await?;
+            if userService::is_email_change_expired(&ver) {
+                return Err(CredentialUnauthorized.into());
+            }
+            audit::record_as(&req, Some(ver.user), "user.cancel_email_change", Some(Target::User(ver.user)), json!({
+                "email": ver.identity
+            })).await;
+        },
+        ACTION_REVERT_EMAIL_CHANGE => {
+            if userService::is_email_change_expired(&ver) {
+                return Err(CredentialUnauthorized.into());
+            }
+            userService::revert_email_change(&ver).await?;
+            audit::record_as(&req, Some(ver.user), "user.revert_email_change", Some(Target::User(ver.user)), json!({
+                "email": ver.identity
+            })).await;
+        },
+        ACTION_SUBSCRIBE => {
+            subscriberDao::confirm(get_db_pool(), &ver.identity).await?;
+        },
+        ACTION_DELETE_ACCOUNT => {
+            let delete_after = Utc::now() + Duration::seconds(get_config!(account).deletion_grace_secs);
+            if !userDao::schedule_deletion(get_db_pool(), ver.user, Some(delete_after)).await? {
+                return Err(NotFound.into());
+            }
+            audit::record_as(&req, Some(ver.user), "user.schedule_deletion", Some(Target::User(ver.user)), json!({
+                "delete_after": delete_after
+            })).await;
+        },
+        // password codes among them, they are typed in rather than followed as a link
+        _ => return Err(CredentialUnauthorized.into())
+    }
     verificationDao::delete_by_id(get_db_pool(), verification_id).await?;
     Ok(web::HttpResponse::Ok().finish())
 }
 
 #[derive(Debug, Validate, Deserialize)]
 struct ListReq {
+    #[serde(default)]
+    filter: Vec<UserFilterable>,
+    #[serde(default)]
+    sort: Vec<UserSortable>,
+    // matched against part of the name or email
+    #[validate(length(min = 1, max = 100))]
+    keyword: Option<String>,
+    role: Option<i32>,
     #[validate(range(min = 0, max = 100))]
     limit: i32,
     #[validate(range(min = 1,))]
     page: i32,
 }
 #[derive(Serialize, JoinHelper)]
-struct ListRes {
+struct UserWithRoles {
     #[foreign_key(key = roles, type = Option<Vec<i32>>)]
     #[serde(flatten)]
     pub user: User,
@@ -161,55 +238,501 @@ async fn get_all_list(mut payload: web::types::Payload, req: web::HttpRequest) -
     req_data.validate()?;
 
     let user_id = get_user_id(&req);
-    check_permission_api(Some(user_id), "MANAGE_USER").await?;
+    check_permission_api(Some(user_id), MANAGE_USER).await?;
 
-    let users = userDao::get_list(
+    let (total, users) = userDao::list(
         get_db_pool(),
         req_data.limit,
         (req_data.page - 1)
             .checked_mul(req_data.limit)
             .ok_or(TooMaxParameter)?,
+        req_data.keyword.as_deref(),
+        req_data.role,
+        req_data.filter,
+        req_data.sort,
     )
     .await?;
-    let mut res = users.into_iter().map(|u| ListRes{user: u, role_infos: Vec::with_capacity(2)}).collect();
+    let mut res = users.into_iter().map(|u| UserWithRoles{user: u, role_infos: Vec::with_capacity(2)}).collect();
     rbacDao::join_user_role_info(get_db_pool(), &mut res).await?;
 
-    Ok(web::HttpResponse::Ok().json(
-        &res
-    ))
+    // the body stays a plain array for existing clients, the count of every match goes in a header
+    Ok(web::HttpResponse::Ok()
+        .header("x-total-count", total.to_string())
+        .json(&res))
 }
 
 #[web::post("/upload_avatar")]
 pub async fn upload_avatar(payload: web::types::Payload, 
     req: web::HttpRequest) -> AppResult<impl Responder> {
-    check_content_length(&req, get_config!(http).max_upload_size)?;
-    let _ = check_mime(&req, &ALLOWED_IMAGE_MIME)?;
+    let image_config = get_config!(image).clone();
+    check_content_length(&req, get_config!(http).max_upload_size.min(image_config.max_input_size))?;
+    let declared = check_mime(&req, &ALLOWED_IMAGE_MIME)?;
     
-    let mut stream = StreamReader::new(payload.map_err(|e| {
-        std::io::Error::new(std::io::ErrorKind::Other, e) 
-    }));
-    FsProvider::upload_file_internal(&mut stream, 
-        DEFAULT_POLICY_ID,
-        get_user_id(&req),
-        &Path::new("__user/avatar")
-    ).await?;
+    let stream = StreamReader::new(payload.map_err(std::io::Error::other));
+    let data = image::read_image_input(stream, &image_config).await?;
+    if !sniffer::declared_matches(sniffer::sniff(&data), declared) {
+        return Err(InvalidMime.into());
+    }
+    let variants = image::run_blocking(move || image::process_avatar(data, &image_config)).await?;
+    let user_id = get_user_id(&req);
+    // the largest variant doubles as the default avatar, so it is stored twice
+    let largest = variants.iter().max_by_key(|t| t.0).map(|t| t.1.data.len()).unwrap_or(0);
+    let bytes = variants.iter().map(|t| t.1.data.len()).sum::<usize>() + largest;
+    let quota = get_effective_quota(user_id).await?;
+    if !quotaDao::update_avatar_bytes(get_db_pool(), user_id, bytes as i64, &quota).await? {
+        return Err(FsUserError::QuotaExceeded.into());
+    }
+    for (size, variant) in &variants {
+        FsProvider::put_file(&mut Cursor::new(&variant.data), 
+            DEFAULT_POLICY_ID,
+            user_id,
+            Path::new(&format!("__user/avatar_{size}"))
+        ).await?;
+    }
+    if let Some((_, largest)) = variants.iter().max_by_key(|t| t.0) {
+        FsProvider::put_file(&mut Cursor::new(&largest.data), 
+            DEFAULT_POLICY_ID,
+            user_id,
+            Path::new("__user/avatar")
+        ).await?;
+    }
+    userDao::touch_avatar(get_db_pool(), user_id).await?;
     Ok(web::HttpResponse::Ok().finish())
 }
 
+#[derive(Debug, Deserialize)]
+struct GetAvatarReq {
+    size: Option<u32>,
+}
 #[web::get("/avatar/{user_id}")]
-pub async fn get_avatar(path: web::types::Path<u32>) -> AppResult<impl Responder> {
-    let mut file = FsProvider::get_file(DEFAULT_POLICY_ID, 
-        path.into_inner() as i32, &Path::new("__user/avatar")).await?;
-    let mut header = file.read_i64().await.map_err(|e| {
-        tracing::error!("read i64 from file error: {}", e);
+pub async fn get_avatar(path: web::types::Path<u32>, query: web::types::Query<GetAvatarReq>,
+    req: web::HttpRequest) -> AppResult<impl Responder> {
+    let user_id = path.into_inner() as i32;
+    let mut avatar_path = PathBuf::from("__user/avatar");
+    if let Some(size) = query.size.and_then(|s| image::nearest_size(&get_config!(image).avatar_sizes, s)) {
+        let variant = PathBuf::from(format!("__user/avatar_{size}"));
+        // avatars uploaded before variants existed only have the default one
+        if FsProvider::stat_file(DEFAULT_POLICY_ID, user_id, &variant).await.is_ok() {
+            avatar_path = variant;
+        }
+    }
+    let mut reader = FsProvider::get_file(DEFAULT_POLICY_ID, user_id, &avatar_path).await?;
+    let header = read_head(&mut reader, sniffer::SNIFF_LEN).await.map_err(|e| {
+        tracing::error!("read avatar header error: {}", e);
         GlobalInternalError::IO
-    })?.to_ne_bytes();
-    header.reverse();
-    let ext_str: &'static str = sniffer::image_sniff(header).into();
-    let stream = ReaderChunkedStream::new(
-        AsyncReadMerger::new(Cursor::new(header), file)
-    );
-    
-    Ok(web::HttpResponse::Ok().header("content-type", format!("image/{ext_str}"))
-        .streaming(stream))
+    })?;
+    ServedFile{
+        policy_id: DEFAULT_POLICY_ID,
+        user_id,
+        path: avatar_path,
+        content_type: sniffer::mime_sniff(&header).to_string(),
+        etag: None,
+        cache_control: get_config!(http).avatar_cache_control.clone(),
+    }.respond(&req).await
+}
+
+#[derive(Serialize)]
+struct MeRes {
+    #[serde(flatten)]
+    pub user: User,
+    pub profile: Profile,
+    pub storage: StorageState,
+    // set while a requested deletion waits for its grace period
+    pub delete_after: Option<DateTime<Utc>>,
+}
+#[web::get("/me")]
+async fn me(req: web::HttpRequest) -> AppResult<impl Responder> {
+    let user_id = get_user_id(&req);
+    let user = userDao::select_by_id(get_db_pool(), user_id)
+        .await?
+        .ok_or(NotFound)?;
+    Ok(web::HttpResponse::Ok().json(&MeRes{
+        user,
+        profile: profileDao::select(get_db_pool(), user_id).await?,
+        storage: get_storage_state(user_id).await?,
+        delete_after: userDao::select_delete_after(get_db_pool(), user_id).await?
+    }))
+}
+// a field left out keeps its value, null clears it; links are replaced as a whole when given
+#[derive(Debug, Validate, Deserialize)]
+struct UpdateMeReq {
+    #[serde(default, deserialize_with = "present")]
+    #[validate(length(min = 1, max = 50))]
+    display_name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    #[validate(length(max = 1000))]
+    bio: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    #[validate(url, length(max = 255), custom = "crate::utils::validate::check_http_url")]
+    website: Option<Option<String>>,
+    #[validate(length(max = 10))]
+    links: Option<Vec<ProfileLink>>,
+    #[serde(default, deserialize_with = "present")]
+    #[validate(length(min = 1, max = 10))]
+    locale: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    #[validate(length(min = 1, max = 64), custom = "check_timezone")]
+    timezone: Option<Option<String>>,
+}
+// tells a field sent as null (Some(None)) apart from one left out (None)
+fn present<'de, T: Deserialize<'de>, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<Option<T>>, D::Error> {
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+// an iana name like Europe/Berlin, not looked up as there is no tz database here
+fn check_timezone(tz: &str) -> Result<(), validator::ValidationError> {
+    if tz.chars().all(|c| c.is_ascii_alphanumeric() || "/_+-".contains(c)) {
+        Ok(())
+    } else {
+        Err(validator::ValidationError::new("timezone"))
+    }
+}
+#[web::post("/me")]
+async fn update_me(req: web::HttpRequest, req_data: web::types::Json<UpdateMeReq>) -> AppResult<impl Responder> {
+    req_data.validate()?;
+    let req_data = req_data.into_inner();
+    for link in req_data.links.iter().flatten() {
+        link.validate()?;
+    }
+    if let Some(Some(locale)) = &req_data.locale {
+        let li: LanguageIdentifier = locale.parse().map_err(|_| UnknownLang)?;
+        if !LOCALES.locales().any(|l| *l == li) {
+            return Err(UnknownLang.into());
+        }
+    }
+    profileDao::update(get_db_pool(), get_user_id(&req), |profile| {
+        if let Some(display_name) = req_data.display_name {
+            profile.display_name = display_name;
+        }
+        if let Some(bio) = req_data.bio {
+            profile.bio = bio;
+        }
+        if let Some(website) = req_data.website {
+            profile.website = website;
+        }
+        if let Some(links) = req_data.links {
+            profile.links = Json(links);
+        }
+        if let Some(locale) = req_data.locale {
+            profile.locale = locale;
+        }
+        if let Some(timezone) = req_data.timezone {
+            profile.timezone = timezone;
+        }
+    }).await?;
+    Ok(web::HttpResponse::Ok().finish())
+}
+
+#[derive(Serialize)]
+struct PublicProfileRes {
+    id: i32,
+    name: String,
+    display_name: Option<String>,
+    bio: Option<String>,
+    website: Option<String>,
+    links: Vec<ProfileLink>,
+    avatar_time: NaiveDateTime,
+}
+// anyone may read it, so nothing private like the email, locale or timezone
+#[web::get("/{user_id}/profile")]
+async fn public_profile(path: web::types::Path<i32>) -> AppResult<impl Responder> {
+    let user_id = path.into_inner();
+    let user = userDao::select_by_id(get_db_pool(), user_id)
+        .await?
+        .ok_or(NotFound)?;
+    let profile = profileDao::select(get_db_pool(), user_id).await?;
+    Ok(web::HttpResponse::Ok().json(&PublicProfileRes{
+        id: user.id,
+        name: user.name,
+        display_name: profile.display_name,
+        bio: profile.bio,
+        website: profile.website,
+        links: profile.links.0,
+        avatar_time: user.avatar_time,
+    }))
+}
+
+#[derive(Debug, Validate, Deserialize)]
+struct SetQuotaReq {
+    user: i32,
+    #[validate(range(min = 0))]
+    quota_bytes: Option<i64>,
+    #[validate(range(min = 0))]
+    quota_files: Option<i32>,
+}
+#[web::post("/set_quota")]
+async fn set_quota(req: web::HttpRequest, req_data: web::types::Json<SetQuotaReq>) -> AppResult<impl Responder> {
+    req_data.validate()?;
+    check_permission_api(Some(get_user_id(&req)), MANAGE_USER).await?;
+    let old = quotaDao::select_user_quota(get_db_pool(), req_data.user).await?;
+    quotaDao::update_user_quota(get_db_pool(), req_data.user, &Quota{
+        quota_bytes: req_data.quota_bytes,
+        quota_files: req_data.quota_files
+    }).await?;
+    audit::record(&req, "user.set_quota", Some(Target::User(req_data.user)), json!({
+        "quota_bytes": { "old": old.quota_bytes, "new": req_data.quota_bytes },
+        "quota_files": { "old": old.quota_files, "new": req_data.quota_files }
+    })).await;
+    Ok(web::HttpResponse::Ok().finish())
+}
+
+async fn check_name_and_email(name: &str, email: &str, except: Option<i32>) -> AppResult<()> {
+    match userDao::select_taken(get_db_pool(), name, email, except).await? {
+        (true, _) => Err(UserUserError::NameTaken.into()),
+        (_, true) => Err(UserUserError::EmailTaken.into()),
+        _ => Ok(())
+    }
+}
+
+#[derive(Debug, Validate, Deserialize)]
+struct CreateUserReq {
+    #[validate(length(min = 1, max = 50))]
+    name: String,
+    #[validate(email, length(max = 100))]
+    email: String,
+    #[validate(length(min = 1, max = 100))]
+    password: String,
+    // the default roles if left out
+    roles: Option<Vec<i32>>,
+}
+#[web::post("/create_user")]
+async fn create_user(req: web::HttpRequest, req_data: web::types::Json<CreateUserReq>) -> AppResult<impl Responder> {
+    req_data.validate()?;
+    check_permission_api(Some(get_user_id(&req)), MANAGE_USER).await?;
+    let mut req_data = req_data.into_inner();
+    check_name_and_email(&req_data.name, &req_data.email, None).await?;
+    if let Some(roles) = &mut req_data.roles {
+        roles.sort_unstable();
+        roles.dedup();
+        if rbacDao::count_existing_roles(get_db_pool(), roles).await? != roles.len() as i32 {
+            return Err(NotFound.into());
+        }
+    }
+    let hashed_password = password_salt::generate_password(&req_data.password, &get_config!(security).password_salt)?;
+    let id = register_user(&req_data.name, &req_data.email, &hashed_password).await?;
+    if let Some(roles) = &req_data.roles {
+        rbacDao::update_user_roles(get_db_pool(), id, roles).await?;
+        cacheService::publish(CacheChange::UserRoles(Some(id))).await?;
+    }
+    audit::record(&req, "user.create", Some(Target::User(id)), json!({
+        "name": req_data.name,
+        "email": req_data.email,
+        "roles": req_data.roles
+    })).await;
+    Ok(web::HttpResponse::Ok().json(&json!({ "id": id })))
+}
+
+#[derive(Debug, Validate, Deserialize)]
+struct UpdateUserReq {
+    user: i32,
+    #[validate(length(min = 1, max = 50))]
+    name: Option<String>,
+    // set without a confirmation mail, the admin vouches for it
+    #[validate(email, length(max = 100))]
+    email: Option<String>,
+}
+#[web::post("/update_user")]
+async fn update_user(req: web::HttpRequest, req_data: web::types::Json<UpdateUserReq>) -> AppResult<impl Responder> {
+    req_data.validate()?;
+    check_permission_api(Some(get_user_id(&req)), MANAGE_USER).await?;
+    let user = userDao::select_by_id(get_db_pool(), req_data.user)
+        .await?
+        .ok_or(NotFound)?;
+    let name = req_data.name.as_deref().unwrap_or(&user.name);
+    let email = req_data.email.as_deref().unwrap_or(&user.email);
+    check_name_and_email(name, email, Some(user.id)).await?;
+    userDao::update_identity(get_db_pool(), user.id, name, email).await?;
+    audit::record(&req, "user.update", Some(Target::User(user.id)), json!({
+        "name": { "old": &user.name, "new": name },
+        "email": { "old": &user.email, "new": email }
+    })).await;
+    Ok(web::HttpResponse::Ok().finish())
+}
+
+#[derive(Debug, Deserialize)]
+struct ForcePasswordResetReq {
+    user: i32,
+}
+// until the user picks a new password every other request is refused
+#[web::post("/force_password_reset")]
+async fn force_password_reset(req: web::HttpRequest, req_data: web::types::Json<ForcePasswordResetReq>) -> AppResult<impl Responder> {
+    check_permission_api(Some(get_user_id(&req)), MANAGE_USER).await?;
+    if !userDao::require_password_reset(get_db_pool(), req_data.user).await? {
+        return Err(NotFound.into());
+    }
+    cacheService::publish(CacheChange::UserStatus(Some(req_data.user))).await?;
+    audit::record(&req, "user.force_password_reset", Some(Target::User(req_data.user)), json!({})).await;
+    Ok(web::HttpResponse::Ok().finish())
+}
+
+#[derive(Debug, Validate, Deserialize)]
+struct SetStatusReq {
+    user: i32,
+    // 0 active, 1 disabled, 2 banned
+    #[validate(range(min = 0, max = 2))]
+    status: i8,
+    #[validate(length(min = 1, max = 255))]
+    reason: Option<String>,
+    // when a ban ends, it never does if left out
+    until: Option<DateTime<Utc>>,
+}
+#[web::post("/set_status")]
+async fn set_status(req: web::HttpRequest, req_data: web::types::Json<SetStatusReq>) -> AppResult<impl Responder> {
+    req_data.validate()?;
+    let user_id = get_user_id(&req);
+    check_permission_api(Some(user_id), MANAGE_USER).await?;
+    // nobody locks themselves out
+    if req_data.user == user_id {
+        return Err(PermissionDenied.into());
+    }
+    let old = userDao::select_restriction(get_db_pool(), req_data.user)
+        .await?
+        .ok_or(NotFound)?;
+    let reason = req_data.reason.as_deref().filter(|_| req_data.status != STATUS_ACTIVE);
+    let until = req_data.until.filter(|_| req_data.status == STATUS_BANNED).map(|t| t.naive_utc());
+    userDao::update_status(get_db_pool(), req_data.user, req_data.status, reason, until).await?;
+    cacheService::publish(CacheChange::UserStatus(Some(req_data.user))).await?;
+    audit::record(&req, "user.set_status", Some(Target::User(req_data.user)), json!({
+        "status": { "old": old.status, "new": req_data.status },
+        "reason": { "old": old.status_reason, "new": reason },
+        "until": { "old": old.status_until, "new": until }
+    })).await;
+    Ok(web::HttpResponse::Ok().finish())
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ArticleDisposal {
+    // to another user
+    Reassign(i32),
+    Delete,
+}
+#[derive(Debug, Deserialize)]
+struct DeleteUserReq {
+    user: i32,
+    articles: ArticleDisposal,
+}
+#[web::post("/delete_user")]
+async fn delete_user(req: web::HttpRequest, req_data: web::types::Json<DeleteUserReq>) -> AppResult<impl Responder> {
+    let user_id = get_user_id(&req);
+    check_permission_api(Some(user_id), MANAGE_USER).await?;
+    if req_data.user == user_id {
+        return Err(PermissionDenied.into());
+    }
+    let user = userDao::select_by_id(get_db_pool(), req_data.user)
+        .await?
+        .ok_or(NotFound)?;
+    let reassign_to = match req_data.articles {
+        ArticleDisposal::Reassign(to) => {
+            if to == user.id || userDao::select_by_id(get_db_pool(), to).await?.is_none() {
+                return Err(NotFound.into());
+            }
+            Some(to)
+        },
+        ArticleDisposal::Delete => None
+    };
+    userService::delete_user(user.id, reassign_to).await?;
+    audit::record(&req, "user.delete", Some(Target::User(user.id)), json!({
+        "name": user.name,
+        "email": user.email,
+        "articles_reassigned_to": reassign_to
+    })).await;
+    Ok(web::HttpResponse::Ok().finish())
+}
+
+#[derive(Serialize)]
+struct RequestExportRes {
+    id: i32,
+}
+// the archive is built in the background, a notification tells when it is ready
+#[web::post("/me/export")]
+async fn request_export(req: web::HttpRequest) -> AppResult<impl Responder> {
+    let user_id = get_user_id(&req);
+    let id = exportService::request_export(user_id).await?;
+    audit::record(&req, "user.request_export", Some(Target::User(user_id)), json!({
+        "export": id
+    })).await;
+    Ok(web::HttpResponse::Ok().json(&RequestExportRes{ id }))
+}
+
+#[derive(Serialize)]
+struct ExportWithLink {
+    #[serde(flatten)]
+    export: DataExport,
+    link: Option<String>,
+}
+#[derive(Serialize)]
+struct ListExportsRes {
+    total: usize,
+    exports: Vec<ExportWithLink>,
+}
+#[web::get("/me/exports")]
+async fn list_exports(req: web::HttpRequest) -> AppResult<impl Responder> {
+    let exports: Vec<_> = exportDao::list_by_user(get_db_pool(), get_user_id(&req))
+        .await?
+        .into_iter()
+        .map(|export| ExportWithLink{
+            link: (export.state == EXPORT_READY).then(|| exportService::download_link(export.id)),
+            export
+        })
+        .collect();
+    Ok(web::HttpResponse::Ok().json(&ListExportsRes{
+        total: exports.len(),
+        exports
+    }))
+}
+
+// the signed link from the notification is all it takes, so it also works from a mail client
+#[web::get("/export/{code}")]
+async fn download_export(path: web::types::Path<String>, req: web::HttpRequest) -> AppResult<impl Responder> {
+    let id = check_download_code(&path).ok_or(CredentialUnauthorized)?;
+    let (user_id, export_path) = exportService::select_downloadable(id).await?;
+    let mut res = ServedFile{
+        policy_id: DEFAULT_POLICY_ID,
+        user_id,
+        path: export_path,
+        content_type: "application/zip".to_string(),
+        etag: None,
+        cache_control: "private, no-store".to_string(),
+    }.respond(&req).await?;
+    res.headers_mut().insert(ntex::http::header::CONTENT_DISPOSITION,
+        ntex::http::header::HeaderValue::from_static("attachment; filename=\"export.zip\""));
+    Ok(res)
+}
+
+#[derive(Debug, Validate, Deserialize)]
+struct RequestDeletionReq<'a> {
+    #[validate(length(min = 1, max = 50))]
+    pub password: Cow<'a, str>,
+    #[validate(length(min = 1, max = 10))]
+    pub lang: &'a str,
+}
+// nothing happens until the link in the mail is followed, and then only after the grace period
+#[web::post("/me/delete")]
+async fn request_deletion(mut payload: web::types::Payload, req: web::HttpRequest) -> AppResult<impl Responder> {
+    let mut payload = RequestPayload::new(&mut payload);
+    let req_data = payload.parse::<RequestDeletionReq>().await?;
+    let li: LanguageIdentifier = req_data.lang.parse().map_err(|_| UnknownLang)?;
+    let user_id = get_user_id(&req);
+    let user = userDao::select_by_id_with_password(get_db_pool(), user_id)
+        .await?
+        .ok_or(NotFound)?;
+    if !password_salt::compare_password(user.password.as_ref().unwrap(), &req_data.password) {
+        return Err(CredentialUnauthorized.into());
+    }
+    userService::send_deletion_email(&user, &li).await?;
+    audit::record(&req, "user.request_deletion", Some(Target::User(user_id)), json!({})).await;
+    Ok(web::HttpResponse::Ok().finish())
+}
+
+#[web::post("/me/cancel_deletion")]
+async fn cancel_deletion(req: web::HttpRequest) -> AppResult<impl Responder> {
+    let user_id = get_user_id(&req);
+    let Some(delete_after) = userDao::select_delete_after(get_db_pool(), user_id).await? else {
+        return Err(NotFound.into());
+    };
+    userDao::schedule_deletion(get_db_pool(), user_id, None).await?;
+    audit::record(&req, "user.cancel_deletion", Some(Target::User(user_id)), json!({
+        "delete_after": delete_after
+    })).await;
+    Ok(web::HttpResponse::Ok().finish())
 }
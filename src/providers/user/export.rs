@@ -0,0 +1,248 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use chrono::{Duration, Utc};
+use rustle_derive::ErrorHelper;
+use serde::Serialize;
+use serde_json::json;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tracing::{error, warn};
+use uuid::Uuid;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::db::{article as articleDao, audit as auditDao, comment as commentDao, export as exportDao, file as fileDao, get_db_pool,
+    profile as profileDao, rbac as rbacDao, user as userDao};
+use crate::db::export::EXPORT_READY;
+use crate::db::notification::NotificationKind;
+use crate::external::fs::interface::FsProvider;
+use crate::external::fs::DEFAULT_POLICY_ID;
+use crate::get_config;
+use crate::providers::notification;
+use crate::types::err::{AppResult, GlobalInternalError};
+use crate::types::err::GlobalUserError::NotFound;
+use crate::utils::hmac::{hmac_signature_url_safe, hmac_verify_url_safe};
+
+// read from the fs provider and handed to the archive in pieces of this size
+const CHUNK_SIZE: usize = 1024 * 1024;
+const AUDIT_BATCH: i32 = 1000;
+
+#[derive(ErrorHelper)]
+#[err(user, default_msg)]
+pub enum ExportUserError{
+    #[err(code = 429)]
+    ExportInProgress,
+}
+#[derive(ErrorHelper)]
+#[err(internal)]
+pub enum ExportInternalError{
+    #[err(msg = "error.export.archive")]
+    Archive
+}
+
+// a zip in a temporary file, removed when dropped; zip only writes synchronously,
+// so every write goes to the blocking pool
+struct Archive{
+    zip: Option<ZipWriter<std::fs::File>>,
+    temp: PathBuf,
+}
+impl Archive{
+    fn create() -> AppResult<Self>{
+        let temp = std::env::temp_dir().join(format!("rustle-export-{}.zip", Uuid::new_v4()));
+        let file = std::fs::File::create(&temp).map_err(|e| {
+            error!("cannot create {}: {:?}", temp.to_string_lossy(), e);
+            GlobalInternalError::IO
+        })?;
+        Ok(Self{ zip: Some(ZipWriter::new(file)), temp })
+    }
+    async fn write<F>(&mut self, f: F) -> AppResult<()>
+    where F: FnOnce(&mut ZipWriter<std::fs::File>) -> zip::result::ZipResult<()> + Send + 'static {
+        let mut zip = self.zip.take().ok_or(ExportInternalError::Archive)?;
+        let (zip, res) = tokio::task::spawn_blocking(move || {
+            let res = f(&mut zip);
+            (zip, res)
+        }).await.map_err(|e| {
+            error!("export archive task failed: {:?}", e);
+            ExportInternalError::Archive
+        })?;
+        self.zip = Some(zip);
+        res.map_err(|e| {
+            error!("cannot write export archive: {:?}", e);
+            ExportInternalError::Archive.into()
+        })
+    }
+    async fn add_json<T: Serialize>(&mut self, name: String, value: &T) -> AppResult<()>{
+        let data = serde_json::to_vec_pretty(value).map_err(|e| {
+            error!("cannot serialize {}: {:?}", name, e);
+            ExportInternalError::Archive
+        })?;
+        self.add(name, data).await
+    }
+    async fn add(&mut self, name: String, data: Vec<u8>) -> AppResult<()>{
+        self.write(move |zip| {
+            zip.start_file(name, FileOptions::default().compression_method(CompressionMethod::Deflated))?;
+            zip.write_all(&data)?;
+            Ok(())
+        }).await
+    }
+    // uploads are mostly compressed already, so they are stored as they are
+    async fn add_reader<R: AsyncRead + Unpin>(&mut self, name: String, size: i64, mut reader: R) -> AppResult<()>{
+        self.write(move |zip| zip.start_file(name, FileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .large_file(size > u32::MAX as i64))).await?;
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        loop {
+            let n = reader.read(&mut buf).await.map_err(|e| {
+                error!("cannot read file for export: {:?}", e);
+                GlobalInternalError::IO
+            })?;
+            if n == 0 {
+                return Ok(());
+            }
+            let chunk = buf[..n].to_vec();
+            self.write(move |zip| Ok(zip.write_all(&chunk)?)).await?;
+        }
+    }
+    async fn finish(&mut self) -> AppResult<()>{
+        self.write(|zip| zip.finish().map(|_| ())).await
+    }
+}
+impl Drop for Archive{
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.temp);
+    }
+}
+
+// one export of a user at a time, it is built in the background
+pub async fn request_export(user: i32) -> AppResult<i32>{
+    let id = exportDao::create_unless_building(get_db_pool(), user).await?
+        .ok_or(ExportUserError::ExportInProgress)?;
+    ntex::rt::spawn(async move {
+        if let Err(e) = build(id, user).await {
+            error!("data export {} of user {} failed: {}", id, user, e);
+            if let Err(e) = exportDao::fail(get_db_pool(), id).await {
+                error!("cannot mark data export {} as failed: {}", id, e);
+            }
+        }
+    });
+    Ok(id)
+}
+
+async fn build(id: i32, user: i32) -> AppResult<()>{
+    let mut archive = Archive::create()?;
+    let account = userDao::select_by_id(get_db_pool(), user).await?.ok_or(NotFound)?;
+    archive.add_json("profile.json".to_string(), &json!({
+        "user": account,
+        "roles": rbacDao::select_user_roles(get_db_pool(), user).await?,
+        "profile": profileDao::select(get_db_pool(), user).await?,
+    })).await?;
+    archive.add_json("comments.json".to_string(), &commentDao::select_by_author(get_db_pool(), user).await?).await?;
+    for article in articleDao::select_by_author(get_db_pool(), user).await? {
+        let dir = format!("articles/{}-{}", article.id, archive_name(&article.alias));
+        // the draft is the markdown the author wrote, the content what was published from it
+        if let Some(draft) = articleDao::select_content(get_db_pool(), article.draft_content_id).await? {
+            archive.add(format!("{dir}/article.md"), draft.into_bytes()).await?;
+        }
+        if let Some(content) = articleDao::select_content(get_db_pool(), article.content_id).await? {
+            archive.add(format!("{dir}/article.html"), content.into_bytes()).await?;
+        }
+        archive.add_json(format!("{dir}/article.json"), &article).await?;
+    }
+    for file in fileDao::select_by_owner(get_db_pool(), user).await? {
+        match FsProvider::get_file(file.policy, file.owner, Path::new(&file.path)).await {
+            Ok(reader) => archive.add_reader(format!("files/{}-{}", file.id, archive_name(&file.name)), file.size, reader).await?,
+            // a missing file should not cost the user the rest of their data
+            Err(e) => warn!("data export {}: cannot read file {}: {}", id, file.id, e)
+        }
+    }
+    let mut audit = Vec::new();
+    let mut after = 0;
+    loop {
+        let entries = auditDao::select_concerning(get_db_pool(), user, after, AUDIT_BATCH).await?;
+        let Some(last) = entries.last() else {
+            break;
+        };
+        after = last.id;
+        audit.extend(entries);
+    }
+    archive.add_json("audit.json".to_string(), &audit).await?;
+    archive.finish().await?;
+
+    let path = format!("__export/{}.zip", Uuid::new_v4());
+    let mut temp = tokio::fs::File::open(&archive.temp).await.map_err(|e| {
+        error!("cannot open export archive: {:?}", e);
+        GlobalInternalError::IO
+    })?;
+    // derived from what the user already stores, so it is not held against their quota
+    let size = FsProvider::put_file(&mut temp, DEFAULT_POLICY_ID, user, Path::new(&path)).await?;
+    let expires_at = Utc::now() + Duration::seconds(get_config!(account).export_ttl_secs);
+    exportDao::finish(get_db_pool(), id, &path, size as i64, expires_at).await?;
+    if let Err(e) = notification::service::notify(user, NotificationKind::DataExportReady, json!({
+        "export": id,
+        "link": download_link(id),
+        "expires_at": expires_at
+    })).await {
+        error!("cannot notify user {} about data export {}: {}", user, id, e);
+    }
+    Ok(())
+}
+
+// names inside the archive, without anything that could be taken for a directory
+fn archive_name(name: &str) -> String{
+    name.chars()
+        .map(|c| if c.is_alphanumeric() || "._- ".contains(c) { c } else { '_' })
+        .take(100)
+        .collect()
+}
+
+// served by the api itself, the expiry is checked against the export
+pub fn download_link(id: i32) -> String{
+    format!("{}/v1/user/export/{}.{}",
+        get_config!(info).link,
+        hmac_signature_url_safe(&get_config!(security).credential_secret, &format!("export.{id}")),
+        id)
+}
+pub fn check_download_code(code: &str) -> Option<i32>{
+    let (sig, id) = code.split_once('.')?;
+    if !hmac_verify_url_safe(&get_config!(security).credential_secret, &format!("export.{id}"), sig) {
+        return None;
+    }
+    id.parse().ok()
+}
+
+pub async fn select_downloadable(id: i32) -> AppResult<(i32, PathBuf)>{
+    let export = exportDao::select(get_db_pool(), id).await?.ok_or(NotFound)?;
+    match (export.state, export.path, export.expires_at) {
+        (EXPORT_READY, Some(path), Some(expires_at)) if expires_at > Utc::now() => Ok((export.user, PathBuf::from(path))),
+        _ => Err(NotFound.into())
+    }
+}
+
+async fn remove(export: &exportDao::DataExport) -> AppResult<()>{
+    if let Some(path) = &export.path {
+        FsProvider::delete_file(DEFAULT_POLICY_ID, export.user, Path::new(path)).await?;
+    }
+    exportDao::delete(get_db_pool(), export.id).await?;
+    Ok(())
+}
+pub async fn remove_exports(user: i32) -> AppResult<()>{
+    for export in exportDao::list_by_user(get_db_pool(), user).await? {
+        remove(&export).await?;
+    }
+    Ok(())
+}
+// failed ones are kept as long as a ready one would be, so the user gets to see them
+pub async fn remove_expired(){
+    let before = Utc::now() - Duration::seconds(get_config!(account).export_ttl_secs);
+    let expired = match exportDao::select_expired(get_db_pool(), before).await {
+        Ok(t) => t,
+        Err(e) => {
+            error!("cannot look for expired data exports: {}", e);
+            return;
+        }
+    };
+    for export in expired {
+        if let Err(e) = remove(&export).await {
+            error!("cannot remove expired data export {}: {}", export.id, e);
+        }
+    }
+}
@@ -1,39 +1,198 @@
+use std::collections::HashMap;
+use std::path::Path;
+use chrono::{Duration, Utc};
+use dashmap::DashMap;
 use fluent_templates::{Loader, LanguageIdentifier};
+use once_cell::sync::Lazy;
 use rand::Rng;
-use tracing::error;
+use tracing::{error, info, warn};
 
-use crate::external::mail::{self, MailToLinkTemplate, MailVerifyTemplate, MAILER_ENABLED, MailQueueError};
-use crate::db::user::User;
-use crate::db::{verification as verificationDao, get_db_pool};
+use crate::external::fs::interface::FsProvider;
+use crate::external::fs::DEFAULT_POLICY_ID;
+use crate::external::mail::{self, MailEmailChangeTemplate, MailToLinkTemplate, MailVerifyTemplate, MAILER_ENABLED};
+use crate::db::user::{User, UserRestriction, STATUS_BANNED, STATUS_DISABLED};
+use crate::db::{export as exportDao, file as fileDao, user as userDao, verification as verificationDao, get_db_pool};
+use crate::db::rbac::DEFAULT_ROLES;
+use crate::providers::cache::service::{self as cacheService, CacheChange};
+use crate::providers::file::service as fileService;
+use crate::internal::event::{self, Event, UserRegistered};
 use crate::external::fs::embed::LOCALES;
-use crate::types::err::AppResult;
-use crate::utils::hmac::hmac_signature;
+use crate::types::err::{AppResult, EmptyErrResult};
+use crate::types::service::AppService;
+use crate::db::verification::{Verification, ACTION_CANCEL_EMAIL_CHANGE, ACTION_CHANGE_EMAIL, ACTION_DELETE_ACCOUNT, ACTION_RESET_PASSWORD, ACTION_REVERT_EMAIL_CHANGE};
+use super::export;
+use crate::utils::hmac::{hmac_signature, hmac_verify};
 use std::sync::atomic;
 use rustle_derive::ErrorHelper;
 use crate::get_config;
-use crate::types::err::GlobalUserError::FeatureNotEnabled;
+use crate::types::err::GlobalUserError::{FeatureNotEnabled, NotFound};
 
 #[derive(ErrorHelper)]
 #[err(internal)]
 pub enum MailInternalError{
     #[err(msg = "error.mail.render")]
-    Render,
-    #[err(msg = "error.mail.busy")]
-    Busy,
-    #[err(msg = "error.mail.parse")]
-    Parse
+    Render
 }
-pub async fn send_tolink_email(email: &str, user: &User, action: &str, lang: &LanguageIdentifier) -> AppResult<()>{
-    if !MAILER_ENABLED.load(atomic::Ordering::Relaxed) {
-        return Err(FeatureNotEnabled.into());
+
+#[derive(ErrorHelper)]
+#[err(user, default_msg)]
+pub enum UserUserError{
+    #[err(code = 409)]
+    NameTaken,
+    #[err(code = 409)]
+    EmailTaken,
+    #[err(code = 403)]
+    AccountDisabled,
+    #[err(code = 403)]
+    AccountBanned,
+    #[err(code = 403)]
+    PasswordResetRequired,
+}
+
+// users that are disabled, banned or have to pick a new password, everybody else is let through
+static RESTRICTIONS: Lazy<DashMap<i32, UserRestriction>> = Lazy::new(DashMap::new);
+
+fn is_restricted(r: &UserRestriction) -> bool{
+    r.status == STATUS_DISABLED || r.status == STATUS_BANNED || r.password_reset_required
+}
+// fails for a disabled or banned user, otherwise tells whether they still have to change their password
+pub fn check_restriction(user: i32) -> AppResult<bool>{
+    let Some(r) = RESTRICTIONS.get(&user) else {
+        return Ok(false);
+    };
+    match r.status {
+        STATUS_DISABLED => Err(UserUserError::AccountDisabled.into()),
+        STATUS_BANNED if r.status_until.map_or(true, |t| t > Utc::now().naive_utc()) => Err(UserUserError::AccountBanned.into()),
+        _ => Ok(r.password_reset_required)
     }
-    let verification_res = verificationDao::create(get_db_pool(), user.id, &user.email, "", 0).await?;
+}
+// reloads one user, or everybody when user is None
+pub async fn reload_restrictions(user: Option<i32>) -> AppResult<()>{
+    match user {
+        Some(user) => match userDao::select_restriction(get_db_pool(), user).await? {
+            Some(r) if is_restricted(&r) => {
+                RESTRICTIONS.insert(user, r);
+            },
+            _ => {
+                RESTRICTIONS.remove(&user);
+            }
+        },
+        None => {
+            let loaded: HashMap<i32, UserRestriction> = userDao::select_restrictions(get_db_pool()).await?
+                .into_iter()
+                .map(|r| (r.id, r))
+                .collect();
+            RESTRICTIONS.retain(|k, _| loaded.contains_key(k));
+            for (k, v) in loaded {
+                RESTRICTIONS.insert(k, v);
+            }
+        }
+    }
+    Ok(())
+}
+
+pub struct UserService;
+impl AppService for UserService{
+    async fn initialize() -> EmptyErrResult<()> {
+        reload_restrictions(None).await.map_err(|e| {
+            error!("failed to load user restrictions: {}", e);
+        })?;
+        match exportDao::fail_building(get_db_pool()).await {
+            Ok(0) => {},
+            Ok(n) => warn!("{} data exports were cut short by a restart", n),
+            Err(e) => error!("cannot mark unfinished data exports: {}", e)
+        }
+        ntex::rt::spawn(async {
+            let sweep_interval = std::time::Duration::from_secs(get_config!(account).sweep_interval_secs);
+            loop {
+                tokio::time::sleep(sweep_interval).await;
+                sweep().await;
+            }
+        });
+        Ok(())
+    }
+    fn name() -> &'static str {
+        "UserService"
+    }
+}
+// links carry "<signature of the id>.<id>"
+pub fn sign_verification(id: i32) -> String{
     let mut sig = hmac_signature(
         &get_config!(security).credential_secret,
-        &verification_res.to_string(),
+        &id.to_string(),
     );
     sig.push('.');
-    sig.push_str(&verification_res.to_string());
+    sig.push_str(&id.to_string());
+    sig
+}
+pub fn check_verification_code(code: &str) -> Option<i32>{
+    let (sig, id) = code.split_once('.')?;
+    if !hmac_verify(&get_config!(security).credential_secret, id, sig) {
+        return None;
+    }
+    id.parse().ok()
+}
+// the new address gets the link that applies the change, the old one a notice with a link
+// that cancels it; a new request replaces a pending one
+pub async fn request_email_change(user: &User, email: &str, lang: &LanguageIdentifier) -> AppResult<()>{
+    if !MAILER_ENABLED.load(atomic::Ordering::Relaxed) {
+        return Err(FeatureNotEnabled.into());
+    }
+    if userDao::select_by_email(get_db_pool(), email).await?.is_some() {
+        return Err(UserUserError::EmailTaken.into());
+    }
+    cancel_email_change(user.id).await?;
+    let confirm = verificationDao::create(get_db_pool(), user.id, email, "", ACTION_CHANGE_EMAIL).await?;
+    let cancel = verificationDao::create(get_db_pool(), user.id, email, "", ACTION_CANCEL_EMAIL_CHANGE).await?;
+    queue_tolink_email(email, user, confirm, "change_email", lang).await?;
+    let site_info = get_config!(info);
+    let mail_content = MailEmailChangeTemplate {
+        site_name: site_info.name.clone(),
+        site_link: site_info.link.clone(),
+        user: user.name.clone(),
+        email: email.to_string(),
+        link: sign_verification(cancel),
+        lang: lang.to_string(),
+    };
+    let mail_str = mail_content.generate().map_err(|x| {
+        error!("failed to generate mail content, {:?}", x);
+        MailInternalError::Render
+    })?;
+    mail::queue(mail_str,
+        LOCALES.lookup(lang, "email_change_requested").unwrap().as_str(),
+        &user.name,
+        &user.email).await?;
+    Ok(())
+}
+// both links of a pending change go, whichever of them was followed; revert links of a confirmed change stay
+pub async fn cancel_email_change(user: i32) -> AppResult<()>{
+    verificationDao::delete_by_user(get_db_pool(), user, ACTION_CHANGE_EMAIL).await?;
+    verificationDao::delete_by_user(get_db_pool(), user, ACTION_CANCEL_EMAIL_CHANGE).await?;
+    Ok(())
+}
+// the cancel link at the old address keeps working until it expires and then puts the old address back,
+// in case whoever confirmed the change was not the owner; returns the old address
+pub async fn confirm_email_change(ver: &Verification) -> AppResult<String>{
+    let old = userDao::select_by_id(get_db_pool(), ver.user).await?.ok_or(NotFound)?;
+    if !userDao::update_email(get_db_pool(), ver.user, &ver.identity).await? {
+        return Err(UserUserError::EmailTaken.into());
+    }
+    verificationDao::delete_by_user(get_db_pool(), ver.user, ACTION_CHANGE_EMAIL).await?;
+    verificationDao::turn_into_revert(get_db_pool(), ver.user, &old.email).await?;
+    Ok(old.email)
+}
+pub async fn revert_email_change(ver: &Verification) -> AppResult<()>{
+    if !userDao::update_email(get_db_pool(), ver.user, &ver.identity).await? {
+        return Err(UserUserError::EmailTaken.into());
+    }
+    // a change requested from the address being reverted must not go through afterwards
+    cancel_email_change(ver.user).await
+}
+pub fn is_email_change_expired(ver: &Verification) -> bool{
+    ver.created_at < (Utc::now() - Duration::seconds(get_config!(account).email_change_ttl_secs)).naive_utc()
+}
+async fn queue_tolink_email(email: &str, user: &User, verification: i32, action: &str, lang: &LanguageIdentifier) -> AppResult<()>{
+    let sig = sign_verification(verification);
     let site_info = get_config!(info);
     let mail_content = MailToLinkTemplate {
         site_name: site_info.name.clone(),
@@ -47,18 +206,10 @@ pub async fn send_tolink_email(email: &str, user: &User, action: &str, lang: &La
         error!("failed to generate mail content, {:?}", x);
         MailInternalError::Render
     })?;
-     match mail::try_send(mail_str, 
+    mail::queue(mail_str,
         LOCALES.lookup(lang, "email_check").unwrap().as_str(), 
         &user.name,
-        &email){
-        Err(MailQueueError::MailBusy) => return Err(
-            MailInternalError::Busy.into()
-        ),
-        Err(MailQueueError::ParseError) => return Err(
-            MailInternalError::Parse.into()
-        ),
-        Ok(_) => {}
-    };
+        &email).await?;
     Ok(())
 }
 
@@ -71,7 +222,7 @@ pub async fn send_verify_email(user: &User, action: &str, lang: &LanguageIdentif
     .map(|_| rand::thread_rng().gen_range('0'..'9'))
     .collect();
     let code: String = code_chars.into_iter().collect();
-    _ = verificationDao::create(get_db_pool(), user.id, &user.email, &code, 0).await?;
+    _ = verificationDao::create(get_db_pool(), user.id, &user.email, &code, ACTION_RESET_PASSWORD).await?;
     let site_info = get_config!(info);
     let mail_content = MailVerifyTemplate {
         site_name: site_info.name.clone(),
@@ -85,17 +236,102 @@ pub async fn send_verify_email(user: &User, action: &str, lang: &LanguageIdentif
         error!("failed to generate mail content, {:?}", x);
         MailInternalError::Render
     })?;
-     match mail::try_send(mail_str, 
+    mail::queue(mail_str,
         LOCALES.lookup(lang, "identity_check").unwrap().as_str(), 
         &user.name,
-        &user.email){
-            Err(MailQueueError::MailBusy) => return Err(
-                MailInternalError::Busy.into()
-            ),
-            Err(MailQueueError::ParseError) => return Err(
-                MailInternalError::Parse.into()
-            ),
-        Ok(_) => {}
+        &user.email).await?;
+    Ok(())
+}
+
+// the password is expected to be hashed already, filters may rewrite the name or veto
+pub async fn register_user(name: &str, email: &str, password: &str) -> AppResult<i32>{
+    let Event::UserRegistered(mut registered) = event::filter(Event::UserRegistered(UserRegistered{
+        id: None,
+        name: name.to_string(),
+        email: email.to_string()
+    })).await? else {
+        unreachable!("filters keep the kind of an event")
     };
+    let id = userDao::create(get_db_pool(), &registered.name, &registered.email, password, DEFAULT_ROLES).await?;
+    registered.id = Some(id);
+    event::dispatch(Event::UserRegistered(registered));
+    Ok(id)
+}
+
+// with reassign_to the articles of the user go to that user, otherwise they are deleted
+pub async fn delete_user(user: i32, reassign_to: Option<i32>) -> AppResult<()>{
+    export::remove_exports(user).await?;
+    if !userDao::delete(get_db_pool(), user, reassign_to).await? {
+        return Err(NotFound.into());
+    }
+    remove_personal_files(user).await;
+    cacheService::publish(CacheChange::UserRoles(Some(user))).await?;
+    cacheService::publish(CacheChange::UserStatus(Some(user))).await?;
     Ok(())
-}
\ No newline at end of file
+}
+
+// what a confirmed self-deletion comes to once the grace period is over, the articles stay
+// under a name that no longer tells who wrote them
+pub async fn anonymize_user(user: i32) -> AppResult<()>{
+    export::remove_exports(user).await?;
+    if !userDao::anonymize(get_db_pool(), user).await? {
+        return Err(NotFound.into());
+    }
+    remove_personal_files(user).await;
+    cacheService::publish(CacheChange::UserRoles(Some(user))).await?;
+    cacheService::publish(CacheChange::UserStatus(Some(user))).await?;
+    Ok(())
+}
+
+// the account is gone at this point, files that cannot be removed are only left behind
+async fn remove_personal_files(user: i32){
+    match fileDao::select_unreferenced_by_owner(get_db_pool(), user).await {
+        Ok(files) => for file in files {
+            if let Err(e) = fileService::delete(&file).await {
+                warn!("cannot delete file {} of deleted user {}: {}", file.id, user, e);
+            }
+        },
+        Err(e) => warn!("cannot list the files of deleted user {}: {}", user, e)
+    }
+    let sizes = get_config!(image).avatar_sizes.clone();
+    let avatars = std::iter::once("__user/avatar".to_string())
+        .chain(sizes.iter().map(|size| format!("__user/avatar_{size}")));
+    for avatar in avatars {
+        if let Err(e) = FsProvider::delete_file(DEFAULT_POLICY_ID, user, Path::new(&avatar)).await {
+            warn!("cannot delete avatar {} of deleted user {}: {}", avatar, user, e);
+        }
+    }
+}
+
+// the address is not changing, so the link goes to the one on record
+pub async fn send_deletion_email(user: &User, lang: &LanguageIdentifier) -> AppResult<()>{
+    if !MAILER_ENABLED.load(atomic::Ordering::Relaxed) {
+        return Err(FeatureNotEnabled.into());
+    }
+    let verification_res = verificationDao::create(get_db_pool(), user.id, &user.email, "", ACTION_DELETE_ACCOUNT).await?;
+    queue_tolink_email(&user.email, user, verification_res, "delete_account", lang).await
+}
+
+async fn sweep(){
+    export::remove_expired().await;
+    let before = (Utc::now() - Duration::seconds(get_config!(account).email_change_ttl_secs)).naive_utc();
+    // password codes live no longer than the links of an email change
+    for action in [ACTION_CHANGE_EMAIL, ACTION_CANCEL_EMAIL_CHANGE, ACTION_REVERT_EMAIL_CHANGE, ACTION_RESET_PASSWORD] {
+        if let Err(e) = verificationDao::delete_created_before(get_db_pool(), action, before).await {
+            error!("cannot remove expired verifications: {}", e);
+        }
+    }
+    let due = match userDao::select_due_deletions(get_db_pool(), Utc::now()).await {
+        Ok(t) => t,
+        Err(e) => {
+            error!("cannot look for due account deletions: {}", e);
+            return;
+        }
+    };
+    for user in due {
+        match anonymize_user(user).await {
+            Ok(_) => info!("account of user {} deleted as requested", user),
+            Err(e) => error!("cannot delete the account of user {}: {}", user, e)
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use ntex::web::{self, Responder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use validator::Validate;
+use super::service::{self, check_url, events_to_str};
+use crate::db::{get_db_pool, webhook as webhookDao, webhook::{Webhook, WebhookDelivery}};
+use crate::middlewares::Auth;
+use crate::providers::auth::permission::MANAGE_WEBHOOK;
+use crate::providers::auth::service::check_permission_api;
+use crate::types::err::AppResult;
+use crate::types::err::GlobalUserError::{NotFound, TooMaxParameter};
+use crate::utils::request::get_user_id;
+
+pub fn init(cfg: &mut web::ServiceConfig){
+    cfg.service(
+        web::scope("/v1/webhook").wrap(Auth)
+            .service(create)
+            .service(list)
+            .service(update)
+            .service(delete)
+            .service(deliveries)
+            .service(redeliver)
+    );
+}
+
+#[derive(Debug, Validate, Deserialize)]
+struct CreateReq {
+    #[validate(length(min = 1, max = 2048))]
+    url: String,
+    #[validate(length(min = 1, max = 10))]
+    events: Vec<String>,
+}
+#[web::post("/create")]
+async fn create(req: web::HttpRequest, req_data: web::types::Json<CreateReq>) -> AppResult<impl Responder> {
+    req_data.validate()?;
+    let user_id = get_user_id(&req);
+    check_permission_api(Some(user_id), MANAGE_WEBHOOK).await?;
+    let (id, secret) = service::create(user_id, &req_data.url, &req_data.events).await?;
+    Ok(web::HttpResponse::Ok().body(
+        json!({
+            "id": id,
+            "secret": secret
+        })
+    ))
+}
+
+#[derive(Debug, Validate, Deserialize)]
+struct ListReq {
+    #[validate(range(min = 0, max = 100))]
+    limit: i32,
+    #[validate(range(min = 1,))]
+    page: i32
+}
+#[derive(Debug, Serialize)]
+struct ListRes {
+    total: i32,
+    webhooks: Vec<Webhook>
+}
+#[web::post("/list")]
+async fn list(req: web::HttpRequest, req_data: web::types::Json<ListReq>) -> AppResult<impl Responder> {
+    req_data.validate()?;
+    check_permission_api(Some(get_user_id(&req)), MANAGE_WEBHOOK).await?;
+    let db_res = webhookDao::list(get_db_pool(),
+        req_data.limit,
+        (req_data.page-1).checked_mul(req_data.limit).ok_or(TooMaxParameter)?).await?;
+    Ok(web::HttpResponse::Ok().json(
+        &ListRes{
+            total: db_res.0,
+            webhooks: db_res.1
+        }
+    ))
+}
+
+#[derive(Debug, Validate, Deserialize)]
+struct UpdateReq {
+    id: i32,
+    #[validate(length(min = 1, max = 2048))]
+    url: String,
+    #[validate(length(min = 1, max = 10))]
+    events: Vec<String>,
+    enabled: bool,
+}
+#[web::post("/update")]
+async fn update(req: web::HttpRequest, req_data: web::types::Json<UpdateReq>) -> AppResult<impl Responder> {
+    req_data.validate()?;
+    check_permission_api(Some(get_user_id(&req)), MANAGE_WEBHOOK).await?;
+    check_url(&req_data.url).await?;
+    let events = events_to_str(&req_data.events)?;
+    if !webhookDao::update(get_db_pool(), req_data.id, &req_data.url, &events, req_data.enabled).await?{
+        return Err(NotFound.into());
+    }
+    Ok(web::HttpResponse::Ok().finish())
+}
+
+#[web::post("/delete/{webhook_id}")]
+async fn delete(path: web::types::Path<i32>, req: web::HttpRequest) -> AppResult<impl Responder> {
+    check_permission_api(Some(get_user_id(&req)), MANAGE_WEBHOOK).await?;
+    if !webhookDao::delete(get_db_pool(), path.into_inner()).await?{
+        return Err(NotFound.into());
+    }
+    Ok(web::HttpResponse::Ok().finish())
+}
+
+#[derive(Debug, Validate, Deserialize)]
+struct DeliveriesReq {
+    webhook: i32,
+    #[validate(range(min = 0, max = 100))]
+    limit: i32,
+    #[validate(range(min = 1,))]
+    page: i32
+}
+#[derive(Debug, Serialize)]
+struct DeliveriesRes {
+    total: i32,
+    deliveries: Vec<WebhookDelivery>
+}
+#[web::post("/deliveries")]
+async fn deliveries(req: web::HttpRequest, req_data: web::types::Json<DeliveriesReq>) -> AppResult<impl Responder> {
+    req_data.validate()?;
+    check_permission_api(Some(get_user_id(&req)), MANAGE_WEBHOOK).await?;
+    let db_res = webhookDao::list_deliveries(get_db_pool(),
+        req_data.webhook,
+        req_data.limit,
+        (req_data.page-1).checked_mul(req_data.limit).ok_or(TooMaxParameter)?).await?;
+    Ok(web::HttpResponse::Ok().json(
+        &DeliveriesRes{
+            total: db_res.0,
+            deliveries: db_res.1
+        }
+    ))
+}
+
+#[web::post("/redeliver/{delivery_id}")]
+async fn redeliver(path: web::types::Path<i32>, req: web::HttpRequest) -> AppResult<impl Responder> {
+    check_permission_api(Some(get_user_id(&req)), MANAGE_WEBHOOK).await?;
+    let id = service::redeliver(path.into_inner()).await?;
+    Ok(web::HttpResponse::Ok().body(
+        json!({
+            "id": id
+        })
+    ))
+}
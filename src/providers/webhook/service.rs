@@ -0,0 +1,68 @@
+use ntex::http::Uri;
+use rand::distributions::{Alphanumeric, DistString};
+use rustle_derive::ErrorHelper;
+
+use crate::db::{get_db_pool, webhook as webhookDao};
+use crate::external::webhook;
+use crate::get_config;
+use crate::internal::event::EventKind;
+use crate::types::err::AppResult;
+use crate::types::err::GlobalUserError::NotFound;
+
+#[derive(ErrorHelper)]
+#[err(user, default_msg)]
+pub enum WebhookUserError{
+    #[err(code = 400)]
+    InvalidUrl,
+    #[err(code = 400)]
+    UnknownEvent,
+    #[err(code = 403)]
+    PrivateTarget,
+}
+
+pub fn events_to_str(events: &[String]) -> AppResult<String>{
+    let mut kinds: Vec<&'static str> = Vec::with_capacity(events.len());
+    for e in events{
+        let kind = EventKind::parse(e).ok_or(WebhookUserError::UnknownEvent)?.as_str();
+        if !kinds.contains(&kind){
+            kinds.push(kind);
+        }
+    }
+    if kinds.is_empty(){
+        return Err(WebhookUserError::UnknownEvent.into());
+    }
+    Ok(kinds.join(","))
+}
+
+// the address is checked again on every delivery, the name may be pointed elsewhere later
+pub async fn check_url(url: &str) -> AppResult<()>{
+    let uri: Uri = url.parse().map_err(|_| WebhookUserError::InvalidUrl)?;
+    if !matches!(uri.scheme_str(), Some("http") | Some("https")) || uri.host().is_none(){
+        return Err(WebhookUserError::InvalidUrl.into());
+    }
+    let allow_private = get_config!(webhook).allow_private_targets;
+    if !allow_private && webhook::is_private_target(&uri).await.map_err(|_| WebhookUserError::InvalidUrl)?{
+        return Err(WebhookUserError::PrivateTarget.into());
+    }
+    Ok(())
+}
+
+// the secret is only returned here, receivers need it to verify signatures
+pub async fn create(user_id: i32, url: &str, events: &[String]) -> AppResult<(i32, String)>{
+    check_url(url).await?;
+    let events = events_to_str(events)?;
+    let secret = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let id = webhookDao::create(get_db_pool(), url, &secret, &events, user_id).await?;
+    Ok((id, secret))
+}
+
+// a new delivery with the same payload is queued, the history of the old one is kept
+pub async fn redeliver(delivery_id: i32) -> AppResult<i32>{
+    let delivery = webhookDao::select_delivery(get_db_pool(), delivery_id).await?
+        .ok_or(NotFound)?;
+    webhookDao::select_by_id(get_db_pool(), delivery.webhook).await?
+        .ok_or(NotFound)?;
+    let id = webhookDao::create_delivery(get_db_pool(), delivery.webhook, &delivery.event, &delivery.payload).await?;
+    webhook::wake();
+    Ok(id)
+}
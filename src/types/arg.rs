@@ -1,7 +1,14 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 #[derive(Debug, Parser)]
 #[command(name = "rustle backend")]
 pub struct Arg {
     #[arg(long, default_value_t = false)]
-    pub debug: bool
+    pub debug: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>
+}
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Print the DNS TXT record of the configured DKIM key and exit
+    DkimRecord
 }
\ No newline at end of file
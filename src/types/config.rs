@@ -14,17 +14,67 @@ pub struct DatabaseConfig {
     #[serde_inline_default(3306)]
     pub port: u32,
 }
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MailTransportKind {
+    // implicit tls, usually port 465
+    #[default] Smtps,
+    // usually port 587
+    Starttls,
+    // unencrypted, for a local relay on port 25
+    Smtp,
+    Sendmail,
+    // writes every mail as an .eml file into file_path
+    File,
+    Stdout,
+}
 #[serde_inline_default]
 #[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct MailConfig {
+    #[serde(default)]
+    pub transport: MailTransportKind,
     pub from: String,
     pub smtp_user_name: String,
     pub smtp_password: String,
     pub host: String,
     #[serde_inline_default(465)]
-    pub port: u32,
-    #[serde_inline_default(100)]
-    pub max_queue_capacity: u32,
+    pub port: u16,
+    // empty means the sendmail found in PATH
+    #[serde(default)]
+    pub sendmail_command: String,
+    #[serde_inline_default(String::from("./mails"))]
+    pub file_path: String,
+    // templates in here replace the embedded ones with the same file name, empty means none
+    #[serde(default)]
+    pub template_dir: String,
+    // a mail is dead-lettered after this many attempts
+    #[serde_inline_default(8)]
+    pub max_attempts: i32,
+    #[serde_inline_default(60)]
+    pub retry_base_secs: i64,
+    #[serde_inline_default(21600)]
+    pub retry_max_secs: i64,
+    #[serde_inline_default(30)]
+    pub poll_interval_secs: u64,
+    // outgoing mail is left unsigned without it
+    #[serde(default)]
+    pub dkim: Option<DkimConfig>,
+}
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DkimAlgorithm {
+    // the key file is a PKCS#1 PEM
+    #[default] Rsa,
+    // the key file is the base64 encoded 32 byte secret
+    Ed25519,
+}
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct DkimConfig {
+    pub selector: String,
+    pub domain: String,
+    pub private_key_path: String,
+    #[serde(default)]
+    pub algorithm: DkimAlgorithm,
 }
 #[derive(Deserialize, Serialize, Debug, Clone, Default)]
 #[serde(untagged)]
@@ -39,7 +89,14 @@ pub struct HttpConfig {
     pub host: String,
     #[serde_inline_default(5800)]
     pub port: u16,
-    pub max_upload_size: usize
+    pub max_upload_size: usize,
+    #[serde_inline_default(String::from("public, max-age=31536000, immutable"))]
+    pub file_cache_control: String,
+    #[serde_inline_default(String::from("public, no-cache"))]
+    pub avatar_cache_control: String,
+    // reverse proxies whose x-forwarded-for is believed, the header of anyone else is ignored
+    #[serde(default)]
+    pub trusted_proxies: Vec<std::net::IpAddr>,
 }
 #[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct SecurityConfig {
@@ -52,6 +109,135 @@ pub struct InfoConfig {
     pub name: String,
     pub link: String,
 }
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct ImageConfig {
+    pub avatar_sizes: Vec<u32>,
+    pub thumbnail_sizes: Vec<u32>,
+    // bytes of an image read into memory for decoding
+    pub max_input_size: usize,
+    pub max_dimension: u32,
+    pub max_alloc: u64,
+    pub jpeg_quality: u8,
+    pub transcode_webp: bool,
+}
+impl Default for ImageConfig {
+    fn default() -> Self {
+        Self {
+            avatar_sizes: vec![64, 128, 256],
+            thumbnail_sizes: vec![256, 1024],
+            max_input_size: 20 * 1024 * 1024,
+            max_dimension: 8192,
+            max_alloc: 512 * 1024 * 1024,
+            jpeg_quality: 85,
+            transcode_webp: false,
+        }
+    }
+}
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct ExtensionConfig {
+    // extensions run third party code in the server process, so they are opt-in
+    pub enabled: bool,
+    pub path: String,
+    // heap an extension's lua state may hold, exceeding it fails the allocating call
+    pub max_memory: usize,
+    // time a single call into an extension may run before it is aborted, a call stuck
+    // inside a c function is given up on a second later
+    pub max_call_millis: u64,
+}
+impl Default for ExtensionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: String::from("./extensions"),
+            max_memory: 16 * 1024 * 1024,
+            max_call_millis: 200,
+        }
+    }
+}
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct WebhookConfig {
+    // a delivery is marked failed after this many attempts
+    pub max_attempts: i32,
+    // the n-th retry waits base_delay_secs * 2^(n-1), at most max_delay_secs
+    pub base_delay_secs: i64,
+    pub max_delay_secs: i64,
+    pub timeout_millis: u32,
+    pub poll_interval_secs: u64,
+    // loopback and private addresses are refused unless enabled, e.g. for a local receiver
+    pub allow_private_targets: bool,
+}
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 8,
+            base_delay_secs: 30,
+            max_delay_secs: 6 * 60 * 60,
+            timeout_millis: 10_000,
+            poll_interval_secs: 30,
+            allow_private_targets: false,
+        }
+    }
+}
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+// picks the cache::sync implementation
+pub enum CacheSyncBackend {
+    // a single instance, changes only apply to this process
+    #[default]
+    Local,
+    // changes are written to a table that every instance polls
+    Database,
+}
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct CacheConfig {
+    // users whose role ids are kept in memory for permission checks
+    pub max_user_role_entity: usize,
+    pub sync: CacheSyncBackend,
+    pub poll_interval_secs: u64,
+    // how far back each poll reads again, for changes that committed after later ones
+    pub overlap_secs: i64,
+    // how long a change stays in the log for instances that are behind
+    pub retention_secs: i64,
+    pub purge_interval_secs: u64,
+}
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            max_user_role_entity: 50,
+            sync: CacheSyncBackend::Local,
+            poll_interval_secs: 5,
+            overlap_secs: 60,
+            retention_secs: 24 * 60 * 60,
+            purge_interval_secs: 60 * 60,
+        }
+    }
+}
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct AccountConfig {
+    // how long the download link of a data export works, the archive is removed afterwards
+    pub export_ttl_secs: i64,
+    // a confirmed self-deletion can be cancelled for this long
+    pub deletion_grace_secs: i64,
+    // the links of an email change, to the new and the old address, stop working after this
+    pub email_change_ttl_secs: i64,
+    // how often expired exports, links and due deletions are looked for
+    pub sweep_interval_secs: u64,
+}
+impl Default for AccountConfig {
+    fn default() -> Self {
+        Self {
+            export_ttl_secs: 7 * 24 * 60 * 60,
+            deletion_grace_secs: 14 * 24 * 60 * 60,
+            email_change_ttl_secs: 24 * 60 * 60,
+            sweep_interval_secs: 10 * 60,
+        }
+    }
+}
 #[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct BaseConfig {
     pub database: DatabaseConfig,
@@ -60,6 +246,16 @@ pub struct BaseConfig {
     pub security: SecurityConfig,
     pub mail: MailEnum,
     pub info: InfoConfig,
+    #[serde(default)]
+    pub image: ImageConfig,
+    #[serde(default)]
+    pub extension: ExtensionConfig,
+    #[serde(default)]
+    pub webhook: WebhookConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub account: AccountConfig,
 }
 pub trait ConfigInitializer {
     fn initialize(c: &mut BaseConfig) -> Result<bool, ()>;
@@ -0,0 +1,5 @@
+// the n-th retry waits base_secs * 2^(n-1), at most max_secs;
+// the exponent is capped so the shift can not overflow
+pub fn retry_delay(base_secs: i64, max_secs: i64, attempts: i32) -> i64{
+    base_secs.saturating_mul(1i64 << (attempts - 1).clamp(0, 30)).min(max_secs)
+}
@@ -28,4 +28,14 @@ pub fn hmac_signature(key: &str, msg: &str) -> String {
 
 pub fn hmac_verify(key: &str, msg: &str, received: &str) -> bool{
     hmac_signature(key, msg) == received
-}
\ No newline at end of file
+}
+// for signatures put into an url path, where '/' and '+' would break
+pub fn hmac_signature_url_safe(key: &str, msg: &str) -> String {
+    hmac_signature(key, msg)
+        .trim_end_matches('=')
+        .replace('+', "-")
+        .replace('/', "_")
+}
+pub fn hmac_verify_url_safe(key: &str, msg: &str, received: &str) -> bool{
+    hmac_signature_url_safe(key, msg) == received
+}
@@ -0,0 +1,196 @@
+use std::io::Cursor;
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{AnimationDecoder, DynamicImage, Frame, ImageDecoder, ImageError, ImageFormat, ImageReader, Limits};
+use rustle_derive::ErrorHelper;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tracing::error;
+use crate::types::config::ImageConfig;
+use crate::types::err::{AppError, AppResult, GlobalInternalError};
+use crate::utils::stream::LimitedReader;
+
+#[derive(ErrorHelper)]
+#[err(user, default_msg)]
+pub enum ImageUserError{
+    #[err(code = 400)]
+    InvalidImage,
+    #[err(code = 413)]
+    ImageTooLarge,
+}
+#[derive(ErrorHelper)]
+#[err(internal)]
+pub enum ImageInternalError{
+    #[err(msg = "error.image.encode")]
+    Encode,
+    #[err(msg = "error.image.task")]
+    Task,
+}
+
+pub struct EncodedImage{
+    pub data: Vec<u8>,
+    pub format: ImageFormat,
+}
+impl EncodedImage{
+    pub fn mime(&self) -> &'static str{
+        self.format.to_mime_type()
+    }
+}
+
+pub async fn read_image_input<R: AsyncRead + Unpin>(reader: R, config: &ImageConfig) -> AppResult<Vec<u8>>{
+    let mut limited = LimitedReader::new(reader, config.max_input_size as u64);
+    let mut data = Vec::new();
+    if let Err(e) = limited.read_to_end(&mut data).await{
+        if limited.exceeded(){
+            return Err(ImageUserError::ImageTooLarge.into());
+        }
+        error!("cannot read image input: {:?}", e);
+        return Err(GlobalInternalError::IO.into());
+    }
+    Ok(data)
+}
+
+// decoding is cpu bound, keep it away from the io threads
+pub async fn run_blocking<T: Send + 'static>(f: impl FnOnce() -> AppResult<T> + Send + 'static) -> AppResult<T>{
+    tokio::task::spawn_blocking(f).await.map_err(|e| {
+        error!("image task failed: {:?}", e);
+        ImageInternalError::Task
+    })?
+}
+
+fn map_decode_error(e: ImageError) -> AppError{
+    match e{
+        ImageError::Limits(_) => ImageUserError::ImageTooLarge.into(),
+        _ => ImageUserError::InvalidImage.into()
+    }
+}
+
+fn limits(config: &ImageConfig) -> Limits{
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(config.max_dimension);
+    limits.max_image_height = Some(config.max_dimension);
+    limits.max_alloc = Some(config.max_alloc);
+    limits
+}
+
+pub fn decode(data: &[u8], config: &ImageConfig) -> AppResult<(DynamicImage, ImageFormat)>{
+    let mut reader = ImageReader::new(Cursor::new(data)).with_guessed_format()
+        .map_err(|_| ImageUserError::InvalidImage)?;
+    let format = reader.format().ok_or(ImageUserError::InvalidImage)?;
+    reader.limits(limits(config));
+    let mut decoder = reader.into_decoder().map_err(map_decode_error)?;
+    // the orientation lives in EXIF, which is dropped on re-encoding, so apply it to the pixels
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let mut img = DynamicImage::from_decoder(decoder).map_err(map_decode_error)?;
+    img.apply_orientation(orientation);
+    Ok((img, format))
+}
+
+pub fn output_format(source: ImageFormat, config: &ImageConfig) -> ImageFormat{
+    if config.transcode_webp{
+        ImageFormat::WebP
+    } else if source == ImageFormat::Jpeg{
+        ImageFormat::Jpeg
+    } else {
+        ImageFormat::Png
+    }
+}
+
+// only pixels are written, so EXIF/GPS and any other metadata of the source is gone
+pub fn encode(img: &DynamicImage, format: ImageFormat, config: &ImageConfig) -> AppResult<EncodedImage>{
+    let format = match format{
+        ImageFormat::WebP | ImageFormat::Jpeg => format,
+        _ => ImageFormat::Png
+    };
+    let mut data = Vec::new();
+    let res = match format{
+        ImageFormat::WebP => DynamicImage::ImageRgba8(img.to_rgba8())
+            .write_with_encoder(WebPEncoder::new_lossless(&mut data)),
+        ImageFormat::Jpeg => DynamicImage::ImageRgb8(img.to_rgb8())
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut data, config.jpeg_quality)),
+        _ => img.write_with_encoder(PngEncoder::new(&mut data))
+    };
+    res.map_err(|e| {
+        error!("failed to encode image: {:?}", e);
+        ImageInternalError::Encode
+    })?;
+    Ok(EncodedImage{ data, format })
+}
+
+pub fn square_crop(img: &DynamicImage) -> DynamicImage{
+    let side = img.width().min(img.height());
+    img.crop_imm((img.width() - side) / 2, (img.height() - side) / 2, side, side)
+}
+
+// never upscales
+pub fn fit(img: &DynamicImage, size: u32) -> DynamicImage{
+    if img.width() <= size && img.height() <= size{
+        img.clone()
+    } else {
+        img.resize(size, size, FilterType::Lanczos3)
+    }
+}
+
+// the smallest configured size able to satisfy the request, or the largest one
+pub fn nearest_size(sizes: &[u32], requested: u32) -> Option<u32>{
+    sizes.iter().copied().filter(|&s| s >= requested).min()
+        .or_else(|| sizes.iter().copied().max())
+}
+
+pub fn process_avatar(data: Vec<u8>, config: &ImageConfig) -> AppResult<Vec<(u32, EncodedImage)>>{
+    let (img, format) = decode(&data, config)?;
+    let img = square_crop(&img);
+    let format = output_format(format, config);
+    config.avatar_sizes.iter().map(|&size| {
+        Ok((size, encode(&fit(&img, size), format, config)?))
+    }).collect()
+}
+
+// validates an uploaded image and strips its metadata, gifs keep their animation
+pub fn sanitize(data: Vec<u8>, config: &ImageConfig) -> AppResult<EncodedImage>{
+    let (img, format) = decode(&data, config)?;
+    if format == ImageFormat::Gif && !config.transcode_webp{
+        return sanitize_gif(&data, config);
+    }
+    encode(&img, output_format(format, config), config)
+}
+
+// the frames are decoded and written again, comments and application extensions stay behind
+fn sanitize_gif(data: &[u8], config: &ImageConfig) -> AppResult<EncodedImage>{
+    let mut decoder = GifDecoder::new(Cursor::new(data)).map_err(map_decode_error)?;
+    decoder.set_limits(limits(config)).map_err(map_decode_error)?;
+    // the limits only cover a single frame, the whole animation is held in memory
+    let mut allocated = 0u64;
+    let mut frames: Vec<Frame> = Vec::new();
+    for frame in decoder.into_frames(){
+        let frame = frame.map_err(map_decode_error)?;
+        allocated += frame.buffer().as_raw().len() as u64;
+        if allocated > config.max_alloc{
+            return Err(ImageUserError::ImageTooLarge.into());
+        }
+        frames.push(frame);
+    }
+    let mut out = Vec::new();
+    {
+        let mut encoder = GifEncoder::new(&mut out);
+        encoder.set_repeat(Repeat::Infinite)
+            .and_then(|_| encoder.encode_frames(frames))
+            .map_err(|e| {
+                error!("failed to encode gif: {:?}", e);
+                ImageInternalError::Encode
+            })?;
+    }
+    Ok(EncodedImage{ data: out, format: ImageFormat::Gif })
+}
+
+// decoded once for every size
+pub fn thumbnails(data: &[u8], sizes: &[u32], config: &ImageConfig) -> AppResult<Vec<(u32, EncodedImage)>>{
+    let (img, format) = decode(data, config)?;
+    let format = output_format(format, config);
+    sizes.iter().map(|&size| {
+        Ok((size, encode(&fit(&img, size), format, config)?))
+    }).collect()
+}
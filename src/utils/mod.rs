@@ -5,4 +5,8 @@ pub mod validate;
 pub mod request;
 pub mod paseto;
 pub mod stream;
-pub mod sniffer;
\ No newline at end of file
+pub mod sniffer;
+pub mod image;
+pub mod backoff;
+pub mod csv;
+pub mod rate_limit;
\ No newline at end of file
@@ -0,0 +1,29 @@
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+
+// a fixed window per key, kept in memory so it only holds for this instance
+pub struct RateLimiter {
+    window: Duration,
+    max: u32,
+    hits: DashMap<String, (Instant, u32)>,
+}
+impl RateLimiter {
+    pub fn new(window: Duration, max: u32) -> Self {
+        Self { window, max, hits: DashMap::new() }
+    }
+    // counts the attempt, false once the key used up its window
+    pub fn check(&self, key: &str) -> bool {
+        let now = Instant::now();
+        // forgotten keys would pile up otherwise
+        if self.hits.len() > 10_000 {
+            self.hits.retain(|_, (start, _)| now.duration_since(*start) < self.window);
+        }
+        let mut entry = self.hits.entry(key.to_string()).or_insert((now, 0));
+        if now.duration_since(entry.0) >= self.window {
+            *entry = (now, 0);
+        }
+        entry.1 += 1;
+        entry.1 <= self.max
+    }
+}
@@ -3,7 +3,8 @@ use ntex::web::types::Payload;
 use ntex::util::BytesMut;
 use once_cell::sync::Lazy;
 use tracing::error;
-use crate::{get_args, middlewares};
+use std::net::IpAddr;
+use crate::{get_args, get_config, middlewares};
 use crate::types::err::{AppError, AppResult, GlobalUserError};
 
 
@@ -22,6 +23,29 @@ pub fn check_content_length(req: &web::HttpRequest, required_size: usize) -> App
         Ok(())
     }
 }
+// the peer address, unless the peer is a configured proxy, then the nearest untrusted hop of x-forwarded-for
+pub fn get_client_ip(req: &web::HttpRequest) -> Option<IpAddr>{
+    let peer = req.peer_addr()?.ip().to_canonical();
+    let forwarded_for = req.headers().get_all("x-forwarded-for")
+        .filter_map(|h| h.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",");
+    Some(resolve_client_ip(peer, &forwarded_for, &get_config!(http).trusted_proxies))
+}
+fn resolve_client_ip(peer: IpAddr, forwarded_for: &str, trusted_proxies: &[IpAddr]) -> IpAddr{
+    let mut client = peer;
+    // every proxy appends the address it got the request from, so the list is walked from its end
+    for hop in forwarded_for.rsplit(',') {
+        if !trusted_proxies.contains(&client) {
+            break;
+        }
+        let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+            break;
+        };
+        client = ip.to_canonical();
+    }
+    client
+}
 use trie_rs::{Trie, TrieBuilder};
 
 pub static ALLOWED_IMAGE_MIME: Lazy<Trie<u8>> = Lazy::new(|| {
@@ -68,4 +92,28 @@ impl<'a> RequestPayload<'a>{
         })
 
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forwarded_for_is_ignored_from_untrusted_peers() {
+        let peer: IpAddr = "203.0.113.7".parse().unwrap();
+        assert_eq!(resolve_client_ip(peer, "198.51.100.1", &[]), peer);
+    }
+
+    #[test]
+    fn forwarded_for_is_walked_through_trusted_proxies() {
+        let proxies: Vec<IpAddr> = vec!["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()];
+        let peer: IpAddr = "10.0.0.2".parse().unwrap();
+        // the client made up the first entry
+        assert_eq!(
+            resolve_client_ip(peer, "1.2.3.4, 198.51.100.1, 10.0.0.1", &proxies),
+            "198.51.100.1".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(resolve_client_ip(peer, "", &proxies), peer);
+        assert_eq!(resolve_client_ip(peer, "garbage", &proxies), peer);
+    }
+}
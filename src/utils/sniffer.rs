@@ -1,33 +1,292 @@
 use strum_macros::IntoStaticStr;
 
-#[derive(IntoStaticStr)]
+#[derive(IntoStaticStr, Clone, Copy, PartialEq, Eq, Debug)]
 #[strum(serialize_all = "snake_case")]
-pub enum ImageExt{
+pub enum FileType{
     Jpeg,
     Png,
     Gif,
     Bmp,
     Webp,
     Tiff,
+    Avif,
+    Heic,
+    Ico,
+    Svg,
+    Pdf,
+    Mp4,
+    Webm,
+    Mp3,
+    M4a,
+    Ogg,
+    Flac,
+    Wav,
     Unknown
 }
 
-pub fn image_sniff(data: [u8;8]) -> ImageExt{
-    if data[..2] == [0xFF, 0xD8] {
-        ImageExt::Jpeg
-    } else if data[..8] == [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A]{
-        ImageExt::Png
-    } else if data[..4] == [0x47, 0x49, 0x46, 0x38]{
-        ImageExt::Gif
-    } else if data[..2] == [0x42, 0x4D]{
-        ImageExt::Bmp
-    } else if data[..4] == [0x52, 0x49, 0x46, 0x46]{
-        ImageExt::Webp
-    } else if data[..4] == [0x49, 0x49, 0x2A, 0x00]{
-        ImageExt::Tiff
-    } else if data[..4] == [0x4D, 0x4D, 0x00, 0x2A]{
-        ImageExt::Tiff
+const KNOWN_TYPES: [FileType; 18] = [
+    FileType::Jpeg, FileType::Png, FileType::Gif, FileType::Bmp, FileType::Webp, FileType::Tiff,
+    FileType::Avif, FileType::Heic, FileType::Ico, FileType::Svg, FileType::Pdf, FileType::Mp4,
+    FileType::Webm, FileType::Mp3, FileType::M4a, FileType::Ogg, FileType::Flac, FileType::Wav,
+];
+
+pub const UNKNOWN_MIME: &str = "application/octet-stream";
+// enough for the container formats and for an svg root element after its prolog in most files
+pub const SNIFF_LEN: usize = 4096;
+
+impl FileType{
+    // the first one is the canonical type, the others are aliases clients commonly send
+    fn mimes(self) -> &'static [&'static str]{
+        match self{
+            FileType::Jpeg => &["image/jpeg", "image/jpg", "image/pjpeg"],
+            FileType::Png => &["image/png"],
+            FileType::Gif => &["image/gif"],
+            FileType::Bmp => &["image/bmp", "image/x-bmp", "image/x-ms-bmp"],
+            FileType::Webp => &["image/webp"],
+            FileType::Tiff => &["image/tiff"],
+            FileType::Avif => &["image/avif"],
+            FileType::Heic => &["image/heic", "image/heif", "image/heic-sequence", "image/heif-sequence"],
+            FileType::Ico => &["image/x-icon", "image/vnd.microsoft.icon"],
+            FileType::Svg => &["image/svg+xml"],
+            FileType::Pdf => &["application/pdf", "application/x-pdf"],
+            FileType::Mp4 => &["video/mp4", "application/mp4"],
+            FileType::Webm => &["video/webm", "audio/webm"],
+            FileType::Mp3 => &["audio/mpeg", "audio/mp3"],
+            FileType::M4a => &["audio/mp4", "audio/x-m4a", "audio/m4a"],
+            FileType::Ogg => &["audio/ogg", "video/ogg", "application/ogg"],
+            FileType::Flac => &["audio/flac", "audio/x-flac"],
+            FileType::Wav => &["audio/wav", "audio/x-wav", "audio/wave", "audio/vnd.wave"],
+            FileType::Unknown => &[]
+        }
+    }
+    pub fn mime(self) -> &'static str{
+        self.mimes().first().copied().unwrap_or(UNKNOWN_MIME)
+    }
+}
+
+pub fn sniff(data: &[u8]) -> FileType{
+    if data.starts_with(&[0xFF, 0xD8, 0xFF]){
+        FileType::Jpeg
+    } else if data.starts_with(&[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A]){
+        FileType::Png
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a"){
+        FileType::Gif
+    } else if is_bmp(data){
+        FileType::Bmp
+    } else if data.starts_with(&[0x49, 0x49, 0x2A, 0x00]) || data.starts_with(&[0x4D, 0x4D, 0x00, 0x2A]){
+        FileType::Tiff
+    } else if data.starts_with(b"RIFF"){
+        // RIFF is only a container, the form type tells what is inside
+        match data.get(8..12){
+            Some(b"WEBP") => FileType::Webp,
+            Some(b"WAVE") => FileType::Wav,
+            _ => FileType::Unknown
+        }
+    } else if data.get(4..8) == Some(b"ftyp"){
+        sniff_iso_bmff(data)
+    } else if data.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]){
+        // only the webm flavour of matroska is accepted
+        if data.windows(4).any(|w| w == b"webm"){
+            FileType::Webm
+        } else {
+            FileType::Unknown
+        }
+    } else if data.starts_with(&[0x00, 0x00, 0x01, 0x00]) && data.get(4..6).is_some_and(|c| c != [0, 0]){
+        FileType::Ico
+    } else if data.starts_with(b"%PDF-"){
+        FileType::Pdf
+    } else if data.starts_with(b"OggS"){
+        FileType::Ogg
+    } else if data.starts_with(b"fLaC"){
+        FileType::Flac
+    } else if data.starts_with(b"ID3") || is_mpeg_audio_frame(data){
+        FileType::Mp3
+    } else if is_svg(data){
+        FileType::Svg
     } else {
-        ImageExt::Unknown
+        FileType::Unknown
+    }
+}
+
+pub fn mime_sniff(data: &[u8]) -> &'static str{
+    sniff(data).mime()
+}
+
+// the declaration has to agree with the content; the generic type only fits content the
+// sniffer does not recognize, and so does any type it is not able to recognize
+pub fn declared_matches(sniffed: FileType, declared: &str) -> bool{
+    let declared = declared.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    if declared.is_empty(){
+        return false;
+    }
+    if declared == UNKNOWN_MIME{
+        return sniffed == FileType::Unknown;
+    }
+    match sniffed{
+        FileType::Unknown => !KNOWN_TYPES.iter().any(|t| t.mimes().contains(&declared.as_str())),
+        _ => sniffed.mimes().contains(&declared.as_str())
+    }
+}
+
+fn sniff_iso_bmff(data: &[u8]) -> FileType{
+    let box_size = data.get(..4).map(|t| u32::from_be_bytes([t[0], t[1], t[2], t[3]]) as usize).unwrap_or(0);
+    let major = match data.get(8..12){
+        Some(t) => t,
+        None => return FileType::Unknown
+    };
+    // compatible brands follow the major brand and its minor version
+    let compatible = data.get(16..box_size.min(data.len())).unwrap_or_default();
+    let has_brand = |brands: &[&[u8]]| compatible.chunks_exact(4).any(|b| brands.contains(&b));
+    const AVIF: [&[u8]; 2] = [b"avif", b"avis"];
+    const HEIC: [&[u8]; 8] = [b"heic", b"heix", b"hevc", b"hevx", b"heim", b"heis", b"hevm", b"hevs"];
+    match major{
+        b if AVIF.contains(&b) => FileType::Avif,
+        b if HEIC.contains(&b) => FileType::Heic,
+        // generic HEIF brands, the compatible list tells which codec is used
+        b"mif1" | b"msf1" => if has_brand(&AVIF){
+            FileType::Avif
+        } else {
+            FileType::Heic
+        },
+        b"M4A " | b"M4B " | b"M4P " => FileType::M4a,
+        b"isom" | b"iso2" | b"iso4" | b"iso5" | b"iso6" | b"mp41" | b"mp42" | b"avc1" | b"dash"
+            | b"M4V " | b"mmp4" | b"f4v " => FileType::Mp4,
+        _ => FileType::Unknown
+    }
+}
+
+// "BM" alone starts plenty of text, so the reserved fields and the dib header size have to fit too
+fn is_bmp(data: &[u8]) -> bool{
+    const DIB_HEADER_SIZES: [u32; 7] = [12, 40, 52, 56, 64, 108, 124];
+    match data.get(..18){
+        Some(h) => h.starts_with(b"BM")
+            && h[6..10] == [0, 0, 0, 0]
+            && DIB_HEADER_SIZES.contains(&u32::from_le_bytes([h[14], h[15], h[16], h[17]])),
+        None => false
+    }
+}
+
+fn is_mpeg_audio_frame(data: &[u8]) -> bool{
+    // 11 sync bits, then a valid version and a layer other than the reserved one (which adts aac uses)
+    match data.get(..2){
+        Some(&[a, b]) => a == 0xFF && b & 0xE0 == 0xE0 && b & 0x18 != 0x08 && b & 0x06 != 0,
+        _ => false
+    }
+}
+
+fn is_svg(data: &[u8]) -> bool{
+    let mut rest = data.strip_prefix(&[0xEF, 0xBB, 0xBF]).unwrap_or(data);
+    loop{
+        rest = &rest[rest.iter().position(|c| !c.is_ascii_whitespace()).unwrap_or(rest.len())..];
+        // skip the xml declaration, comments and the doctype preceding the root element
+        let end = if rest.starts_with(b"<?"){
+            find(rest, b"?>").map(|i| i + 2)
+        } else if rest.starts_with(b"<!--"){
+            find(rest, b"-->").map(|i| i + 3)
+        } else if rest.starts_with(b"<!"){
+            find(rest, b">").map(|i| i + 1)
+        } else {
+            break;
+        };
+        match end{
+            Some(i) => rest = &rest[i..],
+            // the window ended inside the prolog, the root element may well be an svg; as svg
+            // is refused on upload, it is better taken for one than stored as something harmless
+            None => return true
+        }
     }
-}
\ No newline at end of file
+    rest.starts_with(b"<svg") && rest.get(4).is_some_and(|c| c.is_ascii_whitespace() || *c == b'>' || *c == b'/')
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize>{
+    data.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn bmp_header(dib_size: u32) -> Vec<u8>{
+        let mut h = b"BM".to_vec();
+        h.extend_from_slice(&1000u32.to_le_bytes());
+        h.extend_from_slice(&[0; 4]);
+        h.extend_from_slice(&54u32.to_le_bytes());
+        h.extend_from_slice(&dib_size.to_le_bytes());
+        h
+    }
+
+    #[test]
+    fn sniffs_magic_numbers(){
+        assert_eq!(sniff(&[0xFF, 0xD8, 0xFF, 0xE0]), FileType::Jpeg);
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n...."), FileType::Png);
+        assert_eq!(sniff(b"GIF89a...."), FileType::Gif);
+        assert_eq!(sniff(b"RIFF\0\0\0\0WEBPVP8 "), FileType::Webp);
+        assert_eq!(sniff(b"RIFF\0\0\0\0AVI LIST"), FileType::Unknown);
+        assert_eq!(sniff(b"%PDF-1.7"), FileType::Pdf);
+        assert_eq!(sniff(b""), FileType::Unknown);
+    }
+
+    #[test]
+    fn sniffs_iso_bmff_brands(){
+        assert_eq!(sniff(b"\0\0\0\x18ftypavif\0\0\0\0mif1miaf"), FileType::Avif);
+        assert_eq!(sniff(b"\0\0\0\x18ftypmif1\0\0\0\0mif1heic"), FileType::Heic);
+        assert_eq!(sniff(b"\0\0\0\x18ftypmif1\0\0\0\0mif1avif"), FileType::Avif);
+        assert_eq!(sniff(b"\0\0\0\x18ftypisom\0\0\0\0isomiso2"), FileType::Mp4);
+        assert_eq!(sniff(b"\0\0\0\x18ftypM4A \0\0\0\0M4A mp42"), FileType::M4a);
+    }
+
+    #[test]
+    fn bmp_needs_a_plausible_header(){
+        assert_eq!(sniff(&bmp_header(40)), FileType::Bmp);
+        assert_eq!(sniff(&bmp_header(124)), FileType::Bmp);
+        assert_eq!(sniff(&bmp_header(41)), FileType::Unknown);
+        assert_eq!(sniff(b"BM"), FileType::Unknown);
+        assert_eq!(sniff(b"BMW is a car maker, not an image"), FileType::Unknown);
+    }
+
+    #[test]
+    fn mpeg_audio_frames(){
+        assert_eq!(sniff(&[0xFF, 0xFB, 0x90, 0x00]), FileType::Mp3);
+        assert_eq!(sniff(b"ID3\x04\0"), FileType::Mp3);
+        // adts aac uses the reserved layer
+        assert_eq!(sniff(&[0xFF, 0xF1, 0x50, 0x80]), FileType::Unknown);
+    }
+
+    #[test]
+    fn svg_after_prolog(){
+        assert_eq!(sniff(b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>"), FileType::Svg);
+        assert_eq!(sniff(b"\xEF\xBB\xBF <?xml version=\"1.0\"?>\n<!-- made by hand -->\n<!DOCTYPE svg>\n<svg>"), FileType::Svg);
+        assert_eq!(sniff(b"<?xml version=\"1.0\"?><html></html>"), FileType::Unknown);
+        assert_eq!(sniff(b"<svgfoo>"), FileType::Unknown);
+    }
+
+    #[test]
+    fn svg_with_a_prolog_longer_than_the_window(){
+        let mut data = b"<?xml version=\"1.0\"?><!-- ".to_vec();
+        data.resize(SNIFF_LEN, b'x');
+        assert_eq!(sniff(&data), FileType::Svg);
+    }
+
+    #[test]
+    fn declared_has_to_agree(){
+        assert!(declared_matches(FileType::Jpeg, "image/jpeg"));
+        assert!(declared_matches(FileType::Jpeg, "Image/JPG; charset=binary"));
+        assert!(!declared_matches(FileType::Jpeg, "image/png"));
+        assert!(!declared_matches(FileType::Svg, "text/plain"));
+    }
+
+    #[test]
+    fn generic_declarations(){
+        assert!(!declared_matches(FileType::Jpeg, ""));
+        assert!(!declared_matches(FileType::Unknown, ""));
+        assert!(!declared_matches(FileType::Svg, UNKNOWN_MIME));
+        assert!(declared_matches(FileType::Unknown, UNKNOWN_MIME));
+    }
+
+    #[test]
+    fn unknown_content(){
+        assert!(declared_matches(FileType::Unknown, "text/plain"));
+        // a type the sniffer knows has to be recognized
+        assert!(!declared_matches(FileType::Unknown, "image/png"));
+    }
+}
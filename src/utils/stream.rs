@@ -2,7 +2,8 @@ use std::{pin::{pin, Pin}, task::{Context, Poll}};
 
 use futures_util::Stream;
 use ntex::util::{Bytes, BytesMut};
-use tokio::io::AsyncRead;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use sha2::{Digest, Sha256};
 use pin_project::pin_project;
 use tokio_util::io::poll_read_buf;
 
@@ -118,4 +119,101 @@ impl<R: AsyncRead + Unpin, E: AsyncRead + Unpin> AsyncRead for AsyncReadMerger<R
             Poll::Ready(Ok(()))
         }
     }
-}
\ No newline at end of file
+}
+#[pin_project]
+pub struct HashReader<R>{
+    #[pin]
+    reader: R,
+    hasher: Sha256
+}
+
+impl<R: AsyncRead + Unpin> HashReader<R>{
+    pub fn new(reader: R) -> Self{
+        Self{
+            reader,
+            hasher: Sha256::new()
+        }
+    }
+    // lowercase hex digest of everything read so far
+    pub fn finalize(self) -> String{
+        format!("{:x}", self.hasher.finalize())
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for HashReader<R>{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.project();
+        let old_filled_len = buf.filled().len();
+        match this.reader.poll_read(cx, buf){
+            Poll::Ready(Ok(_)) => {
+                this.hasher.update(&buf.filled()[old_filled_len..]);
+                Poll::Ready(Ok(()))
+            },
+            other => other
+        }
+    }
+}
+
+// read at most `size` bytes from the beginning of the reader, fewer only if it reaches EOF
+pub async fn read_head<R: AsyncRead + Unpin>(reader: &mut R, size: usize) -> std::io::Result<Vec<u8>>{
+    let mut head = vec![0u8; size];
+    let mut filled = 0;
+    while filled < size {
+        let n = reader.read(&mut head[filled..]).await?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    head.truncate(filled);
+    Ok(head)
+}
+
+// fails the read once more than `limit` bytes have gone through, so an upload can be aborted mid-stream
+#[pin_project]
+pub struct LimitedReader<R>{
+    #[pin]
+    reader: R,
+    remaining: u64,
+    exceeded: bool
+}
+
+impl<R: AsyncRead + Unpin> LimitedReader<R>{
+    pub fn new(reader: R, limit: u64) -> Self{
+        Self{
+            reader,
+            remaining: limit,
+            exceeded: false
+        }
+    }
+    pub fn exceeded(&self) -> bool{
+        self.exceeded
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for LimitedReader<R>{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.project();
+        let old_filled_len = buf.filled().len();
+        match this.reader.poll_read(cx, buf){
+            Poll::Ready(Ok(_)) => {
+                let n = (buf.filled().len() - old_filled_len) as u64;
+                if n > *this.remaining{
+                    *this.exceeded = true;
+                    return Poll::Ready(Err(std::io::Error::other("read limit exceeded")));
+                }
+                *this.remaining -= n;
+                Poll::Ready(Ok(()))
+            },
+            other => other
+        }
+    }
+}
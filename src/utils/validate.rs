@@ -1,5 +1,5 @@
 use tracing::error;
-use validator::ValidationErrors;
+use validator::{ValidationError, ValidationErrors};
 use crate::get_args;
 use crate::types::err::AppError;
 use crate::types::err::GlobalUserError::InvalidParameter;
@@ -12,4 +12,12 @@ impl From<ValidationErrors> for AppError{
         }
         return InvalidParameter.into();
     }
+}
+// only web links, a javascript: url would run wherever it is rendered
+pub fn check_http_url(url: &str) -> Result<(), ValidationError> {
+    if url.starts_with("https://") || url.starts_with("http://") {
+        Ok(())
+    } else {
+        Err(ValidationError::new("scheme"))
+    }
 }
\ No newline at end of file